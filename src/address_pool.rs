@@ -0,0 +1,657 @@
+// A DHCP style address pool. Machines (clients) ask for an address and get a lease on one
+// from a configured range for a fixed time. They can renew it before it runs out, or release
+// it early, and an address whose lease has run out can be given to someone else.
+//
+// A few rules decide who gets what:
+//   - a client with a reservation always gets its reserved address, and nobody else can
+//   - a client that had a lease before gets the same address back if it's still free
+//   - otherwise it's the lowest address nobody has had, then the one whose lease ran out
+//     longest ago, so an address isn't reused sooner than it has to be
+//
+// Time comes from a Clock rather than straight from the system, so the lesson can move time
+// forward without waiting for leases to run out.
+
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::enums::IpAddrWithTypes;
+
+const FILE_HEADER: &str = "learn_rust address pool v2";
+
+// seconds since the unix epoch, so lease times still mean something after a restart
+pub trait Clock {
+    fn now(&self) -> u64;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+    }
+}
+
+// a clock that only moves when told to
+pub struct ManualClock {
+    now: Cell<u64>,
+}
+
+impl ManualClock {
+    pub fn new(now: u64) -> ManualClock {
+        ManualClock { now: Cell::new(now) }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.now.set(self.now.get() + by.as_secs());
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> u64 {
+        self.now.get()
+    }
+}
+
+// so the pool can borrow a clock the caller keeps hold of and moves forward
+impl<C: Clock> Clock for &C {
+    fn now(&self) -> u64 {
+        (**self).now()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lease {
+    pub address: IpAddrWithTypes,
+    pub client_id: String,
+    pub expires_at: u64,
+}
+
+#[derive(Debug)]
+pub enum PoolError {
+    // the range's two ends are different kinds of address, or the wrong way round
+    BadRange,
+    BadClientId(String),
+    NotInRange(IpAddrWithTypes),
+    Exhausted,
+    // the address is reserved for, or leased to, another client
+    Taken { address: IpAddrWithTypes, by: String },
+    // renew and release need a lease that hasn't run out
+    NoLease(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for PoolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PoolError::BadRange => write!(f, "the range must be two addresses of the same kind, lowest first"),
+            PoolError::BadClientId(id) => write!(f, "'{}' can't be used as a client id", id),
+            PoolError::NotInRange(address) => write!(f, "{} is not in the pool", address),
+            PoolError::Exhausted => write!(f, "no free addresses left in the pool"),
+            PoolError::Taken { address, by } => write!(f, "{} is taken by {}", address, by),
+            PoolError::NoLease(id) => write!(f, "{} has no current lease", id),
+            PoolError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<io::Error> for PoolError {
+    fn from(e: io::Error) -> PoolError {
+        PoolError::Io(e)
+    }
+}
+
+pub struct AddressPool<C: Clock> {
+    // both ends of the range are handed out
+    first: IpAddrWithTypes,
+    last: IpAddrWithTypes,
+    lease_time: Duration,
+    // every lease handed out, by address. Ones that have run out are kept until the address
+    // is needed, so a client coming back can have its old address again
+    leases: BTreeMap<u128, Lease>,
+    by_client: HashMap<String, u128>,
+    reservations: HashMap<String, u128>,
+    reserved_for: BTreeMap<u128, String>,
+    clock: C,
+    // where leases and reservations are saved after every change, if anywhere
+    path: Option<PathBuf>,
+}
+
+impl<C: Clock> AddressPool<C> {
+    pub fn new(first: IpAddrWithTypes, last: IpAddrWithTypes, lease_time: Duration, clock: C) -> Result<AddressPool<C>, PoolError> {
+        if first.width() != last.width() || first > last {
+            return Err(PoolError::BadRange);
+        }
+        Ok(AddressPool {
+            first,
+            last,
+            lease_time,
+            leases: BTreeMap::new(),
+            by_client: HashMap::new(),
+            reservations: HashMap::new(),
+            reserved_for: BTreeMap::new(),
+            clock,
+            path: None,
+        })
+    }
+
+    // A pool that keeps its leases and reservations in a file, picking up the ones already
+    // there. A file that can't be read is an error rather than a fresh start, starting over
+    // could hand out addresses that are still in use.
+    pub fn open(path: &Path, first: IpAddrWithTypes, last: IpAddrWithTypes, lease_time: Duration, clock: C) -> Result<AddressPool<C>, PoolError> {
+        let mut pool = AddressPool::new(first, last, lease_time, clock)?;
+        let saved = load(path)?;
+        // ones for addresses the range no longer covers can't clash with anything
+        for lease in saved.leases {
+            if pool.in_range(lease.address) {
+                pool.by_client.insert(lease.client_id.clone(), lease.address.bits());
+                pool.leases.insert(lease.address.bits(), lease);
+            }
+        }
+        for (client_id, address) in saved.reservations {
+            if pool.in_range(address) {
+                pool.reservations.insert(client_id.clone(), address.bits());
+                pool.reserved_for.insert(address.bits(), client_id);
+            }
+        }
+        pool.path = Some(path.to_path_buf());
+        Ok(pool)
+    }
+
+    fn in_range(&self, address: IpAddrWithTypes) -> bool {
+        address.width() == self.first.width() && (self.first.bits()..=self.last.bits()).contains(&address.bits())
+    }
+
+    fn is_active(&self, lease: &Lease) -> bool {
+        lease.expires_at > self.clock.now()
+    }
+
+    fn check_client_id(client_id: &str) -> Result<(), PoolError> {
+        if client_id.is_empty() || client_id.contains(['\t', '\n', '\r']) {
+            return Err(PoolError::BadClientId(client_id.to_string()));
+        }
+        Ok(())
+    }
+
+    // the client actively holding the address, if it isn't this one
+    fn held_by_other(&self, bits: u128, client_id: &str) -> Option<&str> {
+        match self.leases.get(&bits) {
+            Some(lease) if lease.client_id != client_id && self.is_active(lease) => Some(&lease.client_id),
+            _ => None,
+        }
+    }
+
+    pub fn reserve(&mut self, client_id: &str, address: IpAddrWithTypes) -> Result<(), PoolError> {
+        AddressPool::<C>::check_client_id(client_id)?;
+        if !self.in_range(address) {
+            return Err(PoolError::NotInRange(address));
+        }
+        let bits = address.bits();
+        let other = match self.reserved_for.get(&bits) {
+            Some(other) if other != client_id => Some(other.as_str()),
+            _ => self.held_by_other(bits, client_id),
+        };
+        if let Some(other) = other {
+            return Err(PoolError::Taken { address, by: other.to_string() });
+        }
+        self.update(|pool| {
+            pool.remove_reservation(client_id);
+            pool.reservations.insert(client_id.to_string(), bits);
+            pool.reserved_for.insert(bits, client_id.to_string());
+        })
+    }
+
+    pub fn unreserve(&mut self, client_id: &str) -> Result<(), PoolError> {
+        if !self.reservations.contains_key(client_id) {
+            return Ok(());
+        }
+        self.update(|pool| pool.remove_reservation(client_id))
+    }
+
+    fn remove_reservation(&mut self, client_id: &str) {
+        if let Some(bits) = self.reservations.remove(client_id) {
+            self.reserved_for.remove(&bits);
+        }
+    }
+
+    // The address the client should get, or Exhausted. Two passes over the range, one for
+    // addresses never leased, then one for leases that have run out.
+    fn choose(&self, client_id: &str) -> Result<u128, PoolError> {
+        if let Some(&bits) = self.reservations.get(client_id) {
+            return Ok(bits);
+        }
+        if let Some(&bits) = self.by_client.get(client_id) {
+            if !self.reserved_for.contains_key(&bits) {
+                return Ok(bits);
+            }
+        }
+        // jump from one lease or reservation to the next rather than checking every address,
+        // a v6 range can be too big to walk
+        let mut candidate = self.first.bits();
+        let mut used: Vec<u128> = self.leases.keys().chain(self.reserved_for.keys()).copied().collect();
+        used.sort_unstable();
+        for bits in used {
+            if bits > candidate {
+                break;
+            }
+            if bits == candidate {
+                candidate = match candidate.checked_add(1) {
+                    Some(next) => next,
+                    None => return self.oldest_expired(),
+                };
+            }
+        }
+        if candidate <= self.last.bits() {
+            return Ok(candidate);
+        }
+        self.oldest_expired()
+    }
+
+    fn oldest_expired(&self) -> Result<u128, PoolError> {
+        self.leases
+            .iter()
+            .filter(|(bits, lease)| !self.is_active(lease) && !self.reserved_for.contains_key(bits))
+            .min_by_key(|(_, lease)| lease.expires_at)
+            .map(|(&bits, _)| bits)
+            .ok_or(PoolError::Exhausted)
+    }
+
+    // a new lease, or a fresh one on the address the client already has
+    pub fn request(&mut self, client_id: &str) -> Result<Lease, PoolError> {
+        AddressPool::<C>::check_client_id(client_id)?;
+        let bits = self.choose(client_id)?;
+        // a reservation made after someone else leased the address waits for that lease
+        if let Some(other) = self.held_by_other(bits, client_id) {
+            let address = self.first.with_bits(bits);
+            return Err(PoolError::Taken { address, by: other.to_string() });
+        }
+        self.grant(client_id, bits)
+    }
+
+    // pushes the end of a current lease back by another lease time
+    pub fn renew(&mut self, client_id: &str) -> Result<Lease, PoolError> {
+        match self.lease_for(client_id) {
+            Some(lease) => {
+                let bits = lease.address.bits();
+                self.grant(client_id, bits)
+            }
+            None => Err(PoolError::NoLease(client_id.to_string())),
+        }
+    }
+
+    pub fn release(&mut self, client_id: &str) -> Result<(), PoolError> {
+        if self.lease_for(client_id).is_none() {
+            return Err(PoolError::NoLease(client_id.to_string()));
+        }
+        self.update(|pool| {
+            let bits = pool.by_client.remove(client_id).unwrap();
+            pool.leases.remove(&bits);
+        })
+    }
+
+    fn grant(&mut self, client_id: &str, bits: u128) -> Result<Lease, PoolError> {
+        let lease = Lease {
+            address: self.first.with_bits(bits),
+            client_id: client_id.to_string(),
+            expires_at: self.clock.now() + self.lease_time.as_secs(),
+        };
+        self.update(|pool| {
+            // the client's old address, if it's moving, and whoever had this one before
+            if let Some(old) = pool.by_client.insert(client_id.to_string(), bits) {
+                pool.leases.remove(&old);
+            }
+            if let Some(previous) = pool.leases.remove(&bits) {
+                if previous.client_id != client_id {
+                    pool.by_client.remove(&previous.client_id);
+                }
+            }
+            pool.leases.insert(bits, lease.clone());
+            lease
+        })
+    }
+
+    // the client's lease, as long as it hasn't run out
+    pub fn lease_for(&self, client_id: &str) -> Option<&Lease> {
+        let lease = self.leases.get(self.by_client.get(client_id)?)?;
+        if self.is_active(lease) { Some(lease) } else { None }
+    }
+
+    pub fn active_leases(&self) -> Vec<&Lease> {
+        self.leases.values().filter(|lease| self.is_active(lease)).collect()
+    }
+
+    // Forgets every lease that has run out and returns them. Nothing needs this to be called,
+    // run out leases are ignored anyway, it just stops clients getting their old address back.
+    pub fn expire(&mut self) -> Result<Vec<Lease>, PoolError> {
+        let now = self.clock.now();
+        let expired: Vec<u128> = self.leases.iter().filter(|(_, l)| l.expires_at <= now).map(|(&bits, _)| bits).collect();
+        self.update(|pool| {
+            let mut removed = Vec::new();
+            for bits in expired {
+                let lease = pool.leases.remove(&bits).unwrap();
+                pool.by_client.remove(&lease.client_id);
+                removed.push(lease);
+            }
+            removed
+        })
+    }
+
+    // Makes a change and saves it. If the save fails the change is undone, so the pool never
+    // gets ahead of its file: after a restart it would have forgotten addresses it had
+    // already told clients they could use.
+    fn update<R>(&mut self, change: impl FnOnce(&mut Self) -> R) -> Result<R, PoolError> {
+        if self.path.is_none() {
+            return Ok(change(self));
+        }
+        let before = (self.leases.clone(), self.by_client.clone(), self.reservations.clone(), self.reserved_for.clone());
+        let result = change(self);
+        if let Err(e) = self.save() {
+            (self.leases, self.by_client, self.reservations, self.reserved_for) = before;
+            return Err(e);
+        }
+        Ok(result)
+    }
+
+    // written to a temporary file first, so a crash halfway leaves the old file whole
+    fn save(&self) -> Result<(), PoolError> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let mut text = String::from(FILE_HEADER);
+        text.push('\n');
+        for (&bits, client_id) in &self.reserved_for {
+            text.push_str(&format!("reserved\t{}\t{}\n", self.first.with_bits(bits), client_id));
+        }
+        for lease in self.leases.values() {
+            text.push_str(&format!("leased\t{}\t{}\t{}\n", lease.address, lease.client_id, lease.expires_at));
+        }
+        let mut temp_name = path.clone().into_os_string();
+        temp_name.push(".tmp");
+        let temp_path = PathBuf::from(temp_name);
+        fs::write(&temp_path, text)?;
+        fs::rename(&temp_path, path)?;
+        Ok(())
+    }
+}
+
+// what's in a pool file
+#[derive(Default)]
+struct Saved {
+    leases: Vec<Lease>,
+    // client id and the address kept for it
+    reservations: Vec<(String, IpAddrWithTypes)>,
+}
+
+// the leases and reservations in a pool file, or none if there's no file yet
+fn load(path: &Path) -> io::Result<Saved> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Saved::default()),
+        Err(e) => return Err(e),
+    };
+    let invalid = |line: usize, why: &str| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("{}:{}: {}", path.display(), line, why))
+    };
+
+    let mut lines = text.lines();
+    if lines.next() != Some(FILE_HEADER) {
+        return Err(invalid(1, "not an address pool file"));
+    }
+    // one per line, separated by tabs:
+    //     reserved  address  client id
+    //     leased    address  client id  expiry time
+    let mut saved = Saved::default();
+    for (i, line) in lines.enumerate().filter(|(_, l)| !l.is_empty()) {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let parse_address = |address: &str| address.parse().map_err(|_| invalid(i + 2, "bad address"));
+        match fields.as_slice() {
+            ["reserved", address, client_id] => saved.reservations.push((client_id.to_string(), parse_address(address)?)),
+            ["leased", address, client_id, expires_at] => {
+                let address = parse_address(address)?;
+                let expires_at = expires_at.parse().map_err(|_| invalid(i + 2, "bad expiry time"))?;
+                saved.leases.push(Lease { address, client_id: client_id.to_string(), expires_at });
+            }
+            ["reserved", ..] => return Err(invalid(i + 2, "expected a reservation's address and client id")),
+            ["leased", ..] => return Err(invalid(i + 2, "expected a lease's address, client id and expiry time")),
+            _ => return Err(invalid(i + 2, "expected a reserved or leased line")),
+        }
+    }
+    Ok(saved)
+}
+
+pub fn this_main() {
+    let addr = |text: &str| -> IpAddrWithTypes { text.parse().unwrap() };
+    let hour = Duration::from_secs(60 * 60);
+    let clock = ManualClock::new(1_000_000);
+    let mut pool = AddressPool::new(addr("192.168.1.10"), addr("192.168.1.13"), hour, &clock).unwrap();
+
+    // the printer always gets .12, everyone else gets the lowest free address
+    pool.reserve("printer", addr("192.168.1.12")).unwrap();
+    for client_id in &["laptop", "printer", "phone", "tablet"] {
+        let lease = pool.request(client_id).unwrap();
+        println!("{} got {} until {}", lease.client_id, lease.address, lease.expires_at);
+    }
+    match pool.request("tv") {
+        Ok(lease) => println!("tv got {}", lease.address),
+        Err(e) => println!("tv: {}", e),
+    }
+
+    // the laptop renews halfway through, and is the only one left once the others run out
+    clock.advance(hour / 2);
+    pool.renew("laptop").unwrap();
+    clock.advance(hour * 3 / 4);
+    for lease in pool.expire().unwrap() {
+        println!("{}'s lease on {} ran out", lease.client_id, lease.address);
+    }
+    let tv = pool.request("tv").unwrap();
+    println!("tv got {} now", tv.address);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(text: &str) -> IpAddrWithTypes {
+        text.parse().unwrap()
+    }
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("learn_rust_address_pool_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn lowest_free_address_first() {
+        let clock = ManualClock::new(1_000_000);
+        let mut pool = AddressPool::new(addr("192.168.1.10"), addr("192.168.1.11"), HOUR, &clock).unwrap();
+        let laptop = pool.request("laptop").unwrap();
+        assert_eq!(laptop.address, addr("192.168.1.10"));
+        assert_eq!(laptop.expires_at, 1_000_000 + 3600);
+        // asking again gets the same one
+        assert_eq!(pool.request("laptop").unwrap().address, laptop.address);
+        assert_eq!(pool.request("phone").unwrap().address, addr("192.168.1.11"));
+        assert!(matches!(pool.request("tv"), Err(PoolError::Exhausted)));
+    }
+
+    #[test]
+    fn reservations() {
+        let clock = ManualClock::new(1_000_000);
+        let mut pool = AddressPool::new(addr("192.168.1.10"), addr("192.168.1.13"), HOUR, &clock).unwrap();
+        pool.request("laptop").unwrap();
+        pool.reserve("printer", addr("192.168.1.12")).unwrap();
+        assert!(matches!(pool.reserve("phone", addr("192.168.1.12")), Err(PoolError::Taken { .. })));
+        assert!(matches!(pool.reserve("phone", addr("192.168.1.10")), Err(PoolError::Taken { .. })));
+        assert!(matches!(pool.reserve("phone", addr("10.0.0.1")), Err(PoolError::NotInRange(_))));
+        // .12 is skipped for everyone but the printer
+        assert_eq!(pool.request("phone").unwrap().address, addr("192.168.1.11"));
+        assert_eq!(pool.request("tablet").unwrap().address, addr("192.168.1.13"));
+        assert!(matches!(pool.request("tv"), Err(PoolError::Exhausted)));
+        assert_eq!(pool.request("printer").unwrap().address, addr("192.168.1.12"));
+    }
+
+    #[test]
+    fn renew_and_release() {
+        let clock = ManualClock::new(1_000_000);
+        let mut pool = AddressPool::new(addr("10.0.0.1"), addr("10.0.0.2"), HOUR, &clock).unwrap();
+        pool.request("laptop").unwrap();
+        pool.request("phone").unwrap();
+        clock.advance(HOUR / 2);
+        assert_eq!(pool.renew("laptop").unwrap().expires_at, 1_000_000 + 1800 + 3600);
+        // releasing frees the address straight away
+        pool.release("phone").unwrap();
+        assert!(matches!(pool.renew("phone"), Err(PoolError::NoLease(_))));
+        assert!(matches!(pool.release("phone"), Err(PoolError::NoLease(_))));
+        assert_eq!(pool.request("tv").unwrap().address, addr("10.0.0.2"));
+    }
+
+    #[test]
+    fn lease_expiry() {
+        let clock = ManualClock::new(1_000_000);
+        let mut pool = AddressPool::new(addr("10.0.0.1"), addr("10.0.0.4"), HOUR, &clock).unwrap();
+        pool.reserve("printer", addr("10.0.0.3")).unwrap();
+        for client_id in &["laptop", "printer", "phone", "tablet"] {
+            pool.request(client_id).unwrap();
+        }
+        clock.advance(HOUR / 2);
+        pool.renew("laptop").unwrap();
+        pool.release("phone").unwrap();
+        pool.request("tv").unwrap();
+
+        // the printer and tablet leases run out, the laptop and tv ones have longer to go
+        clock.advance(HOUR * 3 / 4);
+        let active: Vec<&str> = pool.active_leases().iter().map(|l| l.client_id.as_str()).collect();
+        assert_eq!(active, vec!["laptop", "tv"]);
+        assert!(matches!(pool.renew("tablet"), Err(PoolError::NoLease(_))));
+        // the tablet comes back and gets its old address
+        assert_eq!(pool.request("tablet").unwrap().address, addr("10.0.0.4"));
+        // the printer's address is kept for it even while it's away
+        assert!(matches!(pool.request("phone"), Err(PoolError::Exhausted)));
+        // without the reservation, its run out lease is the one to reuse
+        pool.unreserve("printer").unwrap();
+        assert_eq!(pool.request("phone").unwrap().address, addr("10.0.0.3"));
+        assert!(pool.lease_for("printer").is_none());
+
+        clock.advance(HOUR);
+        assert_eq!(pool.expire().unwrap().len(), 4);
+        assert!(pool.active_leases().is_empty());
+    }
+
+    #[test]
+    fn oldest_expired_is_reused_first() {
+        let clock = ManualClock::new(0);
+        let mut pool = AddressPool::new(addr("10.0.0.1"), addr("10.0.0.2"), HOUR, &clock).unwrap();
+        pool.request("one").unwrap();
+        clock.advance(HOUR / 2);
+        pool.request("two").unwrap();
+        clock.advance(HOUR * 2);
+        assert_eq!(pool.request("three").unwrap().address, addr("10.0.0.1"));
+        assert_eq!(pool.request("four").unwrap().address, addr("10.0.0.2"));
+    }
+
+    #[test]
+    fn bad_ranges_and_client_ids() {
+        let clock = ManualClock::new(0);
+        assert!(matches!(AddressPool::new(addr("10.0.0.2"), addr("10.0.0.1"), HOUR, &clock), Err(PoolError::BadRange)));
+        assert!(matches!(AddressPool::new(addr("10.0.0.1"), addr("::1"), HOUR, &clock), Err(PoolError::BadRange)));
+        let mut pool = AddressPool::new(addr("10.0.0.1"), addr("10.0.0.1"), HOUR, &clock).unwrap();
+        assert!(matches!(pool.request("bad\tid"), Err(PoolError::BadClientId(_))));
+        assert!(matches!(pool.reserve("bad\nid", addr("10.0.0.1")), Err(PoolError::BadClientId(_))));
+    }
+
+    // far too big to walk through address by address
+    #[test]
+    fn huge_v6_pool() {
+        let clock = ManualClock::new(0);
+        let mut pool = AddressPool::new(addr("2001:db8::"), addr("2001:db8::ffff:ffff:ffff:ffff"), HOUR, &clock).unwrap();
+        pool.reserve("router", addr("2001:db8::")).unwrap();
+        assert_eq!(pool.request("a").unwrap().address, addr("2001:db8::1"));
+        assert_eq!(pool.request("b").unwrap().address, addr("2001:db8::2"));
+    }
+
+    // leases and reservations survive a restart, so the new pool doesn't hand out an address
+    // that's in use or kept for someone
+    #[test]
+    fn pool_file_round_trip() {
+        let dir = temp_dir("round_trip");
+        let path = dir.join("pool.txt");
+        let clock = ManualClock::new(1_000_000);
+        let (first, last) = (addr("10.0.0.1"), addr("10.0.0.4"));
+        {
+            let mut pool = AddressPool::open(&path, first, last, HOUR, &clock).unwrap();
+            pool.reserve("printer", addr("10.0.0.3")).unwrap();
+            pool.request("one").unwrap();
+            pool.request("two").unwrap();
+            pool.release("one").unwrap();
+        }
+        let mut pool = AddressPool::open(&path, first, last, HOUR, &clock).unwrap();
+        assert_eq!(pool.lease_for("two").unwrap().address, addr("10.0.0.2"));
+        assert_eq!(pool.lease_for("two").unwrap().expires_at, 1_000_000 + 3600);
+        assert!(pool.lease_for("one").is_none());
+        assert_eq!(pool.request("three").unwrap().address, addr("10.0.0.1"));
+        assert_eq!(pool.request("four").unwrap().address, addr("10.0.0.4"));
+        assert!(matches!(pool.request("five"), Err(PoolError::Exhausted)));
+        assert_eq!(pool.request("printer").unwrap().address, addr("10.0.0.3"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // when the file can't be written the change doesn't happen either, so the pool and its
+    // file still agree
+    #[test]
+    fn failed_saves_roll_back() {
+        let dir = temp_dir("roll_back");
+        let path = dir.join("pool.txt");
+        let clock = ManualClock::new(1_000_000);
+        let mut pool = AddressPool::open(&path, addr("10.0.0.1"), addr("10.0.0.4"), HOUR, &clock).unwrap();
+        pool.reserve("printer", addr("10.0.0.3")).unwrap();
+        for client_id in &["one", "two", "printer", "four"] {
+            pool.request(client_id).unwrap();
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(pool.release("two"), Err(PoolError::Io(_))));
+        assert!(matches!(pool.unreserve("printer"), Err(PoolError::Io(_))));
+        clock.advance(HOUR * 2);
+        assert!(matches!(pool.request("five"), Err(PoolError::Io(_))));
+        assert!(pool.lease_for("five").is_none());
+        assert!(matches!(pool.expire(), Err(PoolError::Io(_))));
+        assert_eq!(pool.leases.len(), 4);
+        assert_eq!(pool.reservations.get("printer"), Some(&addr("10.0.0.3").bits()));
+
+        fs::create_dir(&dir).unwrap();
+        assert_eq!(pool.expire().unwrap().len(), 4);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // a damaged file stops the pool from starting rather than being ignored
+    #[test]
+    fn damaged_pool_files() {
+        let dir = temp_dir("damaged");
+        let path = dir.join("pool.txt");
+        let clock = ManualClock::new(0);
+        let open_error = |text: String| {
+            fs::write(&path, text).unwrap();
+            match AddressPool::open(&path, addr("10.0.0.1"), addr("10.0.0.4"), HOUR, &clock) {
+                Err(PoolError::Io(e)) => e.to_string(),
+                _ => panic!("a damaged pool file should be an error"),
+            }
+        };
+        assert!(open_error(String::from("learn_rust address pool v1\n")).ends_with(":1: not an address pool file"));
+        let error = open_error(format!("{}\nleased\t10.0.0.1\tone\n", FILE_HEADER));
+        assert!(error.ends_with(":2: expected a lease's address, client id and expiry time"));
+        let error = open_error(format!("{}\n\nreserved\t10.0.0.300\tone\n", FILE_HEADER));
+        assert!(error.ends_with(":3: bad address"));
+        let error = open_error(format!("{}\nleased\t10.0.0.1\tone\tsoon\n", FILE_HEADER));
+        assert!(error.ends_with(":2: bad expiry time"));
+        assert!(open_error(format!("{}\nlent\t10.0.0.1\n", FILE_HEADER)).ends_with(":2: expected a reserved or leased line"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,320 @@
+// An arbitrary precision unsigned integer, enough to hold fibonacci numbers that don't
+// fit in a u128.
+// The number is stored as a vector of 32 bit 'limbs', least significant first, so
+// 2^32 + 5 is stored as [5, 1]. Each limb operation is done in a u64 so the carry
+// always fits.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Mul, Sub};
+
+// largest power of ten that fits in a u32, used to convert to decimal in chunks
+const DECIMAL_CHUNK: u32 = 1_000_000_000;
+const DECIMAL_CHUNK_DIGITS: usize = 9;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Default)]
+pub struct BigUint {
+    // no trailing zero limbs, so zero is an empty vector and equality can be derived
+    limbs: Vec<u32>,
+}
+
+impl BigUint {
+    pub fn zero() -> BigUint {
+        BigUint { limbs: Vec::new() }
+    }
+
+    pub fn one() -> BigUint {
+        BigUint::from(1u32)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.limbs.is_empty()
+    }
+
+    // returns None if the value is too large for a u128
+    pub fn to_u128(&self) -> Option<u128> {
+        if self.limbs.len() > 4 {
+            return None;
+        }
+        Some(self.limbs.iter().rev().fold(0u128, |acc, &limb| (acc << 32) | limb as u128))
+    }
+
+    // number of significant bits, zero has none
+    pub fn bits(&self) -> u64 {
+        match self.limbs.last() {
+            None => 0,
+            Some(top) => self.limbs.len() as u64 * 32 - top.leading_zeros() as u64,
+        }
+    }
+
+    fn normalise(mut self) -> BigUint {
+        while self.limbs.last() == Some(&0) {
+            self.limbs.pop();
+        }
+        self
+    }
+
+    // divides in place and returns the remainder, long division from the top limb down
+    fn div_rem_small(&mut self, divisor: u32) -> u32 {
+        let mut remainder = 0u64;
+        for limb in self.limbs.iter_mut().rev() {
+            let current = (remainder << 32) | *limb as u64;
+            *limb = (current / divisor as u64) as u32;
+            remainder = current % divisor as u64;
+        }
+        while self.limbs.last() == Some(&0) {
+            self.limbs.pop();
+        }
+        remainder as u32
+    }
+}
+
+impl From<u32> for BigUint {
+    fn from(n: u32) -> BigUint {
+        BigUint { limbs: vec![n] }.normalise()
+    }
+}
+
+impl From<u64> for BigUint {
+    fn from(n: u64) -> BigUint {
+        BigUint::from(n as u128)
+    }
+}
+
+impl From<u128> for BigUint {
+    fn from(mut n: u128) -> BigUint {
+        let mut limbs = Vec::new();
+        while n > 0 {
+            limbs.push(n as u32);
+            n >>= 32;
+        }
+        BigUint { limbs }
+    }
+}
+
+impl Ord for BigUint {
+    fn cmp(&self, other: &BigUint) -> Ordering {
+        // with no trailing zeros, more limbs always means a larger number
+        self.limbs
+            .len()
+            .cmp(&other.limbs.len())
+            .then_with(|| self.limbs.iter().rev().cmp(other.limbs.iter().rev()))
+    }
+}
+
+impl PartialOrd for BigUint {
+    fn partial_cmp(&self, other: &BigUint) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Add<&BigUint> for &BigUint {
+    type Output = BigUint;
+
+    fn add(self, other: &BigUint) -> BigUint {
+        let (long, short) = if self.limbs.len() >= other.limbs.len() {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        let mut limbs = Vec::with_capacity(long.limbs.len() + 1);
+        let mut carry = 0u64;
+        for (i, &limb) in long.limbs.iter().enumerate() {
+            let sum = limb as u64 + *short.limbs.get(i).unwrap_or(&0) as u64 + carry;
+            limbs.push(sum as u32);
+            carry = sum >> 32;
+        }
+        if carry > 0 {
+            limbs.push(carry as u32);
+        }
+        BigUint { limbs }
+    }
+}
+
+impl Add for BigUint {
+    type Output = BigUint;
+
+    fn add(self, other: BigUint) -> BigUint {
+        &self + &other
+    }
+}
+
+// panics if the result would be negative, just like u32 subtraction in a debug build
+impl Sub<&BigUint> for &BigUint {
+    type Output = BigUint;
+
+    fn sub(self, other: &BigUint) -> BigUint {
+        assert!(*self >= *other, "attempt to subtract with overflow");
+        let mut limbs = Vec::with_capacity(self.limbs.len());
+        let mut borrow = 0i64;
+        for (i, &limb) in self.limbs.iter().enumerate() {
+            let mut diff = limb as i64 - *other.limbs.get(i).unwrap_or(&0) as i64 - borrow;
+            borrow = if diff < 0 {
+                diff += 1 << 32;
+                1
+            } else {
+                0
+            };
+            limbs.push(diff as u32);
+        }
+        BigUint { limbs }.normalise()
+    }
+}
+
+impl Sub for BigUint {
+    type Output = BigUint;
+
+    fn sub(self, other: BigUint) -> BigUint {
+        &self - &other
+    }
+}
+
+// schoolbook long multiplication, O(n * m) in the number of limbs
+impl Mul<&BigUint> for &BigUint {
+    type Output = BigUint;
+
+    fn mul(self, other: &BigUint) -> BigUint {
+        if self.is_zero() || other.is_zero() {
+            return BigUint::zero();
+        }
+        let mut limbs = vec![0u32; self.limbs.len() + other.limbs.len()];
+        for (i, &a) in self.limbs.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, &b) in other.limbs.iter().enumerate() {
+                let current = limbs[i + j] as u64 + a as u64 * b as u64 + carry;
+                limbs[i + j] = current as u32;
+                carry = current >> 32;
+            }
+            limbs[i + other.limbs.len()] = carry as u32;
+        }
+        BigUint { limbs }.normalise()
+    }
+}
+
+impl Mul for BigUint {
+    type Output = BigUint;
+
+    fn mul(self, other: BigUint) -> BigUint {
+        &self * &other
+    }
+}
+
+impl fmt::Display for BigUint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_zero() {
+            return f.pad_integral(true, "", "0");
+        }
+        // peel off nine decimal digits at a time, least significant chunk first
+        let mut n = self.clone();
+        let mut chunks = Vec::new();
+        while !n.is_zero() {
+            chunks.push(n.div_rem_small(DECIMAL_CHUNK));
+        }
+        let mut digits = chunks.pop().unwrap().to_string();
+        for chunk in chunks.iter().rev() {
+            digits.push_str(&format!("{:0width$}", chunk, width = DECIMAL_CHUNK_DIGITS));
+        }
+        f.pad_integral(true, "", &digits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn big(n: u128) -> BigUint {
+        BigUint::from(n)
+    }
+
+    // 2^exponent, built by doubling so it doesn't depend on From<u128>
+    fn power_of_two(exponent: u32) -> BigUint {
+        (0..exponent).fold(BigUint::one(), |n, _| &n + &n)
+    }
+
+    #[test]
+    fn limbs_least_significant_first() {
+        assert_eq!(big((1 << 32) + 5).limbs, vec![5, 1]);
+        assert_eq!(BigUint::from(0u32).limbs, Vec::<u32>::new());
+        assert_eq!(BigUint::from(0u64), BigUint::zero());
+        assert_eq!(BigUint::from(u64::MAX).limbs, vec![u32::MAX, u32::MAX]);
+        assert_eq!(big(u128::MAX).to_u128(), Some(u128::MAX));
+        assert_eq!(power_of_two(128).to_u128(), None);
+        assert_eq!((big(0).bits(), big(1).bits(), big(u128::MAX).bits(), power_of_two(128).bits()), (0, 1, 128, 129));
+    }
+
+    #[test]
+    fn add_carries_across_limbs() {
+        assert_eq!(big(u32::MAX as u128) + BigUint::one(), big(1 << 32));
+        assert_eq!(big(u64::MAX as u128) + BigUint::one(), big(1 << 64));
+        // the carry runs all the way up and makes a new limb
+        assert_eq!(big(u128::MAX) + BigUint::one(), power_of_two(128));
+        assert_eq!((big(u128::MAX) + big(u128::MAX)).limbs, vec![u32::MAX - 1, u32::MAX, u32::MAX, u32::MAX, 1]);
+        // short plus long, either way round
+        assert_eq!(BigUint::one() + big(u128::MAX), power_of_two(128));
+        assert_eq!(big(12) + BigUint::zero(), big(12));
+    }
+
+    #[test]
+    fn sub_borrows_across_limbs() {
+        assert_eq!(big(1 << 32) - BigUint::one(), big(u32::MAX as u128));
+        assert_eq!(power_of_two(128) - BigUint::one(), big(u128::MAX));
+        // the result has fewer limbs than either side
+        assert_eq!((power_of_two(128) - big(u128::MAX)).limbs, vec![1]);
+        assert_eq!(big(u128::MAX) - big(u128::MAX), BigUint::zero());
+        assert_eq!(big(12) - BigUint::zero(), big(12));
+    }
+
+    #[test]
+    #[should_panic(expected = "subtract with overflow")]
+    fn sub_underflow() {
+        let _ = big(5) - big(6);
+    }
+
+    #[test]
+    #[should_panic(expected = "subtract with overflow")]
+    fn sub_underflow_across_limbs() {
+        let _ = big(u128::MAX) - power_of_two(128);
+    }
+
+    #[test]
+    fn mul() {
+        assert_eq!(big(6) * big(7), big(42));
+        assert_eq!(big(u64::MAX as u128) * big(u64::MAX as u128), big(u64::MAX as u128 * u64::MAX as u128));
+        assert_eq!(power_of_two(100) * power_of_two(100), power_of_two(200));
+        assert_eq!(big(u128::MAX) * BigUint::zero(), BigUint::zero());
+        assert_eq!(BigUint::zero() * big(u128::MAX), BigUint::zero());
+        assert_eq!(big(u128::MAX) * BigUint::one(), big(u128::MAX));
+        // (2^128 - 1)^2 = 2^256 - 2^129 + 1
+        let square = big(u128::MAX) * big(u128::MAX);
+        assert_eq!(square, power_of_two(256) - power_of_two(129) + BigUint::one());
+    }
+
+    #[test]
+    fn cmp() {
+        assert!(big(1 << 32) > big(u32::MAX as u128));
+        assert!(BigUint::zero() < BigUint::one());
+        // more limbs wins even when the low limbs are smaller
+        assert!(power_of_two(128) > big(u128::MAX));
+        // same length, decided by the top limb, not the bottom
+        assert!(big((2 << 32) + 1) > big((1 << 32) + 5));
+        assert_eq!(big(7).cmp(&big(7)), Ordering::Equal);
+        let mut sorted = vec![power_of_two(64), big(3), BigUint::zero(), big(u64::MAX as u128)];
+        sorted.sort();
+        assert_eq!(sorted, vec![BigUint::zero(), big(3), big(u64::MAX as u128), power_of_two(64)]);
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(BigUint::zero().to_string(), "0");
+        assert_eq!(big(42).to_string(), "42");
+        assert_eq!(big(u128::MAX).to_string(), u128::MAX.to_string());
+        // inner chunks keep their leading zeros
+        assert_eq!(big(1_000_000_000).to_string(), "1000000000");
+        assert_eq!(big(5_000_000_000_000_000_007).to_string(), "5000000000000000007");
+        assert_eq!(power_of_two(128).to_string(), "340282366920938463463374607431768211456");
+        // padding works like it does for the built in integers
+        assert_eq!(format!("{:>6}", big(42)), "    42");
+        assert_eq!(format!("{:06}", big(42)), "000042");
+        assert_eq!(format!("{:<4}|", BigUint::zero()), "0   |");
+    }
+}
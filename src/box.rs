@@ -12,6 +12,9 @@
 // rather than being a specific trait
 
 
+use std::fmt;
+use std::iter::FromIterator;
+
 fn storing_data_on_heap(){
     // this would never really happen as i32 values should always be stored on stack
     let b = Box::new(5);
@@ -26,24 +29,234 @@ fn storing_data_on_heap(){
 // each item in a cons lists contains two elements, the value of the current item, and the
 // next item.
 
-enum List{
-    Cons(i32, Box<List>),
-    Nil
+#[derive(Default)]
+pub enum List<T> {
+    Cons(T, Link<T>),
+    #[default]
+    Nil,
+}
+
+// the Box pointing at the rest of the list. It's wrapped in its own type so that it can have
+// a Drop impl, see the bottom of the file. Putting Drop on List itself would stop us moving
+// values out of a Cons, which pop and into_iter need to do
+pub struct Link<T>(Box<List<T>>);
+
+use List::{Cons, Nil};
+
+impl<T> Link<T> {
+    pub fn new(list: List<T>) -> Link<T> {
+        Link(Box::new(list))
+    }
+
+    // swaps the rest of the list out, leaving Nil in its place
+    fn take(&mut self) -> List<T> {
+        std::mem::replace(&mut *self.0, Nil)
+    }
 }
 
 fn use_cons(){
-    let list = List::Cons(1, Box::new(List::Cons(2, Box::new(List::Cons(3, Box::new(List::Nil))))));
+    let list = Cons(1, Link::new(Cons(2, Link::new(Cons(3, Link::new(Nil))))));
+    // or, with the macro below
+    let list = list![1, 2, 3];
+}
+
+// the list! macro builds a list from its arguments in order, like vec!
+// it goes through FromIterator, so list![1, 2, 3] is 1 -> 2 -> 3 -> Nil
+macro_rules! list {
+    () => { $crate::r#box::List::Nil };
+    ($($x:expr),+ $(,)?) => {
+        <$crate::r#box::List<_> as std::iter::FromIterator<_>>::from_iter([$($x),+])
+    };
+}
+pub(crate) use list;
+
+impl<T> List<T> {
+    pub fn new() -> List<T> {
+        Nil
+    }
+
+    pub fn push_front(&mut self, value: T) {
+        let rest = std::mem::replace(self, Nil);
+        *self = Cons(value, Link::new(rest));
+    }
+
+    // removes and returns the first value
+    pub fn pop(&mut self) -> Option<T> {
+        match std::mem::replace(self, Nil) {
+            Cons(value, mut rest) => {
+                *self = rest.take();
+                Some(value)
+            }
+            Nil => None,
+        }
+    }
+
+    pub fn head(&self) -> Option<&T> {
+        match self {
+            Cons(value, _) => Some(value),
+            Nil => None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        matches!(self, Nil)
+    }
+
+    // popping everything off the front and pushing it onto a new list reverses it
+    pub fn reverse(mut self) -> List<T> {
+        let mut reversed = Nil;
+        while let Some(value) = self.pop() {
+            reversed.push_front(value);
+        }
+        reversed
+    }
+
+    pub fn map<U, F: FnMut(&T) -> U>(&self, f: F) -> List<U> {
+        self.iter().map(f).collect()
+    }
+
+    pub fn fold<B, F: FnMut(B, &T) -> B>(&self, init: B, f: F) -> B {
+        self.iter().fold(init, f)
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { next: self }
+    }
+}
+
+// iterating by reference walks along the list without changing it
+pub struct Iter<'a, T> {
+    next: &'a List<T>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        match self.next {
+            Cons(value, rest) => {
+                self.next = &rest.0;
+                Some(value)
+            }
+            Nil => None,
+        }
+    }
+}
+
+impl<'a, T> IntoIterator for &'a List<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+// iterating by value pops the values off one at a time
+pub struct IntoIter<T>(List<T>);
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.0.pop()
+    }
+}
+
+impl<T> IntoIterator for List<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter(self)
+    }
+}
+
+// builds the list front to back, keeping a reference to the Nil at the end and replacing
+// it with each new value, so the values stay in the order they came in
+impl<T> FromIterator<T> for List<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> List<T> {
+        let mut list = Nil;
+        let mut end = &mut list;
+        for value in iter {
+            *end = Cons(value, Link::new(Nil));
+            end = match end {
+                Cons(_, rest) => &mut rest.0,
+                Nil => unreachable!(),
+            };
+        }
+        list
+    }
+}
+
+// the derived versions would recurse once per element, like the default drop
+impl<T: Clone> Clone for List<T> {
+    fn clone(&self) -> List<T> {
+        self.iter().cloned().collect()
+    }
+}
+
+impl<T: PartialEq> PartialEq for List<T> {
+    fn eq(&self, other: &List<T>) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for List<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+// 1 -> 2 -> 3 -> Nil
+impl<T: fmt::Display> fmt::Display for List<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for value in self {
+            write!(f, "{} -> ", value)?;
+        }
+        write!(f, "Nil")
+    }
+}
+
+// The drop rust generates for us would drop the first Link, which drops the List in its
+// Box, which drops the next Link and so on, one stack frame per element, so a long enough
+// list overflows the stack. Instead each Link unhooks the rest of the list and walks along
+// it in a loop. Every node it lets go of has had its own rest swapped for Nil first, so
+// dropping that node doesn't go any deeper.
+impl<T> Drop for Link<T> {
+    fn drop(&mut self) {
+        let mut rest = self.take();
+        while let Cons(_, mut next) = rest {
+            rest = next.take();
+        }
+    }
+}
+
+pub fn cons_list() {
+    let mut list = list![1, 2, 3];
+    println!("{} has {} values", list, list.len());
+
+    list.push_front(0);
+    println!("after push_front: {}", list);
+    println!("popped {:?}", list.pop());
+    println!("times ten: {}", list.map(|x| x * 10));
+    println!("sum: {}", list.fold(0, |acc, x| acc + x));
+    println!("reversed: {}", list.clone().reverse());
+
+    let words: List<String> = vec!["a", "b"].into_iter().map(String::from).collect();
+    println!("{:?}", words);
 }
 
 // to compute the size of a non recursive type:
 // e.g. the message enum
 
-enum Message{
-    Quit,
-    Move {x: i32, y: i32},
-    Write (String),
-    ChangeColour(i32, i32, i32) // largest variant will determine the size of space needed
-}
+// (the same Message as the enums chapter, see message.rs)
+// the largest variant will determine the size of space needed
+use crate::message::Message;
 
 // If we were to do this in the recursive case we would end up in an infinite loop, trying
 // to find out the size of List
@@ -52,3 +265,83 @@ enum Message{
 
 // Box<T> implements the Deref trait which allows Box<T> values to be treated like references,
 // When a Box<T> goes out of scope its implementation of Drop will also clean up the heap
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn list_macro() {
+        let list = list![1, 2, 3];
+        assert_eq!(list.len(), 3);
+        assert_eq!(list.to_string(), "1 -> 2 -> 3 -> Nil");
+        assert_eq!(format!("{:?}", list), "[1, 2, 3]");
+        assert_eq!(list, Cons(1, Link::new(Cons(2, Link::new(Cons(3, Link::new(Nil)))))));
+        // a trailing comma is fine, like vec!
+        assert_eq!(list![1, 2, 3,], list);
+        let empty: List<i32> = list![];
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn push_front_and_pop() {
+        let mut list = list![1, 2];
+        list.push_front(0);
+        assert_eq!(list.head(), Some(&0));
+        assert_eq!(list.pop(), Some(0));
+        assert_eq!(list.pop(), Some(1));
+        assert_eq!(list.pop(), Some(2));
+        assert_eq!(list.pop(), None);
+        assert_eq!(list.head(), None);
+        assert!(list.is_empty());
+    }
+
+    #[test]
+    fn map_fold_and_iter() {
+        let list = list![1, 2, 3];
+        assert_eq!(list.map(|x| x * 10), list![10, 20, 30]);
+        assert_eq!(list.map(|x| x.to_string()).to_string(), "1 -> 2 -> 3 -> Nil");
+        assert_eq!(list.fold(0, |acc, x| acc + x), 6);
+        assert_eq!(list.iter().collect::<Vec<_>>(), vec![&1, &2, &3]);
+        assert_eq!((&list).into_iter().count(), 3);
+    }
+
+    #[test]
+    fn reverse_clone_and_into_iter() {
+        let list = list![1, 2, 3];
+        let reversed = list.clone().reverse();
+        assert_eq!(reversed, list![3, 2, 1]);
+        assert!(reversed != list);
+        assert_eq!(list.into_iter().collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(List::<i32>::new().reverse(), list![]);
+    }
+
+    // values that aren't Copy move out of the list
+    #[test]
+    fn owned_values() {
+        let mut words: List<String> = vec!["a", "b"].into_iter().map(String::from).collect();
+        assert_eq!(words.to_string(), "a -> b -> Nil");
+        assert_eq!(words.pop(), Some(String::from("a")));
+        assert_eq!(words.into_iter().collect::<Vec<_>>(), vec![String::from("b")]);
+    }
+
+    #[test]
+    fn empty_list() {
+        let mut empty: List<i32> = List::default();
+        assert_eq!(empty.len(), 0);
+        assert_eq!(empty.pop(), None);
+        assert_eq!(empty.to_string(), "Nil");
+        assert_eq!(format!("{:?}", empty), "[]");
+    }
+
+    // each of these would overflow the stack if it recursed once per element
+    #[test]
+    fn long_lists_dont_overflow_the_stack() {
+        let long: List<u32> = (0..1_000_000).collect();
+        assert_eq!(long.len(), 1_000_000);
+        let copy = long.clone();
+        assert!(copy == long);
+        drop(copy);
+        drop(long);
+    }
+}
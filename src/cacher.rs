@@ -0,0 +1,366 @@
+// The Cacher from the closures chapter, grown up.
+// It holds a closure and lazily evaluates it, remembering the result for each argument.
+// Unlike the book version it works for any key and value type, and can be told how to
+// make room when it gets full.
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+// how the cacher decides what to throw away
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Policy {
+    // keep everything forever, like the original Cacher
+    Unbounded,
+    // once full, evict the entry that was used longest ago
+    Lru { capacity: usize },
+    // once full, evict the entry used the fewest times, the oldest of those on a tie
+    Lfu { capacity: usize },
+    // entries are thrown away once they are older than ttl
+    Ttl { ttl: Duration },
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+struct Entry<V> {
+    value: V,
+    inserted: Instant,
+    // the tick the entry was inserted on
+    inserted_tick: u64,
+    uses: u64,
+    last_used: u64,
+}
+
+pub struct Cacher<T, K, V>
+where
+    T: Fn(K) -> V,
+{
+    calculation: T,
+    policy: Policy,
+    values: HashMap<K, Entry<V>>,
+    // entries ordered by how keen we are to evict them, the first is the next to go
+    eviction_order: BTreeMap<(u64, u64), K>,
+    // bumped on every lookup, used as a timestamp for recency
+    tick: u64,
+    stats: Stats,
+}
+
+impl<T, K, V> Cacher<T, K, V>
+where
+    T: Fn(K) -> V,
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    pub fn new(calculation: T) -> Cacher<T, K, V> {
+        Cacher::with_policy(calculation, Policy::Unbounded)
+    }
+
+    pub fn with_policy(calculation: T, policy: Policy) -> Cacher<T, K, V> {
+        Cacher {
+            calculation,
+            policy,
+            values: HashMap::new(),
+            eviction_order: BTreeMap::new(),
+            tick: 0,
+            stats: Stats::default(),
+        }
+    }
+
+    pub fn value(&mut self, arg: K) -> V {
+        self.tick += 1;
+        self.expire(&arg);
+
+        if let Some(entry) = self.values.get_mut(&arg) {
+            self.stats.hits += 1;
+            let old_rank = rank(self.policy, entry);
+            entry.uses += 1;
+            entry.last_used = self.tick;
+            let new_rank = rank(self.policy, entry);
+            let value = entry.value.clone();
+            if let Some(key) = self.eviction_order.remove(&old_rank) {
+                self.eviction_order.insert(new_rank, key);
+            }
+            return value;
+        }
+
+        self.stats.misses += 1;
+        let v = (self.calculation)(arg.clone());
+        if self.capacity() == Some(0) {
+            return v; // nothing can be cached
+        }
+        if let Some(capacity) = self.capacity() {
+            while self.values.len() >= capacity {
+                self.evict_first();
+            }
+        }
+        let entry = Entry {
+            value: v.clone(),
+            inserted: Instant::now(),
+            inserted_tick: self.tick,
+            uses: 1,
+            last_used: self.tick,
+        };
+        self.eviction_order.insert(rank(self.policy, &entry), arg.clone());
+        self.values.insert(arg, entry);
+        v
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    fn capacity(&self) -> Option<usize> {
+        match self.policy {
+            Policy::Lru { capacity } | Policy::Lfu { capacity } => Some(capacity),
+            Policy::Unbounded | Policy::Ttl { .. } => None,
+        }
+    }
+
+    // with a ttl, entries are ordered by insertion time so everything stale sits at the
+    // front. The key being looked up is checked too, in case it is stale but not first.
+    fn expire(&mut self, arg: &K) {
+        let ttl = match self.policy {
+            Policy::Ttl { ttl } => ttl,
+            _ => return,
+        };
+        let is_stale = |entry: &Entry<V>| entry.inserted.elapsed() >= ttl;
+        while let Some(key) = self.eviction_order.values().next() {
+            if !is_stale(&self.values[key]) {
+                break;
+            }
+            self.evict_first();
+        }
+        if self.values.get(arg).is_some_and(is_stale) {
+            let entry = self.values.remove(arg).unwrap();
+            self.eviction_order.remove(&rank(self.policy, &entry));
+            self.stats.evictions += 1;
+        }
+    }
+
+    fn evict_first(&mut self) {
+        let first = self.eviction_order.keys().next().copied();
+        if let Some(rank) = first {
+            let key = self.eviction_order.remove(&rank).unwrap();
+            self.values.remove(&key);
+            self.stats.evictions += 1;
+        }
+    }
+}
+
+// where an entry sits in the eviction order, the smallest rank is evicted first.
+// no two entries share a tick, so the ticks double as tie breakers and ranks never clash
+fn rank<V>(policy: Policy, entry: &Entry<V>) -> (u64, u64) {
+    match policy {
+        Policy::Lfu { .. } => (entry.uses, entry.last_used),
+        // a ttl only cares about insertion order, which never changes after insert
+        Policy::Ttl { .. } => (0, entry.inserted_tick),
+        Policy::Unbounded | Policy::Lru { .. } => (0, entry.last_used),
+    }
+}
+
+// A Cacher that can be shared between threads behind an Arc, so value takes &self.
+// Each key gets its own OnceLock. The first thread to ask for a key runs the calculation
+// inside get_or_init, and any other thread asking for the same key meanwhile blocks on the
+// lock until the value is ready, so the closure only runs once per key (single-flight).
+// The map's mutex is only held long enough to find or create the slot, so slow
+// calculations for different keys still run in parallel.
+pub struct SyncCacher<T, K, V>
+where
+    T: Fn(K) -> V,
+{
+    calculation: T,
+    values: Mutex<HashMap<K, Arc<OnceLock<V>>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl<T, K, V> SyncCacher<T, K, V>
+where
+    T: Fn(K) -> V,
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    pub fn new(calculation: T) -> SyncCacher<T, K, V> {
+        SyncCacher {
+            calculation,
+            values: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn value(&self, arg: K) -> V {
+        let slot = {
+            let mut values = self.values.lock().unwrap();
+            Arc::clone(values.entry(arg.clone()).or_default())
+        };
+        let mut computed = false;
+        // if the calculation panics the slot is left empty and the next caller retries
+        let value = slot.get_or_init(|| {
+            computed = true;
+            (self.calculation)(arg)
+        });
+        let counter = if computed { &self.misses } else { &self.hits };
+        counter.fetch_add(1, Ordering::Relaxed);
+        value.clone()
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn cacher(policy: Policy) -> Cacher<impl Fn(u32) -> u32, u32, u32> {
+        Cacher::with_policy(|x| x * 10, policy)
+    }
+
+    // looks every key up in turn, true for each one that was already cached
+    fn hits<T: Fn(u32) -> u32>(cacher: &mut Cacher<T, u32, u32>, keys: &[u32]) -> Vec<bool> {
+        keys.iter()
+            .map(|&key| {
+                let before = cacher.stats().hits;
+                assert_eq!(cacher.value(key), key * 10);
+                cacher.stats().hits > before
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unbounded_keeps_everything() {
+        let mut cacher = cacher(Policy::Unbounded);
+        assert!(cacher.is_empty());
+        assert_eq!(hits(&mut cacher, &[1, 2, 3, 1, 2, 3]), vec![false, false, false, true, true, true]);
+        assert_eq!(cacher.len(), 3);
+        assert_eq!(cacher.stats(), Stats { hits: 3, misses: 3, evictions: 0 });
+    }
+
+    #[test]
+    fn the_calculation_runs_once_per_key() {
+        let calls = std::cell::Cell::new(0);
+        let mut cacher = Cacher::new(|x: u32| {
+            calls.set(calls.get() + 1);
+            x + 1
+        });
+        assert_eq!((cacher.value(1), cacher.value(1), cacher.value(2)), (2, 2, 3));
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[test]
+    fn lru_evicts_the_least_recently_used() {
+        let mut cacher = cacher(Policy::Lru { capacity: 2 });
+        assert_eq!(hits(&mut cacher, &[1, 2]), vec![false, false]);
+        // using 1 makes 2 the oldest, so 3 pushes 2 out
+        assert_eq!(hits(&mut cacher, &[1, 3]), vec![true, false]);
+        assert_eq!(hits(&mut cacher, &[1, 3, 2]), vec![true, true, false]);
+        // 2 pushed out 1, which was used before 3
+        assert_eq!(hits(&mut cacher, &[3, 1]), vec![true, false]);
+        assert_eq!(cacher.len(), 2);
+        assert_eq!(cacher.stats(), Stats { hits: 4, misses: 5, evictions: 3 });
+    }
+
+    #[test]
+    fn lfu_evicts_the_least_frequently_used() {
+        let mut cacher = cacher(Policy::Lfu { capacity: 2 });
+        assert_eq!(hits(&mut cacher, &[1, 1, 1, 2, 2]), vec![false, true, true, false, true]);
+        // 2 has been used less than 1, even though it was used more recently
+        assert_eq!(hits(&mut cacher, &[3, 1, 2]), vec![false, true, false]);
+        assert_eq!(cacher.stats(), Stats { hits: 4, misses: 4, evictions: 2 });
+    }
+
+    // on a tie the one whose last use was longest ago goes
+    #[test]
+    fn lfu_ties_evict_the_oldest() {
+        let mut cacher = cacher(Policy::Lfu { capacity: 3 });
+        hits(&mut cacher, &[1, 2, 3, 2, 1, 3]);
+        // all used twice, 2 was last used first
+        assert_eq!(hits(&mut cacher, &[4, 1, 3, 2]), vec![false, true, true, false]);
+        // when 2 came back it pushed out 4, the only one used just once, and 1 and 3 stay
+        assert_eq!(hits(&mut cacher, &[1, 3, 4]), vec![true, true, false]);
+    }
+
+    #[test]
+    fn zero_capacity_caches_nothing() {
+        let mut cacher = cacher(Policy::Lru { capacity: 0 });
+        assert_eq!(hits(&mut cacher, &[1, 1]), vec![false, false]);
+        assert!(cacher.is_empty());
+        assert_eq!(cacher.stats(), Stats { hits: 0, misses: 2, evictions: 0 });
+    }
+
+    // everything is already stale by the time it's looked up again
+    #[test]
+    fn ttl_of_zero_always_recalculates() {
+        let mut cacher = cacher(Policy::Ttl { ttl: Duration::ZERO });
+        assert_eq!(hits(&mut cacher, &[1, 1, 2]), vec![false, false, false]);
+        // the stale 1 went when 2 was looked up
+        assert_eq!(cacher.len(), 1);
+        assert_eq!(cacher.stats(), Stats { hits: 0, misses: 3, evictions: 2 });
+    }
+
+    #[test]
+    fn ttl_entries_expire_oldest_first() {
+        let mut cacher = cacher(Policy::Ttl { ttl: Duration::from_millis(100) });
+        hits(&mut cacher, &[1, 2]);
+        thread::sleep(Duration::from_millis(150));
+        // 1 and 2 are both stale, only 2 was asked for but 1 goes with it
+        assert_eq!(hits(&mut cacher, &[3, 2]), vec![false, false]);
+        assert_eq!(cacher.len(), 2);
+        assert_eq!(hits(&mut cacher, &[3, 2]), vec![true, true]);
+        assert_eq!(cacher.stats(), Stats { hits: 2, misses: 4, evictions: 2 });
+    }
+
+    // using an entry doesn't make it live any longer
+    #[test]
+    fn ttl_ignores_use() {
+        let mut cacher = cacher(Policy::Ttl { ttl: Duration::from_millis(100) });
+        hits(&mut cacher, &[1]);
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(hits(&mut cacher, &[1]), vec![true]);
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(hits(&mut cacher, &[1]), vec![false]);
+    }
+
+    #[test]
+    fn sync_cacher_counts_hits_and_misses() {
+        let cacher = Arc::new(SyncCacher::new(|x: u32| {
+            thread::sleep(Duration::from_millis(20));
+            x * 10
+        }));
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let cacher = Arc::clone(&cacher);
+                thread::spawn(move || cacher.value(7))
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), 70);
+        }
+        // only one thread ran the calculation, the rest waited for it
+        assert_eq!(cacher.stats(), Stats { hits: 3, misses: 1, evictions: 0 });
+        assert_eq!(cacher.value(8), 80);
+        assert_eq!(cacher.stats().misses, 2);
+    }
+}
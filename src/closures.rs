@@ -8,10 +8,17 @@ different context. They can capture values from the scope in which they're defin
 use std::thread;
 use std::time::Duration;
 use std::collections::HashMap;
+use std::sync::Arc;
 
+use crate::cacher::{Cacher, Policy, SyncCacher};
+use crate::persistent_cacher::{IntCodec, PersistentCacher};
+use crate::workout::Rng;
+
+// long enough to notice when the cache misses, short enough that the caching lessons
+// below don't drag
 fn simulated_expensive_calc(intesity: u32) -> u32 {
     println!("calculating slowly...");
-    thread::sleep(Duration::from_secs(2));
+    thread::sleep(Duration::from_millis(200));
     intesity
 }
 
@@ -33,11 +40,51 @@ fn generate_workout(intesity: u32, random_number: u32) {
     }
 }
 
-fn main() {
+// Cacher::value needs &mut self so it can't be shared between threads, SyncCacher can be
+// wrapped in an Arc instead. Both threads ask for the same intensity at the same time,
+// but the slow calculation only runs once, the second thread waits for the first
+fn generate_workouts_in_parallel(intensity: u32) {
+    let expensive_result = Arc::new(SyncCacher::new(simulated_expensive_calc));
+    let handles: Vec<_> = (0..2)
+        .map(|_| {
+            let expensive_result = Arc::clone(&expensive_result);
+            thread::spawn(move || {
+                println!("Today, do {} pushups", expensive_result.value(intensity));
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(expensive_result.stats().misses, 1);
+}
+
+// results saved to a file are still there next time the program runs, so only the first
+// run has to wait for the slow calculation
+fn remembering_between_runs() -> std::io::Result<()> {
+    let path = std::env::temp_dir().join("simulated_expensive_calc.cache");
+    let mut expensive_result =
+        PersistentCacher::open(path, simulated_expensive_calc, IntCodec, IntCodec)?;
+    println!("Today, do {} pushups", expensive_result.value(10)?);
+    expensive_result.compact()
+}
+
+pub fn this_main() {
     let simulated_user_specific_value = 10;
-    let simulated_random_number = 7;
+    // the full planner built on this is in workout.rs, run it with `learn_rust workout`
+    let simulated_random_number = Rng::from_time().below(10);
 
     generate_workout(simulated_user_specific_value, simulated_random_number);
+
+    one_type_inference_per_closure_def();
+    pass_closure();
+    capturing_environment();
+    move_keyword();
+
+    tuning_a_cache();
+    generate_workouts_in_parallel(simulated_user_specific_value);
+    // only calculates slowly the first time it's ever run on this machine
+    remembering_between_runs().expect("the cache file in the temp directory should be usable");
 }
 
 
@@ -69,35 +116,21 @@ fn accept_closure_int(f: fn(u32) -> u32) {
 // we use generic params and trait bounds to define structs, enums, or functions that use
 // closure
 
-struct Cacher<T> where T: Fn(u32) -> u32 {
-    calculation: T,
-    value: HashMap<u32, u32>,
-}
+// the generic version of the Cacher lives in cacher.rs, it works for any hashable key
+// and can evict entries using an LRU, LFU or time-to-live policy
 
-impl<T> Cacher<T> where T: Fn(u32) -> u32 {
-    fn new(calculation: T) -> Cacher<T> {
-        Cacher {
-            calculation,
-            value: HashMap::new(),
-        }
-    }
-
-    fn value(&mut self, arg: u32) -> u32 {
-        match self.value.get(&arg) {
-            None => {
-                let v = (self.calculation)(arg);
-                self.value.insert(arg, v); // cache
-                v // return
-            }
-            Some(val) => *val
-        }
+// a bounded cache around the expensive calculation, the stats show how well it is doing
+fn tuning_a_cache() {
+    let mut cached_calc = Cacher::with_policy(simulated_expensive_calc, Policy::Lru { capacity: 2 });
+    for intensity in [10, 20, 10, 30, 10, 20].iter() {
+        cached_calc.value(*intensity);
     }
+    let stats = cached_calc.stats();
+    println!("hits {}, misses {}, evictions {}", stats.hits, stats.misses, stats.evictions);
+    // 30 pushes out 20, and the last 20 pushes out 30, so only two of the repeats are hits
+    assert_eq!((stats.hits, stats.misses, stats.evictions), (2, 4, 2));
 }
 
-
-// the cacher has limits:  it will only have one value once the virst call to c.value has
-// been made
-
 fn capturing_environment() {
     let x = 4;
     let equal_to_x = |z| z == x;
@@ -2,7 +2,10 @@
 // operator. Often this is emulate the behaviour of a regular reference.
 
 
-use std::ops::Deref;
+use std::cell::{Cell, UnsafeCell};
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::panic::Location;
 
 fn dereference_operator(){
     let x = 5;
@@ -46,3 +49,270 @@ fn deref_my_box(){
     assert_eq!(5, x);
     assert_eq!(5, *y); // need to impl Deref trait
 }
+
+
+// our own RefCell, using the same tricks as MyBox to hand out references through guards
+
+// RefCell moves the borrowing rules from compile time to run time. It hands out guards
+// (Ref and RefMut) that deref to the value inside, and keeps a count of how many are alive:
+// any number of shared borrows, or exactly one mutable one. Breaking the rules panics
+// (or returns an Err from the try_ versions) instead of failing to compile.
+//
+// On top of what std does, every borrow remembers where in the source it was made (via
+// #[track_caller]), so a failed borrow can say which borrow is still in the way.
+
+pub struct MyRefCell<T> {
+    // the count of borrows: 0 is none, n > 0 is n shared borrows, -1 is a mutable borrow
+    borrow: Cell<isize>,
+    // where each live borrow was made. There can be several shared borrows, but only one
+    // mutable, so the list never has more than one entry while borrow is -1
+    held_at: UnsafeCell<Vec<&'static Location<'static>>>,
+    // UnsafeCell is the only way in rust to get a &mut T from a &self, every other cell
+    // type (including std's RefCell) is built on top of it
+    value: UnsafeCell<T>,
+}
+
+#[derive(Debug)]
+pub struct BorrowError {
+    // the mutable borrow that's still alive
+    pub held_at: &'static Location<'static>,
+}
+
+#[derive(Debug)]
+pub struct BorrowMutError {
+    // one of the borrows that's still alive, the oldest if there are several
+    pub held_at: &'static Location<'static>,
+}
+
+impl fmt::Display for BorrowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "already mutably borrowed at {}", self.held_at)
+    }
+}
+
+impl fmt::Display for BorrowMutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "already borrowed at {}", self.held_at)
+    }
+}
+
+impl<T> MyRefCell<T> {
+    pub fn new(value: T) -> MyRefCell<T> {
+        MyRefCell { borrow: Cell::new(0), held_at: UnsafeCell::new(Vec::new()), value: UnsafeCell::new(value) }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    // &mut self already proves nobody else is borrowing, so no checks are needed
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    #[track_caller]
+    pub fn borrow(&self) -> MyRef<'_, T> {
+        match self.try_borrow() {
+            Ok(guard) => guard,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[track_caller]
+    pub fn borrow_mut(&self) -> MyRefMut<'_, T> {
+        match self.try_borrow_mut() {
+            Ok(guard) => guard,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[track_caller]
+    pub fn try_borrow(&self) -> Result<MyRef<'_, T>, BorrowError> {
+        let count = self.borrow.get();
+        if count < 0 {
+            return Err(BorrowError { held_at: self.oldest_borrow() });
+        }
+        self.borrow.set(count.checked_add(1).expect("too many shared borrows"));
+        let location = Location::caller();
+        self.locations().push(location);
+        Ok(MyRef { cell: self, location })
+    }
+
+    #[track_caller]
+    pub fn try_borrow_mut(&self) -> Result<MyRefMut<'_, T>, BorrowMutError> {
+        if self.borrow.get() != 0 {
+            return Err(BorrowMutError { held_at: self.oldest_borrow() });
+        }
+        self.borrow.set(-1);
+        self.locations().push(Location::caller());
+        Ok(MyRefMut { cell: self })
+    }
+
+    #[track_caller]
+    pub fn replace(&self, value: T) -> T {
+        std::mem::replace(&mut *self.borrow_mut(), value)
+    }
+
+    fn oldest_borrow(&self) -> &'static Location<'static> {
+        self.locations()[0]
+    }
+
+    #[allow(clippy::mut_from_ref)]
+    fn locations(&self) -> &mut Vec<&'static Location<'static>> {
+        // SAFETY: MyRefCell isn't Sync, so only this thread can get here, and none of the
+        // callers hold on to the returned reference past the line it's used on
+        unsafe { &mut *self.held_at.get() }
+    }
+}
+
+pub struct MyRef<'b, T> {
+    cell: &'b MyRefCell<T>,
+    location: &'static Location<'static>,
+}
+
+impl<T> Deref for MyRef<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: while this guard is alive the count is above zero, so no MyRefMut exists
+        // and nobody can be changing the value
+        unsafe { &*self.cell.value.get() }
+    }
+}
+
+impl<T> Drop for MyRef<'_, T> {
+    fn drop(&mut self) {
+        self.cell.borrow.set(self.cell.borrow.get() - 1);
+        let locations = self.cell.locations();
+        if let Some(i) = locations.iter().position(|l| std::ptr::eq(*l, self.location)) {
+            locations.remove(i);
+        }
+    }
+}
+
+pub struct MyRefMut<'b, T> {
+    cell: &'b MyRefCell<T>,
+}
+
+impl<T> Deref for MyRefMut<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: the count is -1 while this guard is alive, so it's the only way in
+        unsafe { &*self.cell.value.get() }
+    }
+}
+
+impl<T> DerefMut for MyRefMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: as above, and &mut self means the guard itself isn't being shared
+        unsafe { &mut *self.cell.value.get() }
+    }
+}
+
+impl<T> Drop for MyRefMut<'_, T> {
+    fn drop(&mut self) {
+        self.cell.borrow.set(0);
+        self.cell.locations().clear();
+    }
+}
+
+// each test runs the same sequence of borrows against MyRefCell and std's RefCell, and checks
+// that every step succeeds or fails the same way on both
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    #[test]
+    fn shared_borrows_block_mutable_ones() {
+        let mine = MyRefCell::new(vec![1, 2, 3]);
+        let theirs = RefCell::new(vec![1, 2, 3]);
+
+        let a = (mine.try_borrow(), theirs.try_borrow());
+        let b = (mine.try_borrow(), theirs.try_borrow());
+        assert!(a.0.is_ok() && a.1.is_ok() && b.0.is_ok() && b.1.is_ok());
+        // can't borrow mutably while there are shared borrows
+        assert_eq!(mine.try_borrow_mut().is_ok(), theirs.try_borrow_mut().is_ok());
+        drop(a);
+        assert_eq!(mine.try_borrow_mut().is_ok(), theirs.try_borrow_mut().is_ok());
+        drop(b);
+        // all shared borrows gone, now it works
+        assert!(mine.try_borrow_mut().is_ok() && theirs.try_borrow_mut().is_ok());
+    }
+
+    #[test]
+    fn mutable_borrow_blocks_everything() {
+        let mine = MyRefCell::new(vec![1, 2, 3]);
+        let theirs = RefCell::new(vec![1, 2, 3]);
+        {
+            let mut m = mine.borrow_mut();
+            let mut t = theirs.borrow_mut();
+            m.push(4);
+            t.push(4);
+            assert_eq!(mine.try_borrow().is_ok(), theirs.try_borrow().is_ok());
+            assert_eq!(mine.try_borrow_mut().is_ok(), theirs.try_borrow_mut().is_ok());
+        }
+        assert_eq!(*mine.borrow(), *theirs.borrow());
+    }
+
+    #[test]
+    fn replace_get_mut_and_into_inner() {
+        let mut mine = MyRefCell::new(vec![1, 2, 3]);
+        let mut theirs = RefCell::new(vec![1, 2, 3]);
+        assert_eq!(mine.replace(vec![9]), theirs.replace(vec![9]));
+        mine.get_mut().push(10);
+        theirs.get_mut().push(10);
+        assert_eq!(mine.into_inner(), theirs.into_inner());
+    }
+
+    // borrows the cell and says where from. Both are #[track_caller], so the borrow records
+    // the same place as the one returned: the line in the test that called this
+    #[track_caller]
+    fn borrow_here<T>(cell: &MyRefCell<T>) -> (MyRef<'_, T>, &'static Location<'static>) {
+        (cell.borrow(), Location::caller())
+    }
+
+    #[track_caller]
+    fn borrow_mut_here<T>(cell: &MyRefCell<T>) -> (MyRefMut<'_, T>, &'static Location<'static>) {
+        (cell.borrow_mut(), Location::caller())
+    }
+
+    // the error points at the borrow that's still held
+    #[test]
+    fn errors_say_where_the_borrow_is_held() {
+        let cell = MyRefCell::new(5);
+        let (held, held_at) = borrow_mut_here(&cell);
+        let err = cell.try_borrow().err().expect("the cell is mutably borrowed");
+        assert_eq!(err.held_at, held_at);
+        assert_eq!(err.held_at.file(), file!());
+        assert_eq!(err.to_string(), format!("already mutably borrowed at {}", err.held_at));
+        drop(held);
+
+        // with two shared borrows, the one still held is the one reported
+        let (first, first_at) = borrow_here(&cell);
+        let (second, second_at) = borrow_here(&cell);
+        drop(first);
+        let err = cell.try_borrow_mut().err().expect("the cell is borrowed");
+        assert_eq!(err.held_at, second_at);
+        assert_ne!(err.held_at, first_at);
+        drop(second);
+    }
+
+    // and both panic when borrow_mut is called on a borrowed cell
+    #[test]
+    #[should_panic(expected = "already borrowed at")]
+    fn my_borrow_mut_panics_while_borrowed() {
+        let mine = MyRefCell::new(0);
+        let _m = mine.borrow();
+        mine.borrow_mut();
+    }
+
+    #[test]
+    #[should_panic(expected = "already borrowed")]
+    fn their_borrow_mut_panics_while_borrowed() {
+        let theirs = RefCell::new(0);
+        let _t = theirs.borrow();
+        theirs.borrow_mut();
+    }
+}
@@ -3,6 +3,13 @@ Drop trait allows you to customise what happens when a value goes out of scope.
 This can be implemented on any type (you can use it to control the release of resources
 such as network connections and files)
  */
+use std::any;
+use std::ops::{Deref, DerefMut};
+use std::panic::Location;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread::{self, ThreadId};
+
 struct CustomSmartPointer{
     data: String,
 }
@@ -13,6 +20,141 @@ impl Drop for CustomSmartPointer{
     }
 }
 
+// Printing shows the drop order, but nothing checks it. Tracked wraps any value and writes
+// to a log when it's made and when it's dropped, so the order can be asserted on instead of
+// read off the terminal.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Created,
+    Dropped,
+}
+
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub kind: EventKind,
+    // unique to each Tracked value, so its two events can be paired up
+    pub id: u64,
+    pub label: &'static str,
+    pub type_name: &'static str,
+    // where the value was made. Drop can't be #[track_caller], so the drop event carries
+    // the same location to say which value went
+    pub created_at: &'static Location<'static>,
+    thread: ThreadId,
+}
+
+// one log for the whole program. Each event remembers its thread, so a scope only looks at
+// what happened on its own thread
+static LOG: Mutex<Vec<Event>> = Mutex::new(Vec::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+fn record(kind: EventKind, id: u64, label: &'static str, type_name: &'static str, created_at: &'static Location<'static>) {
+    let thread = thread::current().id();
+    LOG.lock().unwrap().push(Event { kind, id, label, type_name, created_at, thread });
+}
+
+pub struct Tracked<T> {
+    value: T,
+    id: u64,
+    label: &'static str,
+    created_at: &'static Location<'static>,
+}
+
+impl<T> Tracked<T> {
+    #[track_caller]
+    pub fn new(label: &'static str, value: T) -> Tracked<T> {
+        let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+        let created_at = Location::caller();
+        record(EventKind::Created, id, label, any::type_name::<T>(), created_at);
+        Tracked { value, id, label, created_at }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+}
+
+impl<T> Deref for Tracked<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for Tracked<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+// runs before the wrapped value's own drop, so the event is logged before anything T prints
+impl<T> Drop for Tracked<T> {
+    fn drop(&mut self) {
+        record(EventKind::Dropped, self.id, self.label, any::type_name::<T>(), self.created_at);
+    }
+}
+
+pub fn is_dropped(id: u64) -> bool {
+    LOG.lock().unwrap().iter().any(|e| e.id == id && e.kind == EventKind::Dropped)
+}
+
+// Runs f and hands back every event for values it made, taking them out of the global log so
+// it doesn't keep growing. Values made before the scope are left alone, even if f drops them.
+pub fn scope<R>(f: impl FnOnce() -> R) -> (R, Vec<Event>) {
+    let guard = ScopeGuard { first_id: NEXT_ID.load(Ordering::SeqCst), thread: thread::current().id() };
+    let result = f();
+    let events = guard.drain();
+    (result, events)
+}
+
+// if f panics the events are never handed back, but they still have to come out of the log,
+// so the draining happens when the guard is dropped during the unwind as well
+struct ScopeGuard {
+    first_id: u64,
+    thread: ThreadId,
+}
+
+impl ScopeGuard {
+    fn drain(&self) -> Vec<Event> {
+        // a panic can't happen while the lock is held, but if one somehow did the log is
+        // still fine to read, and panicking again in the middle of an unwind would abort
+        let mut log = LOG.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let (mine, rest) = log.drain(..).partition(|e| e.thread == self.thread && e.id >= self.first_id);
+        *log = rest;
+        mine
+    }
+}
+
+impl Drop for ScopeGuard {
+    fn drop(&mut self) {
+        self.drain();
+    }
+}
+
+// the values that were made but never dropped, either forgotten, caught in an Rc cycle, or
+// still alive in whatever the scope returned
+pub fn leaks(events: &[Event]) -> Vec<&Event> {
+    events
+        .iter()
+        .filter(|e| e.kind == EventKind::Created)
+        .filter(|created| !events.iter().any(|e| e.id == created.id && e.kind == EventKind::Dropped))
+        .collect()
+}
+
+// Runs f and checks that everything it made was dropped, in the order given by label
+pub fn assert_drop_order<R>(expected: &[&str], f: impl FnOnce() -> R) -> R {
+    let (result, events) = scope(f);
+    let leaked: Vec<String> = leaks(&events)
+        .iter()
+        .map(|e| format!("{} ({}) made at {}", e.label, e.type_name, e.created_at))
+        .collect();
+    assert!(leaked.is_empty(), "never dropped: {}", leaked.join(", "));
+    let dropped: Vec<&str> = events.iter().filter(|e| e.kind == EventKind::Dropped).map(|e| e.label).collect();
+    assert_eq!(dropped, expected, "values were dropped in the wrong order");
+    result
+}
+
 fn use_custom_smart_pointer(){
     let c = CustomSmartPointer{
         data: String::from("my stuff"),
@@ -37,4 +179,86 @@ fn calling_drop_manually(){
     println!("CustomSmartPointer dropped before the end of main.");
 }
 
+// the two functions above again, with Tracked checking what they only print
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // variables are dropped in the reverse order they were created
+    #[test]
+    fn use_custom_smart_pointer() {
+        assert_drop_order(&["d", "c"], || {
+            let c = Tracked::new("c", CustomSmartPointer{
+                data: String::from("my stuff"),
+            });
+
+            let d = Tracked::new("d", CustomSmartPointer{
+                data: String::from("other stuff"),
+            });
+        });
+    }
+
+    // makes a Tracked and says where from. Tracked::new is #[track_caller] too, so both see
+    // the line in the test that called this
+    #[track_caller]
+    fn tracked_here<T>(label: &'static str, value: T) -> (Tracked<T>, &'static Location<'static>) {
+        (Tracked::new(label, value), Location::caller())
+    }
+
+    // and the log knows what each one was and where it was made
+    #[test]
+    fn events_say_what_and_where() {
+        let (made_at, events) = scope(|| {
+            let (c, made_at) = tracked_here("c", CustomSmartPointer{ data: String::from("my stuff") });
+            made_at
+        });
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].type_name, any::type_name::<CustomSmartPointer>());
+        assert_eq!(events[0].created_at.file(), file!());
+        assert_eq!(events[0].created_at, made_at);
+        assert_eq!(events[1].created_at, made_at);
+        assert_eq!(events[1].kind, EventKind::Dropped);
+        assert_eq!(events[1].id, events[0].id);
+    }
+
+    // mem::forget skips the drop, so it shows up as a leak
+    #[test]
+    fn forgotten_values_leak() {
+        let (_, events) = scope(|| std::mem::forget(Tracked::new("forgotten", 1)));
+        assert_eq!(leaks(&events).iter().map(|e| e.label).collect::<Vec<_>>(), vec!["forgotten"]);
+    }
 
+    // the events from a scope that panics don't stay in the log
+    #[test]
+    fn panicking_scope_leaves_nothing_behind() {
+        let mut id = None;
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            scope(|| {
+                let kept = Tracked::new("kept", 1);
+                id = Some(kept.id());
+                std::mem::forget(kept);
+                panic!("scope panicked");
+            })
+        }));
+        assert!(result.is_err());
+        let id = id.unwrap();
+        assert!(!LOG.lock().unwrap().iter().any(|e| e.id == id));
+    }
+
+    #[test]
+    fn calling_drop_manually() {
+        assert_drop_order(&["c", "e"], || {
+            let e = Tracked::new("e", CustomSmartPointer{
+                data: String::from("Some other data"),
+            });
+            let c = Tracked::new("c", CustomSmartPointer{
+                data: String::from("Some data"),
+            });
+            let id = c.id();
+            std::mem::drop(c);
+            // c went here, rather than after e at the end of the scope
+            assert!(is_dropped(id));
+            assert!(!is_dropped(e.id()));
+        });
+    }
+}
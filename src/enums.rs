@@ -1,6 +1,12 @@
 // Enums allow you to define a type by enumerating its possible variants. These are
 // essentially an algebraic data type from functional program
 
+use std::fmt;
+use std::net::{IpAddr as StdIpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use crate::big_uint::BigUint;
+
 
 // Example... we need to work with IP addresses, and there are two possible variants
 // v4, v6
@@ -22,6 +28,7 @@ fn enums() {
 
 fn route(ip_kind: IpAddrKind) {
     // do something w/ ip address
+    // (routing_table.rs does it for real, finding the most specific route for an address)
 }
 
 fn call_route() {
@@ -38,45 +45,879 @@ struct IpAddr {
 
 // or we can be more concise with enums
 
-enum IpAddrWithTypes {
-    V4(String),
-    V6(String),
+// The book stores the address as a String, which would happily take "999.1.1.1". Here the
+// variants hold the checked address types further down, so the only way to get one is
+// to parse it (or build it from numbers that can't be out of range).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum IpAddrWithTypes {
+    V4(IpV4Addr),
+    V6(IpV6Addr),
 }
 
 fn use_enum_with_data() {
-    let home = IpAddrWithTypes::V4(String::from("127.0.0.1"));
-    let home = IpAddrWithTypes::V6(String::from("::1"));
+    let home: IpAddrWithTypes = "127.0.0.1".parse().unwrap();
+    let home: IpAddrWithTypes = "::1".parse().unwrap();
 }
 
 // aside from being concise the enum is more flexible that the struct option
 // each value can have different types
 
-enum IpAddrWithDiffTypes {
+// four u8s can't be out of range, so only the V6 text needed checking
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IpAddrWithDiffTypes {
     V4(u8, u8, u8, u8),
-    V6(String),
+    V6(IpV6Addr),
 }
 
 // any type can be used as values, even other enums
-struct IpV4Addr {}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct IpV4Addr {
+    octets: [u8; 4],
+}
 
-struct IpV6Addr {}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct IpV6Addr {
+    segments: [u16; 8],
+}
 
 enum IpAddrWithStructs {
     V4(IpV4Addr),
     V6(IpV6Addr),
 }
 
-// a more complex enum
-enum Message {
-    Quit,
-    // no associated data
-    Move { x: i32, y: i32 },
-    // includes an anonymous struct
-    Write(String),
-    // includes a single string
-    ChangeColour(i32, i32, i32),    // includes three i32 values
+// Parsing addresses from text
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseIpError {
+    Empty,
+    // a dotted quad needs exactly four numbers
+    WrongOctetCount(usize),
+    // not a number at all, e.g. "1.2.x.4" or "1..3.4"
+    BadOctet(String),
+    OctetOutOfRange(String),
+    // "01" could mean octal to some tools, so it's refused rather than guessed at
+    LeadingZero(String),
+    // a v6 group has to be one to four hex digits
+    BadGroup(String),
+    // without "::" there must be exactly eight groups, with it fewer than eight
+    WrongGroupCount(usize),
+    TooManyDoubleColons,
+    // a dotted quad inside a v6 address can only be the last 32 bits
+    Ipv4NotAtEnd(String),
+}
+
+impl fmt::Display for ParseIpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseIpError::Empty => write!(f, "empty address"),
+            ParseIpError::WrongOctetCount(n) => write!(f, "expected 4 octets, found {}", n),
+            ParseIpError::BadOctet(o) => write!(f, "octet '{}' is not a number", o),
+            ParseIpError::OctetOutOfRange(o) => write!(f, "octet '{}' is out of range (0-255)", o),
+            ParseIpError::LeadingZero(o) => write!(f, "octet '{}' has a leading zero", o),
+            ParseIpError::BadGroup(g) => write!(f, "group '{}' is not 1 to 4 hex digits", g),
+            ParseIpError::WrongGroupCount(n) => write!(f, "wrong number of groups ({}) for an IPv6 address", n),
+            ParseIpError::TooManyDoubleColons => write!(f, "'::' can only appear once"),
+            ParseIpError::Ipv4NotAtEnd(part) => write!(f, "embedded IPv4 address '{}' must come last", part),
+        }
+    }
+}
+
+impl IpV4Addr {
+    pub fn new(a: u8, b: u8, c: u8, d: u8) -> IpV4Addr {
+        IpV4Addr { octets: [a, b, c, d] }
+    }
+
+    pub fn octets(self) -> [u8; 4] {
+        self.octets
+    }
+
+    // ::ffff:a.b.c.d, how a v6 socket sees a v4 address
+    pub fn to_ipv6_mapped(self) -> IpV6Addr {
+        let [a, b, c, d] = self.octets;
+        IpV6Addr::new([0, 0, 0, 0, 0, 0xffff, u16::from_be_bytes([a, b]), u16::from_be_bytes([c, d])])
+    }
+}
+
+impl IpV6Addr {
+    pub fn new(segments: [u16; 8]) -> IpV6Addr {
+        IpV6Addr { segments }
+    }
+
+    pub fn segments(self) -> [u16; 8] {
+        self.segments
+    }
+
+    pub fn to_ipv4_mapped(self) -> Option<IpV4Addr> {
+        match self.segments {
+            [0, 0, 0, 0, 0, 0xffff, high, low] => {
+                let [a, b] = high.to_be_bytes();
+                let [c, d] = low.to_be_bytes();
+                Some(IpV4Addr::new(a, b, c, d))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for IpV4Addr {
+    type Err = ParseIpError;
+
+    fn from_str(s: &str) -> Result<IpV4Addr, ParseIpError> {
+        if s.is_empty() {
+            return Err(ParseIpError::Empty);
+        }
+        let parts: Vec<&str> = s.split('.').collect();
+        if parts.len() != 4 {
+            return Err(ParseIpError::WrongOctetCount(parts.len()));
+        }
+        let mut octets = [0; 4];
+        for (octet, part) in octets.iter_mut().zip(parts) {
+            // checked by hand, u8::from_str would also take "+1"
+            if part.is_empty() || !part.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(ParseIpError::BadOctet(part.to_string()));
+            }
+            if part.len() > 1 && part.starts_with('0') {
+                return Err(ParseIpError::LeadingZero(part.to_string()));
+            }
+            *octet = part.parse().map_err(|_| ParseIpError::OctetOutOfRange(part.to_string()))?;
+        }
+        Ok(IpV4Addr { octets })
+    }
+}
+
+// the groups on one side of a "::", or the whole address if there isn't one.
+// A dotted quad counts as two groups, and is only allowed as the very last part
+fn parse_groups(part: &str, ends_address: bool) -> Result<Vec<u16>, ParseIpError> {
+    let mut groups = Vec::new();
+    if part.is_empty() {
+        return Ok(groups);
+    }
+    let pieces: Vec<&str> = part.split(':').collect();
+    for (i, piece) in pieces.iter().enumerate() {
+        if piece.contains('.') {
+            if !ends_address || i != pieces.len() - 1 {
+                return Err(ParseIpError::Ipv4NotAtEnd(piece.to_string()));
+            }
+            let [a, b, c, d] = piece.parse::<IpV4Addr>()?.octets;
+            groups.push(u16::from_be_bytes([a, b]));
+            groups.push(u16::from_be_bytes([c, d]));
+        } else if piece.is_empty() || piece.len() > 4 || !piece.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(ParseIpError::BadGroup(piece.to_string()));
+        } else {
+            groups.push(u16::from_str_radix(piece, 16).unwrap());
+        }
+    }
+    Ok(groups)
+}
+
+impl FromStr for IpV6Addr {
+    type Err = ParseIpError;
+
+    fn from_str(s: &str) -> Result<IpV6Addr, ParseIpError> {
+        if s.is_empty() {
+            return Err(ParseIpError::Empty);
+        }
+        let mut segments = [0; 8];
+        match s.find("::") {
+            None => {
+                let groups = parse_groups(s, true)?;
+                if groups.len() != 8 {
+                    return Err(ParseIpError::WrongGroupCount(groups.len()));
+                }
+                segments.copy_from_slice(&groups);
+            }
+            Some(i) => {
+                let (head, tail) = (&s[..i], &s[i + 2..]);
+                if tail.contains("::") {
+                    return Err(ParseIpError::TooManyDoubleColons);
+                }
+                let head = parse_groups(head, false)?;
+                let tail = parse_groups(tail, true)?;
+                // "::" stands for at least one group of zeros
+                if head.len() + tail.len() > 7 {
+                    return Err(ParseIpError::WrongGroupCount(head.len() + tail.len()));
+                }
+                segments[..head.len()].copy_from_slice(&head);
+                segments[8 - tail.len()..].copy_from_slice(&tail);
+            }
+        }
+        Ok(IpV6Addr { segments })
+    }
+}
+
+impl FromStr for IpAddrWithTypes {
+    type Err = ParseIpError;
+
+    // only v6 addresses have colons in them
+    fn from_str(s: &str) -> Result<IpAddrWithTypes, ParseIpError> {
+        if s.contains(':') {
+            s.parse().map(IpAddrWithTypes::V6)
+        } else {
+            s.parse().map(IpAddrWithTypes::V4)
+        }
+    }
+}
+
+impl FromStr for IpAddrWithDiffTypes {
+    type Err = ParseIpError;
+
+    fn from_str(s: &str) -> Result<IpAddrWithDiffTypes, ParseIpError> {
+        Ok(s.parse::<IpAddrWithTypes>()?.into())
+    }
+}
+
+// Writing them back out. There's more than one way to write the same v6 address, so RFC 5952
+// picks one: lowercase hex without leading zeros, the longest run of two or more zero groups
+// (the first one if there's a tie) squashed to "::", and v4-mapped addresses end in a
+// dotted quad
+
+impl fmt::Display for IpV4Addr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [a, b, c, d] = self.octets;
+        write!(f, "{}.{}.{}.{}", a, b, c, d)
+    }
+}
+
+// where the longest run of zero groups starts and how long it is, if it's worth squashing
+fn longest_zero_run(segments: &[u16; 8]) -> Option<(usize, usize)> {
+    let mut best: Option<(usize, usize)> = None;
+    let mut i = 0;
+    while i < segments.len() {
+        let len = segments[i..].iter().take_while(|&&g| g == 0).count();
+        if len >= 2 && best.is_none_or(|(_, best_len)| len > best_len) {
+            best = Some((i, len));
+        }
+        i += len.max(1);
+    }
+    best
+}
+
+fn write_groups(f: &mut fmt::Formatter, groups: &[u16]) -> fmt::Result {
+    for (i, group) in groups.iter().enumerate() {
+        if i > 0 {
+            write!(f, ":")?;
+        }
+        write!(f, "{:x}", group)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for IpV6Addr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(v4) = self.to_ipv4_mapped() {
+            return write!(f, "::ffff:{}", v4);
+        }
+        match longest_zero_run(&self.segments) {
+            Some((start, len)) => {
+                write_groups(f, &self.segments[..start])?;
+                write!(f, "::")?;
+                write_groups(f, &self.segments[start + len..])
+            }
+            None => write_groups(f, &self.segments),
+        }
+    }
+}
+
+impl fmt::Display for IpAddrWithTypes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IpAddrWithTypes::V4(addr) => addr.fmt(f),
+            IpAddrWithTypes::V6(addr) => addr.fmt(f),
+        }
+    }
+}
+
+impl fmt::Display for IpAddrWithDiffTypes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        IpAddrWithTypes::from(*self).fmt(f)
+    }
+}
+
+// Converting between the two enums, and to and from the std::net types that sockets take
+
+impl From<IpAddrWithTypes> for IpAddrWithDiffTypes {
+    fn from(addr: IpAddrWithTypes) -> IpAddrWithDiffTypes {
+        match addr {
+            IpAddrWithTypes::V4(v4) => {
+                let [a, b, c, d] = v4.octets;
+                IpAddrWithDiffTypes::V4(a, b, c, d)
+            }
+            IpAddrWithTypes::V6(v6) => IpAddrWithDiffTypes::V6(v6),
+        }
+    }
+}
+
+impl From<IpAddrWithDiffTypes> for IpAddrWithTypes {
+    fn from(addr: IpAddrWithDiffTypes) -> IpAddrWithTypes {
+        match addr {
+            IpAddrWithDiffTypes::V4(a, b, c, d) => IpAddrWithTypes::V4(IpV4Addr::new(a, b, c, d)),
+            IpAddrWithDiffTypes::V6(v6) => IpAddrWithTypes::V6(v6),
+        }
+    }
+}
+
+impl From<Ipv4Addr> for IpV4Addr {
+    fn from(addr: Ipv4Addr) -> IpV4Addr {
+        IpV4Addr { octets: addr.octets() }
+    }
+}
+
+impl From<IpV4Addr> for Ipv4Addr {
+    fn from(addr: IpV4Addr) -> Ipv4Addr {
+        Ipv4Addr::from(addr.octets)
+    }
+}
+
+impl From<Ipv6Addr> for IpV6Addr {
+    fn from(addr: Ipv6Addr) -> IpV6Addr {
+        IpV6Addr { segments: addr.segments() }
+    }
+}
+
+impl From<IpV6Addr> for Ipv6Addr {
+    fn from(addr: IpV6Addr) -> Ipv6Addr {
+        Ipv6Addr::from(addr.segments)
+    }
+}
+
+impl From<StdIpAddr> for IpAddrWithTypes {
+    fn from(addr: StdIpAddr) -> IpAddrWithTypes {
+        match addr {
+            StdIpAddr::V4(v4) => IpAddrWithTypes::V4(v4.into()),
+            StdIpAddr::V6(v6) => IpAddrWithTypes::V6(v6.into()),
+        }
+    }
+}
+
+impl From<IpAddrWithTypes> for StdIpAddr {
+    fn from(addr: IpAddrWithTypes) -> StdIpAddr {
+        match addr {
+            IpAddrWithTypes::V4(v4) => StdIpAddr::V4(v4.into()),
+            IpAddrWithTypes::V6(v6) => StdIpAddr::V6(v6.into()),
+        }
+    }
+}
+
+impl From<StdIpAddr> for IpAddrWithDiffTypes {
+    fn from(addr: StdIpAddr) -> IpAddrWithDiffTypes {
+        IpAddrWithTypes::from(addr).into()
+    }
+}
+
+impl From<IpAddrWithDiffTypes> for StdIpAddr {
+    fn from(addr: IpAddrWithDiffTypes) -> StdIpAddr {
+        IpAddrWithTypes::from(addr).into()
+    }
+}
+
+// checks the parser and the RFC 5952 output against std::net, which does the same job
+pub fn parsing_addresses() {
+    let home: IpAddrWithTypes = "127.0.0.1".parse().unwrap();
+    assert_eq!(home, IpAddrWithTypes::V4(IpV4Addr::new(127, 0, 0, 1)));
+    assert_eq!("127.0.0.1".parse(), Ok(IpAddrWithDiffTypes::V4(127, 0, 0, 1)));
+
+    // each bad address gets told what's wrong with it
+    let bad = [
+        ("", ParseIpError::Empty),
+        ("999.1.1.1", ParseIpError::OctetOutOfRange("999".to_string())),
+        ("1.2.3", ParseIpError::WrongOctetCount(3)),
+        ("1.2.3.4.5", ParseIpError::WrongOctetCount(5)),
+        ("1..3.4", ParseIpError::BadOctet("".to_string())),
+        ("1.2.+3.4", ParseIpError::BadOctet("+3".to_string())),
+        ("01.2.3.4", ParseIpError::LeadingZero("01".to_string())),
+        ("1:2:3:4:5:6:7", ParseIpError::WrongGroupCount(7)),
+        ("1:2:3:4:5:6:7:8:9", ParseIpError::WrongGroupCount(9)),
+        ("1:2:3:4::5:6:7:8", ParseIpError::WrongGroupCount(8)),
+        ("1::2::3", ParseIpError::TooManyDoubleColons),
+        ("12345::", ParseIpError::BadGroup("12345".to_string())),
+        ("g::", ParseIpError::BadGroup("g".to_string())),
+        (":1::", ParseIpError::BadGroup("".to_string())),
+        ("1:::2", ParseIpError::BadGroup("".to_string())),
+        ("1.2.3.4::", ParseIpError::Ipv4NotAtEnd("1.2.3.4".to_string())),
+        ("::1.2.3.4:5", ParseIpError::Ipv4NotAtEnd("1.2.3.4".to_string())),
+        ("::ffff:1.2.3.256", ParseIpError::OctetOutOfRange("256".to_string())),
+        ("fe80::1%eth0", ParseIpError::BadGroup("1%eth0".to_string())),
+    ];
+    for (text, err) in bad {
+        assert_eq!(text.parse::<IpAddrWithTypes>(), Err(err), "{}", text);
+        assert!(text.parse::<StdIpAddr>().is_err(), "std accepts {}", text);
+    }
+    println!("999.1.1.1: {}", "999.1.1.1".parse::<IpAddrWithTypes>().unwrap_err());
+
+    // the good ones, in whatever form they're written, come out the same way std writes them
+    let good = [
+        ("0.0.0.0", "0.0.0.0"),
+        ("255.255.255.255", "255.255.255.255"),
+        ("::", "::"),
+        ("::1", "::1"),
+        ("1::", "1::"),
+        ("2001:DB8:0:0:0:0:0:1", "2001:db8::1"),
+        ("2001:0db8:0000:0000:0001:0000:0000:0001", "2001:db8::1:0:0:1"),
+        ("2001:db8:0:1:1:1:1:1", "2001:db8:0:1:1:1:1:1"),
+        ("2001:0:0:1:0:0:0:1", "2001:0:0:1::1"),
+        ("1:2:3:4:5:6:7::", "1:2:3:4:5:6:7:0"),
+        ("::ffff:192.0.2.1", "::ffff:192.0.2.1"),
+        ("0:0:0:0:0:ffff:c000:0201", "::ffff:192.0.2.1"),
+        ("64:ff9b::192.0.2.33", "64:ff9b::c000:221"),
+    ];
+    for (text, canonical) in good {
+        let mine: IpAddrWithTypes = text.parse().unwrap();
+        let theirs: StdIpAddr = text.parse().unwrap();
+        assert_eq!(mine.to_string(), canonical);
+        assert_eq!(mine.to_string(), theirs.to_string());
+        assert_eq!(StdIpAddr::from(mine), theirs);
+        assert_eq!(IpAddrWithTypes::from(theirs), mine);
+        assert_eq!(IpAddrWithDiffTypes::from(theirs).to_string(), canonical);
+    }
+
+    let mapped: IpV6Addr = "::ffff:10.0.0.1".parse().unwrap();
+    assert_eq!(mapped.to_ipv4_mapped(), Some(IpV4Addr::new(10, 0, 0, 1)));
+    assert_eq!(IpV4Addr::new(10, 0, 0, 1).to_ipv6_mapped(), mapped);
+}
+
+// Subnets. A CIDR block like 10.0.0.0/8 is every address whose first 8 bits match, so all
+// the maths is on the address as one number: the prefix length splits it into the network
+// bits on the left and the host bits on the right.
+
+impl From<IpV4Addr> for u32 {
+    fn from(addr: IpV4Addr) -> u32 {
+        u32::from_be_bytes(addr.octets)
+    }
+}
+
+impl From<u32> for IpV4Addr {
+    fn from(bits: u32) -> IpV4Addr {
+        IpV4Addr { octets: bits.to_be_bytes() }
+    }
+}
+
+impl From<IpV6Addr> for u128 {
+    fn from(addr: IpV6Addr) -> u128 {
+        addr.segments.iter().fold(0, |bits, &segment| bits << 16 | u128::from(segment))
+    }
+}
+
+impl From<u128> for IpV6Addr {
+    fn from(bits: u128) -> IpV6Addr {
+        let mut segments = [0; 8];
+        for (i, segment) in segments.iter_mut().enumerate() {
+            *segment = (bits >> (16 * (7 - i))) as u16;
+        }
+        IpV6Addr { segments }
+    }
+}
+
+impl IpAddrWithTypes {
+    // how many bits the address has, 32 or 128
+    pub fn width(&self) -> u8 {
+        match self {
+            IpAddrWithTypes::V4(_) => 32,
+            IpAddrWithTypes::V6(_) => 128,
+        }
+    }
+
+    // both kinds fit in a u128, a v4 address just uses the bottom 32 bits
+    pub fn bits(&self) -> u128 {
+        match *self {
+            IpAddrWithTypes::V4(v4) => u128::from(u32::from(v4)),
+            IpAddrWithTypes::V6(v6) => u128::from(v6),
+        }
+    }
+
+    // an address of the same kind as self with the given bits
+    pub fn with_bits(&self, bits: u128) -> IpAddrWithTypes {
+        match self {
+            IpAddrWithTypes::V4(_) => IpAddrWithTypes::V4(IpV4Addr::from(bits as u32)),
+            IpAddrWithTypes::V6(_) => IpAddrWithTypes::V6(IpV6Addr::from(bits)),
+        }
+    }
+
+    fn same_kind(&self, other: &IpAddrWithTypes) -> bool {
+        self.width() == other.width()
+    }
+}
+
+// the host part of an address with this prefix, all ones
+fn host_mask(width: u8, prefix: u8) -> u128 {
+    match width - prefix {
+        128 => u128::MAX,
+        host_bits => (1 << host_bits) - 1,
+    }
+}
+
+// 2^bits, which doesn't fit in a u128 when bits is 128
+fn power_of_two(bits: u8) -> BigUint {
+    match bits {
+        128 => BigUint::from(u128::MAX) + BigUint::one(),
+        _ => BigUint::from(1u128 << bits),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CidrError {
+    MissingPrefix,
+    BadAddress(ParseIpError),
+    BadPrefix(String),
+    PrefixTooLong { prefix: u8, max: u8 },
+    // split can only make the prefix longer, and no longer than the address
+    BadSplit { prefix: u8, new_prefix: u8, max: u8 },
+}
+
+impl fmt::Display for CidrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CidrError::MissingPrefix => write!(f, "expected <address>/<prefix length>"),
+            CidrError::BadAddress(e) => write!(f, "bad address: {}", e),
+            CidrError::BadPrefix(p) => write!(f, "'{}' is not a valid prefix length", p),
+            CidrError::PrefixTooLong { prefix, max } => write!(f, "prefix length {} is longer than {}", prefix, max),
+            CidrError::BadSplit { prefix, new_prefix, max } => {
+                write!(f, "can't split a /{} into /{}, the new prefix must be between {} and {}", prefix, new_prefix, prefix, max)
+            }
+        }
+    }
+}
+
+impl From<ParseIpError> for CidrError {
+    fn from(e: ParseIpError) -> CidrError {
+        CidrError::BadAddress(e)
+    }
+}
+
+// Only ever holds the network address, any host bits it was made with are cleared. The
+// derived ordering puts v4 blocks before v6, then sorts by address, then bigger blocks first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Cidr {
+    network: IpAddrWithTypes,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn new(addr: IpAddrWithTypes, prefix: u8) -> Result<Cidr, CidrError> {
+        let max = addr.width();
+        if prefix > max {
+            return Err(CidrError::PrefixTooLong { prefix, max });
+        }
+        let network = addr.with_bits(addr.bits() & !host_mask(max, prefix));
+        Ok(Cidr { network, prefix })
+    }
+
+    // a block of the same kind as self
+    fn block_at(&self, bits: u128, prefix: u8) -> Cidr {
+        Cidr { network: self.network.with_bits(bits), prefix }
+    }
+
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    pub fn network(&self) -> IpAddrWithTypes {
+        self.network
+    }
+
+    fn first_bits(&self) -> u128 {
+        self.network.bits()
+    }
+
+    fn last_bits(&self) -> u128 {
+        self.network.bits() | host_mask(self.network.width(), self.prefix)
+    }
+
+    pub fn last(&self) -> IpAddrWithTypes {
+        self.network.with_bits(self.last_bits())
+    }
+
+    // v6 doesn't have broadcast addresses, and a v4 /31 or /32 is too small to spare one
+    pub fn broadcast(&self) -> Option<IpAddrWithTypes> {
+        match self.network {
+            IpAddrWithTypes::V4(_) if self.prefix <= 30 => Some(self.last()),
+            _ => None,
+        }
+    }
+
+    pub fn netmask(&self) -> IpAddrWithTypes {
+        let width = self.network.width();
+        self.network.with_bits(host_mask(width, 0) & !host_mask(width, self.prefix))
+    }
+
+    pub fn address_count(&self) -> BigUint {
+        power_of_two(self.network.width() - self.prefix)
+    }
+
+    // the addresses that can be given to machines. A v4 block loses its network and broadcast
+    // addresses, except a /31 which is a point to point link with neither (RFC 3021)
+    pub fn host_count(&self) -> BigUint {
+        match self.network {
+            IpAddrWithTypes::V4(_) if self.prefix <= 30 => self.address_count() - BigUint::from(2u32),
+            _ => self.address_count(),
+        }
+    }
+
+    pub fn first_host(&self) -> IpAddrWithTypes {
+        match self.network {
+            IpAddrWithTypes::V4(_) if self.prefix <= 30 => self.network.with_bits(self.first_bits() + 1),
+            _ => self.network,
+        }
+    }
+
+    pub fn last_host(&self) -> IpAddrWithTypes {
+        match self.network {
+            IpAddrWithTypes::V4(_) if self.prefix <= 30 => self.network.with_bits(self.last_bits() - 1),
+            _ => self.last(),
+        }
+    }
+
+    pub fn contains(&self, addr: IpAddrWithTypes) -> bool {
+        self.network.same_kind(&addr) && (self.first_bits()..=self.last_bits()).contains(&addr.bits())
+    }
+
+    pub fn contains_cidr(&self, other: &Cidr) -> bool {
+        self.prefix <= other.prefix && self.contains(other.network)
+    }
+
+    // two blocks either don't overlap at all or one is inside the other
+    pub fn overlaps(&self, other: &Cidr) -> bool {
+        self.contains_cidr(other) || other.contains_cidr(self)
+    }
+
+    // the block one bit shorter that this one is half of
+    pub fn supernet(&self) -> Option<Cidr> {
+        match self.prefix {
+            0 => None,
+            prefix => Cidr::new(self.network, prefix - 1).ok(),
+        }
+    }
+
+    pub fn split(&self, new_prefix: u8) -> Result<Subnets, CidrError> {
+        let max = self.network.width();
+        if new_prefix < self.prefix || new_prefix > max {
+            return Err(CidrError::BadSplit { prefix: self.prefix, new_prefix, max });
+        }
+        let last = self.last_bits() & !host_mask(max, new_prefix);
+        Ok(Subnets { template: *self, next: Some(self.first_bits()), last, prefix: new_prefix })
+    }
+
+    // The fewest blocks that cover exactly the same addresses. Blocks inside other blocks are
+    // dropped, then neighbouring halves of the same supernet are merged, over and over, so
+    // 10.0.0.0/25 and 10.0.0.128/25 become 10.0.0.0/24.
+    pub fn aggregate(cidrs: &[Cidr]) -> Vec<Cidr> {
+        let mut sorted = cidrs.to_vec();
+        // bigger blocks sort first when they start at the same address
+        sorted.sort();
+        let mut merged: Vec<Cidr> = Vec::new();
+        for cidr in sorted {
+            if merged.last().is_some_and(|last| last.contains_cidr(&cidr)) {
+                continue;
+            }
+            merged.push(cidr);
+            // the new block might complete a pair with the one before it, and the merged
+            // block might then complete a pair with the one before that
+            while let [.., a, b] = merged.as_slice() {
+                match a.supernet() {
+                    Some(parent) if a.prefix == b.prefix && parent == b.supernet().unwrap() => {
+                        merged.truncate(merged.len() - 2);
+                        merged.push(parent);
+                    }
+                    _ => break,
+                }
+            }
+        }
+        merged
+    }
+
+    // Every pair that overlaps. After sorting, a block can only overlap the blocks before it
+    // that it's inside of, and those are all still on the stack when it comes along.
+    pub fn overlapping(cidrs: &[Cidr]) -> Vec<(Cidr, Cidr)> {
+        let mut sorted = cidrs.to_vec();
+        sorted.sort();
+        let mut open: Vec<Cidr> = Vec::new();
+        let mut pairs = Vec::new();
+        for cidr in sorted {
+            open.retain(|outer| outer.contains_cidr(&cidr));
+            pairs.extend(open.iter().map(|outer| (*outer, cidr)));
+            open.push(cidr);
+        }
+        pairs
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = CidrError;
+
+    fn from_str(s: &str) -> Result<Cidr, CidrError> {
+        let (addr, prefix) = s.split_once('/').ok_or(CidrError::MissingPrefix)?;
+        let addr: IpAddrWithTypes = addr.parse()?;
+        if prefix.is_empty() || !prefix.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(CidrError::BadPrefix(prefix.to_string()));
+        }
+        let prefix = prefix.parse().map_err(|_| CidrError::BadPrefix(prefix.to_string()))?;
+        Cidr::new(addr, prefix)
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+// the blocks a split makes, worked out one at a time since a /0 split into /128s has
+// more than any Vec could hold
+pub struct Subnets {
+    template: Cidr,
+    next: Option<u128>,
+    // where the last subnet starts
+    last: u128,
+    prefix: u8,
+}
+
+impl Iterator for Subnets {
+    type Item = Cidr;
+
+    fn next(&mut self) -> Option<Cidr> {
+        let bits = self.next?;
+        self.next = if bits == self.last {
+            None
+        } else {
+            Some(bits + host_mask(self.template.network.width(), self.prefix) + 1)
+        };
+        Some(self.template.block_at(bits, self.prefix))
+    }
+}
+
+const IP_USAGE: &str = "usage: learn_rust ip <cidr>
+       learn_rust ip <cidr> split <prefix>
+       learn_rust ip <cidr> contains <address|cidr>
+       learn_rust ip aggregate <cidr>...
+       learn_rust ip overlaps <cidr>...";
+
+// a split that makes more subnets than this only prints the first ones
+const MAX_SUBNETS_SHOWN: usize = 1024;
+
+fn parse_cidr(text: &str) -> Result<Cidr, String> {
+    text.parse().map_err(|e: CidrError| format!("{}: {}", text, e))
 }
 
+fn parse_cidrs(texts: &[&str]) -> Result<Vec<Cidr>, String> {
+    texts.iter().map(|text| parse_cidr(text)).collect()
+}
+
+// the ip subcommand. Returns Ok(false) when the answer is no, so contains and overlaps can be
+// used from a script through the exit code
+pub fn ip_command(args: &[String]) -> Result<bool, String> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["aggregate", cidrs @ ..] if !cidrs.is_empty() => {
+            for cidr in Cidr::aggregate(&parse_cidrs(cidrs)?) {
+                println!("{}", cidr);
+            }
+            Ok(true)
+        }
+        ["overlaps", cidrs @ ..] if !cidrs.is_empty() => {
+            let pairs = Cidr::overlapping(&parse_cidrs(cidrs)?);
+            for (outer, inner) in &pairs {
+                println!("{} overlaps {}", outer, inner);
+            }
+            Ok(pairs.is_empty())
+        }
+        [cidr] => {
+            let cidr = parse_cidr(cidr)?;
+            println!("network:   {}", cidr);
+            println!("netmask:   {}", cidr.netmask());
+            match cidr.broadcast() {
+                Some(broadcast) => println!("broadcast: {}", broadcast),
+                None => println!("broadcast: none"),
+            }
+            println!("addresses: {}", cidr.address_count());
+            println!("hosts:     {} - {} ({})", cidr.first_host(), cidr.last_host(), cidr.host_count());
+            Ok(true)
+        }
+        [cidr, "split", prefix] => {
+            let cidr = parse_cidr(cidr)?;
+            let prefix: u8 = prefix.parse().map_err(|_| format!("'{}' is not a valid prefix length", prefix))?;
+            let subnets = cidr.split(prefix).map_err(|e| e.to_string())?;
+            let mut shown = 0;
+            for subnet in subnets.take(MAX_SUBNETS_SHOWN) {
+                println!("{}", subnet);
+                shown += 1;
+            }
+            let total = power_of_two(prefix - cidr.prefix());
+            if BigUint::from(shown as u64) < total {
+                println!("... {} more", total - BigUint::from(shown as u64));
+            }
+            Ok(true)
+        }
+        [cidr, "contains", other] => {
+            let cidr = parse_cidr(cidr)?;
+            let inside = match other.parse::<IpAddrWithTypes>() {
+                Ok(addr) => cidr.contains(addr),
+                Err(_) => cidr.contains_cidr(&parse_cidr(other)?),
+            };
+            println!("{}", if inside { "yes" } else { "no" });
+            Ok(inside)
+        }
+        _ => Err(IP_USAGE.to_string()),
+    }
+}
+
+// checks the subnet maths on blocks we know the answers for
+pub fn subnet_maths() {
+    let net: Cidr = "192.168.1.77/24".parse().unwrap();
+    assert_eq!(net.to_string(), "192.168.1.0/24");
+    assert_eq!(net.netmask().to_string(), "255.255.255.0");
+    assert_eq!(net.broadcast().unwrap().to_string(), "192.168.1.255");
+    assert_eq!(net.host_count().to_string(), "254");
+    assert_eq!((net.first_host().to_string(), net.last_host().to_string()), ("192.168.1.1".to_string(), "192.168.1.254".to_string()));
+    assert!(net.contains("192.168.1.200".parse().unwrap()));
+    assert!(!net.contains("192.168.2.1".parse().unwrap()));
+    assert!(!net.contains("::ffff:192.168.1.1".parse().unwrap()));
+
+    let point_to_point: Cidr = "10.0.0.0/31".parse().unwrap();
+    assert_eq!(point_to_point.broadcast(), None);
+    assert_eq!(point_to_point.host_count().to_string(), "2");
+    let host: Cidr = "10.0.0.7/32".parse().unwrap();
+    assert_eq!(host.host_count().to_string(), "1");
+    assert_eq!(host.first_host(), host.last_host());
+
+    let v6: Cidr = "2001:db8::1/32".parse().unwrap();
+    assert_eq!(v6.to_string(), "2001:db8::/32");
+    assert_eq!(v6.netmask().to_string(), "ffff:ffff::");
+    assert_eq!(v6.broadcast(), None);
+    assert_eq!(v6.last().to_string(), "2001:db8:ffff:ffff:ffff:ffff:ffff:ffff");
+    assert_eq!(v6.address_count().to_string(), "79228162514264337593543950336");
+    let everything: Cidr = "::/0".parse().unwrap();
+    assert_eq!(everything.address_count().to_string(), "340282366920938463463374607431768211456");
+    assert_eq!(everything.supernet(), None);
+    assert!(everything.contains_cidr(&v6));
+
+    assert_eq!("10.0.0.0".parse::<Cidr>(), Err(CidrError::MissingPrefix));
+    assert_eq!("10.0.0.0/33".parse::<Cidr>(), Err(CidrError::PrefixTooLong { prefix: 33, max: 32 }));
+    assert_eq!("10.0.0.0/-1".parse::<Cidr>(), Err(CidrError::BadPrefix("-1".to_string())));
+    assert_eq!("10.0.0/8".parse::<Cidr>(), Err(CidrError::BadAddress(ParseIpError::WrongOctetCount(3))));
+
+    let split: Vec<String> = "10.0.0.0/8".parse::<Cidr>().unwrap().split(16).unwrap().map(|c| c.to_string()).collect();
+    assert_eq!(split.len(), 256);
+    assert_eq!((split[0].as_str(), split[255].as_str()), ("10.0.0.0/16", "10.255.0.0/16"));
+    assert_eq!(net.split(24).unwrap().collect::<Vec<_>>(), vec![net]);
+    assert_eq!(net.split(23).err(), Some(CidrError::BadSplit { prefix: 24, new_prefix: 23, max: 32 }));
+    let mut huge = everything.split(128).unwrap();
+    assert_eq!(huge.nth(1).unwrap().to_string(), "::1/128");
+    let halves: Vec<String> = everything.split(1).unwrap().map(|c| c.to_string()).collect();
+    assert_eq!(halves, vec!["::/1", "8000::/1"]);
+
+    let blocks: Vec<Cidr> = ["10.0.0.0/25", "10.0.0.128/25", "10.0.1.0/24", "10.0.0.5/32", "10.0.3.0/24", "::/1", "8000::/1"]
+        .iter()
+        .map(|c| c.parse().unwrap())
+        .collect();
+    let aggregated: Vec<String> = Cidr::aggregate(&blocks).iter().map(|c| c.to_string()).collect();
+    assert_eq!(aggregated, vec!["10.0.0.0/23", "10.0.3.0/24", "::/0"]);
+    let overlaps: Vec<(String, String)> = Cidr::overlapping(&blocks).iter().map(|(a, b)| (a.to_string(), b.to_string())).collect();
+    assert_eq!(overlaps, vec![("10.0.0.0/25".to_string(), "10.0.0.5/32".to_string())]);
+}
+
+// a more complex enum, it lives in message.rs since the box chapter uses it too, along with
+// ways of sending it as bytes or text
+pub use crate::message::Message;
+
 // this is similar to defining various kinds of structs, except they are all grouped
 // under the message type
 
@@ -115,4 +956,79 @@ fn using_message_method() {
 //     result
 // }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workout::Rng;
+
+    // lots of random v6 addresses. Most groups are zero, so there are plenty of runs to squash
+    #[test]
+    fn random_v6_addresses_match_std() {
+        let mut rng = Rng::new(5952);
+        for _ in 0..10_000 {
+            let mut segments = [0u16; 8];
+            for segment in segments.iter_mut() {
+                if rng.below(3) == 0 {
+                    *segment = rng.next_u64() as u16;
+                }
+            }
+            let mine = IpV6Addr::new(segments);
+            let theirs = Ipv6Addr::from(segments);
+            assert_eq!(mine.to_string(), theirs.to_string());
+            assert_eq!(mine.to_string().parse(), Ok(mine));
+            assert_eq!(IpV6Addr::from(theirs), mine);
+        }
+    }
+
+    // aggregate and overlapping against brute force on random blocks inside 10.0.0.0/24,
+    // small enough to check every address one by one
+    #[test]
+    fn prefixes_too_big_for_a_u8() {
+        assert_eq!("10.0.0.0/255".parse::<Cidr>(), Err(CidrError::PrefixTooLong { prefix: 255, max: 32 }));
+        assert_eq!("10.0.0.0/300".parse::<Cidr>(), Err(CidrError::BadPrefix("300".to_string())));
+        assert_eq!("::/99999999999999999999".parse::<Cidr>(), Err(CidrError::BadPrefix("99999999999999999999".to_string())));
+        assert_eq!("10.0.0.0/300".parse::<Cidr>().unwrap_err().to_string(), "'300' is not a valid prefix length");
+    }
+
+    #[test]
+    fn random_blocks_match_brute_force() {
+        let mut rng = Rng::new(4632);
+        let base = u32::from("10.0.0.0".parse::<IpV4Addr>().unwrap());
+        let covered = |cidrs: &[Cidr]| -> Vec<bool> {
+            (0..256).map(|i| cidrs.iter().any(|c| c.contains(IpAddrWithTypes::V4(IpV4Addr::from(base + i))))).collect()
+        };
+        for _ in 0..500 {
+            let cidrs: Vec<Cidr> = (0..rng.below(12))
+                .map(|_| {
+                    let addr = IpAddrWithTypes::V4(IpV4Addr::from(base + rng.below(256)));
+                    Cidr::new(addr, 24 + rng.below(9) as u8).unwrap()
+                })
+                .collect();
 
+            let aggregated = Cidr::aggregate(&cidrs);
+            assert_eq!(covered(&aggregated), covered(&cidrs));
+            // nothing left to merge or drop
+            for (i, a) in aggregated.iter().enumerate() {
+                for b in &aggregated[i + 1..] {
+                    assert!(!a.overlaps(b));
+                    assert!(!(a.prefix == b.prefix && a.supernet() == b.supernet()));
+                }
+            }
+
+            let mut expected = Vec::new();
+            let mut sorted = cidrs.clone();
+            sorted.sort();
+            for (i, a) in sorted.iter().enumerate() {
+                for b in &sorted[i + 1..] {
+                    if a.overlaps(b) {
+                        expected.push((*a, *b));
+                    }
+                }
+            }
+            let mut found = Cidr::overlapping(&cidrs);
+            found.sort();
+            expected.sort();
+            assert_eq!(found, expected);
+        }
+    }
+}
@@ -1,17 +1,315 @@
 use std::collections::HashMap;
 use std::collections::hash_map::RandomState;
+use std::time::{Duration, Instant};
 
-pub fn fib(n: u128, memo: &mut HashMap<u128, u128>) -> u128 {
+use crate::big_uint::BigUint;
+use crate::linear_recurrence::LinearRecurrence;
+
+// F(n) = F(n - 1) + F(n - 2) with F(0) = 0 and F(1) = 1, the same sequence as the fibonacci
+// preset of LinearRecurrence. A u128 runs out at F(187), so the result is a BigUint. Up to
+// there fib_exact stays on cheap u128 adds, past it fast doubling only needs log2(n) steps
+// and keeps nothing but the current pair of terms.
+pub fn fib(n: u128) -> BigUint {
+    if n <= LAST_U128_FIB {
+        fib_exact(n)
+    } else {
+        fib_fast_doubling(n)
+    }
+}
+
+// F(186) is the largest fibonacci number that fits in a u128
+const LAST_U128_FIB: u128 = 186;
+
+// u128 overflows after F(186), so past that point we need a BigUint.
+// Adding u128s is much cheaper than adding BigUints, so stay on them for as long as the
+// values fit and only promote once checked_add says the next term would overflow.
+pub fn fib_exact(n: u128) -> BigUint {
+    if n == 0 {
+        return BigUint::zero();
+    }
+    let (mut a, mut b) = (0u128, 1u128); // F(i - 1), F(i)
+    let mut i = 1;
+    while i < n {
+        match a.checked_add(b) {
+            Some(next) => {
+                a = b;
+                b = next;
+                i += 1;
+            }
+            None => break,
+        }
+    }
+    if i == n {
+        return BigUint::from(b);
+    }
+
+    let (mut a, mut b) = (BigUint::from(a), BigUint::from(b));
+    while i < n {
+        let next = &a + &b;
+        a = b;
+        b = next;
+        i += 1;
+    }
+    b
+}
+
+// the ways of computing F(n) we can choose between
+// naive and memoised recurse once per step, so large n will overflow the stack
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    Naive,
+    Memoised,
+    Iterative,
+    FastDoubling,
+    Matrix,
+}
+
+impl Strategy {
+    pub const ALL: [Strategy; 5] = [
+        Strategy::Naive,
+        Strategy::Memoised,
+        Strategy::Iterative,
+        Strategy::FastDoubling,
+        Strategy::Matrix,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Strategy::Naive => "naive",
+            Strategy::Memoised => "memoised",
+            Strategy::Iterative => "iterative",
+            Strategy::FastDoubling => "fast doubling",
+            Strategy::Matrix => "matrix",
+        }
+    }
+
+    // largest n the benchmark will try, naive is exponential and memoised recurses n deep
+    fn bench_limit(&self) -> u128 {
+        match self {
+            Strategy::Naive => 30,
+            Strategy::Memoised => 5_000,
+            _ => u128::MAX,
+        }
+    }
+}
+
+pub fn fib_with(n: u128, strategy: Strategy) -> BigUint {
+    match strategy {
+        Strategy::Naive => fib_naive(n),
+        Strategy::Memoised => fib_memoised(n, &mut HashMap::new()),
+        Strategy::Iterative => fib_exact(n),
+        Strategy::FastDoubling => fib_fast_doubling(n),
+        Strategy::Matrix => fib_matrix(n),
+    }
+}
+
+// straight from the definition, O(phi^n) calls
+pub fn fib_naive(n: u128) -> BigUint {
     match n {
-        1 | 2 => 1,
+        0 => BigUint::zero(),
+        1 => BigUint::one(),
+        _ => fib_naive(n - 1) + fib_naive(n - 2),
+    }
+}
+
+// the book's memoised recursion, with BigUint values so it doesn't overflow past F(186)
+pub fn fib_memoised(n: u128, memo: &mut HashMap<u128, BigUint>) -> BigUint {
+    match n {
+        0 => BigUint::zero(),
+        1 | 2 => BigUint::one(),
         _ => {
-            if memo.contains_key(&n) {
-                return memo[&n];
-            } else {
-                let result = fib(n - 1, memo) + fib(n - 2, memo);
-                memo.insert(n, result);
-                result
+            if let Some(result) = memo.get(&n) {
+                return result.clone();
+            }
+            let result = fib_memoised(n - 1, memo) + fib_memoised(n - 2, memo);
+            memo.insert(n, result.clone());
+            result
+        }
+    }
+}
+
+// fast doubling uses the identities
+//     F(2k)     = F(k) * (2 * F(k + 1) - F(k))
+//     F(2k + 1) = F(k)^2 + F(k + 1)^2
+// walking the bits of n from the top, each step doubles k and adds one if the bit is set,
+// so it only takes log2(n) steps
+pub fn fib_fast_doubling(n: u128) -> BigUint {
+    let (mut a, mut b) = (BigUint::zero(), BigUint::one()); // F(k), F(k + 1), k = 0
+    for bit in (0..128 - n.leading_zeros()).rev() {
+        let two_b = &b + &b;
+        let c = &a * &(&two_b - &a); // F(2k)
+        let d = &(&a * &a) + &(&b * &b); // F(2k + 1)
+        if (n >> bit) & 1 == 1 {
+            b = &c + &d;
+            a = d;
+        } else {
+            a = c;
+            b = d;
+        }
+    }
+    a
+}
+
+// the matrix [[1, 1], [1, 0]] raised to the nth power is [[F(n+1), F(n)], [F(n), F(n-1)]]
+// and can be computed by repeated squaring, fibonacci is just the simplest linear recurrence
+// so this is the general companion matrix method applied to the fibonacci preset
+pub fn fib_matrix(n: u128) -> BigUint {
+    LinearRecurrence::fibonacci().nth_matrix(n)
+}
+
+// F(n) mod m without ever computing F(n), using the same doubling identities as
+// fib_fast_doubling but reducing after every step, so the values stay below m.
+// m fits in a u64 so the product of two residues always fits in a u128.
+pub fn fib_mod(n: u128, m: u64) -> u64 {
+    assert!(m != 0, "attempt to calculate fibonacci with a modulus of zero");
+    let m = m as u128;
+    let (mut a, mut b) = (0u128, 1 % m); // F(k), F(k + 1) mod m
+    for bit in (0..128 - n.leading_zeros()).rev() {
+        let c = a * ((2 * b + m - a) % m) % m;
+        let d = (a * a % m + b * b % m) % m;
+        if (n >> bit) & 1 == 1 {
+            b = (c + d) % m;
+            a = d;
+        } else {
+            a = c;
+            b = d;
+        }
+    }
+    a as u64
+}
+
+// fibonacci numbers modulo m repeat, the length of the cycle is the pisano period.
+// e.g. mod 3: 0 1 1 2 0 2 2 1 | 0 1 1 ... has a period of 8
+// It's found by walking the sequence until the pair (0, 1) comes round again, which takes
+// at most 6m steps, so periods are cached in a memo, the way fib_memoised keeps its values.
+pub fn pisano_period(m: u64, memo: &mut HashMap<u128, u128>) -> u128 {
+    if let Some(period) = memo.get(&(m as u128)) {
+        return *period;
+    }
+    let mut sequence = fib_mod_iter(m);
+    let mut previous = sequence.next().unwrap();
+    let mut period = 0;
+    for (i, current) in sequence.enumerate() {
+        if i > 0 && previous == 0 && current == 1 % m {
+            period = i as u128;
+            break;
+        }
+        previous = current;
+    }
+    memo.insert(m as u128, period);
+    period
+}
+
+// an endless iterator over F(0), F(1), F(2), ... mod m
+pub struct FibMod {
+    current: u64,
+    next: u64,
+    m: u64,
+}
+
+pub fn fib_mod_iter(m: u64) -> FibMod {
+    assert!(m != 0, "attempt to calculate fibonacci with a modulus of zero");
+    FibMod { current: 0, next: 1 % m, m }
+}
+
+impl Iterator for FibMod {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        let value = self.current;
+        // widen so the addition can't overflow when m is close to u64::MAX
+        let following = ((self.current as u128 + self.next as u128) % self.m as u128) as u64;
+        self.current = self.next;
+        self.next = following;
+        Some(value)
+    }
+}
+
+// times every strategy against each n and prints a table, a '-' means the strategy was
+// skipped because n is past what it can sensibly handle
+pub fn bench(ns: &[u128]) {
+    print!("{:>10}", "n");
+    for strategy in Strategy::ALL.iter() {
+        print!(" {:>15}", strategy.name());
+    }
+    println!();
+
+    for &n in ns {
+        print!("{:>10}", n);
+        let mut expected: Option<BigUint> = None;
+        for strategy in Strategy::ALL.iter() {
+            if n > strategy.bench_limit() {
+                print!(" {:>15}", "-");
+                continue;
+            }
+            let start = Instant::now();
+            let result = fib_with(n, *strategy);
+            let elapsed = start.elapsed();
+            // every strategy should agree, otherwise the timings mean nothing
+            match &expected {
+                Some(e) => assert_eq!(*e, result, "{} disagrees at n = {}", strategy.name(), n),
+                None => expected = Some(result),
             }
+            print!(" {:>15}", format_duration(elapsed));
+        }
+        println!();
+    }
+}
+
+pub fn format_duration(d: Duration) -> String {
+    let nanos = d.as_nanos();
+    if nanos < 1_000 {
+        format!("{} ns", nanos)
+    } else if nanos < 1_000_000 {
+        format!("{:.1} us", nanos as f64 / 1_000.0)
+    } else if nanos < 1_000_000_000 {
+        format!("{:.1} ms", nanos as f64 / 1_000_000.0)
+    } else {
+        format!("{:.2} s", d.as_secs_f64())
+    }
+}
+
+pub fn this_main() {
+    println!("{}", fib(100));
+    // F(187) is the first fibonacci number too large for a u128
+    println!("{}", fib(187));
+    assert_eq!(fib(187).to_string(), "538522340430300790495419781092981030533");
+    let f = fib_exact(10_000);
+    println!("F(10000) has {} digits", f.to_string().len());
+
+    let mut periods = HashMap::new();
+    let sequence: Vec<u64> = fib_mod_iter(3).take(10).collect();
+    println!("fibonacci mod 3: {:?}, period {}", sequence, pisano_period(3, &mut periods));
+    println!("F(10^30) mod 1_000_000_007 = {}", fib_mod(10u128.pow(30), 1_000_000_007));
+
+    // fibonacci is one of a family of sequences defined by a linear recurrence
+    let presets = [
+        LinearRecurrence::fibonacci(),
+        LinearRecurrence::lucas(),
+        LinearRecurrence::tribonacci(),
+        LinearRecurrence::pell(),
+        LinearRecurrence::padovan(),
+    ];
+    for mut sequence in presets {
+        let first: Vec<String> = sequence.iter().take(10).map(|t| t.to_string()).collect();
+        assert_eq!(sequence.nth(50), sequence.nth_matrix(50));
+        assert_eq!(sequence.nth(50), sequence.nth_cached(50));
+        println!("{:<10} {} ... a(50) = {}", sequence.name(), first.join(", "), sequence.nth(50));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fib_either_side_of_u128() {
+        assert_eq!(fib(0), BigUint::zero());
+        assert_eq!(fib(1), BigUint::one());
+        assert_eq!(fib(186).to_string(), "332825110087067562321196029789634457848");
+        assert_eq!(fib(187).to_string(), "538522340430300790495419781092981030533");
+        for n in [100, 185, 186, 187, 188, 500] {
+            assert_eq!(fib(n), fib_matrix(n), "F({})", n);
         }
     }
-}
\ No newline at end of file
+}
@@ -72,3 +72,23 @@ enum Result<T, E> {
     Ok(T),
     Err(E),
 }
+
+pub fn this_main() {
+    let number_list = vec![34, 50, 25, 100, 65];
+    assert_eq!(largest_i32(&number_list), 100);
+    // the generic version works on the same list, and on anything else that can be
+    // compared and copied
+    assert_eq!(largest(&number_list), 100);
+    let char_list = vec!['y', 'm', 'a', 'q'];
+    println!("The largest char is {}", largest(&char_list));
+
+    let integer = Point { x: 5, y: 10 };
+    println!("p.x = {}", integer.x());
+    let float = Point { x: 1.0, y: 4.0 };
+    println!("p.y = {}", float.y()); // only there because this is a Point<f32>
+
+    let p1 = PointTwoDataTypes { x: 5, y: 10.4 };
+    let p2 = PointTwoDataTypes { x: "Hello", y: 'c' };
+    let p3 = p1.mixup(p2);
+    println!("p3.x = {}, p3.y = {}", p3.x, p3.y);
+}
@@ -0,0 +1,227 @@
+// A directed graph where each node holds Rc pointers to the nodes its edges lead to.
+// Unlike a tree, a graph can have cycles (a -> b -> a), and a cycle of Rcs is never freed
+// on its own. So the Graph itself owns every node, and when it's dropped it clears every
+// node's edges first, which breaks any cycles and lets the nodes go.
+
+use std::cell::RefCell;
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::rc::Rc;
+
+pub struct GraphNode<T> {
+    pub value: T,
+    edges: RefCell<Vec<Rc<GraphNode<T>>>>,
+}
+
+impl<T> GraphNode<T> {
+    pub fn neighbours(&self) -> Vec<Rc<GraphNode<T>>> {
+        self.edges.borrow().clone()
+    }
+}
+
+pub struct Graph<T> {
+    nodes: Vec<Rc<GraphNode<T>>>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum GraphError {
+    // the handle is for a node of another graph, or one that has been removed from this one.
+    // An edge to it would keep it alive without the graph knowing, and Drop would never
+    // clear its edges, so any cycle through it would leak
+    NotInGraph,
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GraphError::NotInGraph => write!(f, "the node isn't part of this graph"),
+        }
+    }
+}
+
+impl<T> Graph<T> {
+    pub fn new() -> Graph<T> {
+        Graph { nodes: Vec::new() }
+    }
+
+    pub fn add_node(&mut self, value: T) -> Rc<GraphNode<T>> {
+        let node = Rc::new(GraphNode { value, edges: RefCell::new(Vec::new()) });
+        self.nodes.push(Rc::clone(&node));
+        node
+    }
+
+    pub fn nodes(&self) -> &[Rc<GraphNode<T>>] {
+        &self.nodes
+    }
+
+    pub fn contains(&self, node: &Rc<GraphNode<T>>) -> bool {
+        self.nodes.iter().any(|n| Rc::ptr_eq(n, node))
+    }
+
+    // adding the same edge twice is a no-op
+    pub fn add_edge(&self, from: &Rc<GraphNode<T>>, to: &Rc<GraphNode<T>>) -> Result<(), GraphError> {
+        if !self.contains(from) || !self.contains(to) {
+            return Err(GraphError::NotInGraph);
+        }
+        let mut edges = from.edges.borrow_mut();
+        if !edges.iter().any(|n| Rc::ptr_eq(n, to)) {
+            edges.push(Rc::clone(to));
+        }
+        Ok(())
+    }
+
+    pub fn remove_edge(&self, from: &Rc<GraphNode<T>>, to: &Rc<GraphNode<T>>) -> bool {
+        let mut edges = from.edges.borrow_mut();
+        let before = edges.len();
+        edges.retain(|n| !Rc::ptr_eq(n, to));
+        edges.len() != before
+    }
+
+    // removes the node along with every edge into or out of it
+    pub fn remove_node(&mut self, node: &Rc<GraphNode<T>>) -> bool {
+        let before = self.nodes.len();
+        self.nodes.retain(|n| !Rc::ptr_eq(n, node));
+        if self.nodes.len() == before {
+            return false;
+        }
+        node.edges.borrow_mut().clear();
+        for other in &self.nodes {
+            other.edges.borrow_mut().retain(|n| !Rc::ptr_eq(n, node));
+        }
+        true
+    }
+
+    // the nodes reachable from start, in depth first order. Nodes are told apart by their
+    // address, so two nodes holding equal values are still different nodes
+    pub fn depth_first(&self, start: &Rc<GraphNode<T>>) -> Vec<Rc<GraphNode<T>>> {
+        let mut seen = HashSet::new();
+        let mut order = Vec::new();
+        let mut stack = vec![Rc::clone(start)];
+        while let Some(node) = stack.pop() {
+            if !seen.insert(Rc::as_ptr(&node)) {
+                continue;
+            }
+            stack.extend(node.edges.borrow().iter().rev().cloned());
+            order.push(node);
+        }
+        order
+    }
+
+    pub fn breadth_first(&self, start: &Rc<GraphNode<T>>) -> Vec<Rc<GraphNode<T>>> {
+        let mut seen = HashSet::new();
+        seen.insert(Rc::as_ptr(start));
+        let mut order = Vec::new();
+        let mut queue = VecDeque::from(vec![Rc::clone(start)]);
+        while let Some(node) = queue.pop_front() {
+            for next in node.edges.borrow().iter() {
+                if seen.insert(Rc::as_ptr(next)) {
+                    queue.push_back(Rc::clone(next));
+                }
+            }
+            order.push(node);
+        }
+        order
+    }
+}
+
+impl<T> Default for Graph<T> {
+    fn default() -> Graph<T> {
+        Graph::new()
+    }
+}
+
+impl<T> Drop for Graph<T> {
+    fn drop(&mut self) {
+        for node in &self.nodes {
+            node.edges.borrow_mut().clear();
+        }
+    }
+}
+
+fn values<T: Copy>(nodes: &[Rc<GraphNode<T>>]) -> Vec<T> {
+    nodes.iter().map(|n| n.value).collect()
+}
+
+// a -> b -> c -> a, and a -> d
+fn example_graph() -> (Graph<char>, [Rc<GraphNode<char>>; 4]) {
+    let mut graph = Graph::new();
+    let a = graph.add_node('a');
+    let b = graph.add_node('b');
+    let c = graph.add_node('c');
+    let d = graph.add_node('d');
+    graph.add_edge(&a, &b).unwrap();
+    graph.add_edge(&a, &d).unwrap();
+    graph.add_edge(&b, &c).unwrap();
+    graph.add_edge(&c, &a).unwrap();
+    graph.add_edge(&c, &a).unwrap();
+    (graph, [a, b, c, d])
+}
+
+pub fn this_main() {
+    let (mut graph, [a, b, c, d]) = example_graph();
+
+    assert_eq!(values(&graph.depth_first(&a)), vec!['a', 'b', 'c', 'd']);
+    assert_eq!(values(&graph.breadth_first(&a)), vec!['a', 'b', 'd', 'c']);
+    assert_eq!(values(&graph.depth_first(&c)), vec!['c', 'a', 'b', 'd']);
+    assert_eq!(values(&c.neighbours()), vec!['a']);
+
+    assert!(graph.remove_edge(&a, &d));
+    assert!(!graph.remove_edge(&a, &d));
+    assert_eq!(values(&graph.depth_first(&a)), vec!['a', 'b', 'c']);
+
+    assert!(graph.remove_node(&b));
+    assert_eq!(values(&graph.depth_first(&a)), vec!['a']);
+    assert_eq!(values(graph.nodes()), vec!['a', 'c', 'd']);
+    graph.add_edge(&a, &c).unwrap();
+
+    // b is gone, so it can't be joined back up
+    assert_eq!(graph.add_edge(&a, &b), Err(GraphError::NotInGraph));
+    assert_eq!(graph.add_edge(&b, &a), Err(GraphError::NotInGraph));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Weak;
+
+    // a -> b -> c -> a is a cycle, but dropping the graph breaks it, so once our own
+    // handles are gone nothing is left alive
+    #[test]
+    fn dropping_the_graph_frees_its_cycles() {
+        let (graph, handles) = example_graph();
+        let weak: Vec<Weak<GraphNode<char>>> = graph.nodes().iter().map(Rc::downgrade).collect();
+        drop(handles);
+        assert!(weak.iter().all(|w| w.upgrade().is_some()));
+        drop(graph);
+        assert!(weak.iter().all(|w| w.upgrade().is_none()));
+    }
+
+    // handles from another graph are turned away too, edges in either direction would tie
+    // the two graphs' lifetimes together
+    #[test]
+    fn edges_only_join_nodes_in_the_graph() {
+        let (graph, [a, ..]) = example_graph();
+        let mut other = Graph::new();
+        let stranger = other.add_node('z');
+        assert_eq!(graph.add_edge(&a, &stranger), Err(GraphError::NotInGraph));
+        assert_eq!(graph.add_edge(&stranger, &a), Err(GraphError::NotInGraph));
+        assert!(stranger.neighbours().is_empty());
+        assert_eq!(values(&a.neighbours()), vec!['b', 'd']);
+        assert_eq!(other.add_edge(&stranger, &stranger), Ok(()));
+    }
+
+    // a removed node loses its edges both ways, so our handle is the last thing keeping it
+    // alive, even though it was part of the cycle
+    #[test]
+    fn removed_nodes_are_freed() {
+        let (mut graph, [a, b, c, d]) = example_graph();
+        assert!(graph.remove_node(&b));
+        let removed = Rc::downgrade(&b);
+        drop(b);
+        assert!(removed.upgrade().is_none());
+        drop((a, c, d));
+        let weak: Vec<Weak<GraphNode<char>>> = graph.nodes().iter().map(Rc::downgrade).collect();
+        drop(graph);
+        assert!(weak.iter().all(|w| w.upgrade().is_none()));
+    }
+}
@@ -1,3 +1,7 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::iter::{FusedIterator, Sum};
+
 fn processing_some_numbers() {
     let v1 = vec![1, 2, 3];
     let v1_iter = v1.iter(); // responsible for iterating logic (when end, next)
@@ -23,6 +27,312 @@ pub trait StdIterator {
     // this code says implementing the iterator trait requires that you also define an
     // Item type, and this Item type is used in the return type of the next method
     // e.g. The Item type will be returned from the iterator
+
+    // everything below is a provided method, implementors only have to write next and get
+    // all of these for free. The adapters return a new struct wrapping self, which does
+    // nothing until something calls next on it, the consumers call next until None.
+    // The Self: Sized bounds are needed because they take self by value.
+
+    fn map<B, F>(self, f: F) -> Map<Self, F>
+        where Self: Sized, F: FnMut(Self::Item) -> B {
+        Map { iter: self, f }
+    }
+
+    fn filter<P>(self, predicate: P) -> Filter<Self, P>
+        where Self: Sized, P: FnMut(&Self::Item) -> bool {
+        Filter { iter: self, predicate }
+    }
+
+    fn zip<U>(self, other: U) -> Zip<Self, U>
+        where Self: Sized, U: StdIterator {
+        Zip { a: self, b: other }
+    }
+
+    fn skip(self, n: usize) -> Skip<Self> where Self: Sized {
+        Skip { iter: self, n }
+    }
+
+    fn take(self, n: usize) -> Take<Self> where Self: Sized {
+        Take { iter: self, n }
+    }
+
+    fn chain<U>(self, other: U) -> Chain<Self, U>
+        where Self: Sized, U: StdIterator<Item = Self::Item> {
+        Chain { a: Some(self), b: other }
+    }
+
+    fn enumerate(self) -> Enumerate<Self> where Self: Sized {
+        Enumerate { iter: self, count: 0 }
+    }
+
+    fn peekable(self) -> Peekable<Self> where Self: Sized {
+        Peekable { iter: self, peeked: None }
+    }
+
+    fn flat_map<U, F>(self, f: F) -> FlatMap<Self, U, F>
+        where Self: Sized, U: StdIterator, F: FnMut(Self::Item) -> U {
+        FlatMap { iter: self, f, inner: None }
+    }
+
+    fn fold<B, F>(mut self, init: B, mut f: F) -> B
+        where Self: Sized, F: FnMut(B, Self::Item) -> B {
+        let mut acc = init;
+        while let Some(x) = self.next() {
+            acc = f(acc, x);
+        }
+        acc
+    }
+
+    // std's Sum trait already knows how to add up every number type, so reuse it
+    fn sum<S>(self) -> S where Self: Sized, S: Sum<Self::Item> {
+        S::sum(self.into_std())
+    }
+
+    fn count(self) -> usize where Self: Sized {
+        self.fold(0, |count, _| count + 1)
+    }
+
+    // like std, the type to collect into is picked by annotation or turbofish
+    // let v: Vec<_> = iter.collect();
+    fn collect<B>(self) -> B where Self: Sized, B: FromStdIterator<Self::Item> {
+        B::from_std_iter(self)
+    }
+
+    // how many items are left, as (at least, at most). The default knows nothing, an
+    // iterator that knows better can say so, and into_std passes it on
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, None)
+    }
+
+    // bridges to the real Iterator trait, so std adapters and for loops can be used
+    fn into_std(self) -> IntoStd<Self> where Self: Sized {
+        IntoStd { iter: self }
+    }
+}
+
+// the counterpart of std's FromIterator, implemented by the collections collect can build
+pub trait FromStdIterator<A> {
+    fn from_std_iter<I: StdIterator<Item = A>>(iter: I) -> Self;
+}
+
+impl<A> FromStdIterator<A> for Vec<A> {
+    fn from_std_iter<I: StdIterator<Item = A>>(iter: I) -> Vec<A> {
+        iter.fold(Vec::new(), |mut v, x| {
+            v.push(x);
+            v
+        })
+    }
+}
+
+impl<K: Hash + Eq, V> FromStdIterator<(K, V)> for HashMap<K, V> {
+    fn from_std_iter<I: StdIterator<Item = (K, V)>>(iter: I) -> HashMap<K, V> {
+        iter.fold(HashMap::new(), |mut m, (k, v)| {
+            m.insert(k, v);
+            m
+        })
+    }
+}
+
+// the adapters, each one holds the iterator it wraps
+
+pub struct Map<I, F> {
+    iter: I,
+    f: F,
+}
+
+impl<B, I: StdIterator, F: FnMut(I::Item) -> B> StdIterator for Map<I, F> {
+    type Item = B;
+
+    fn next(&mut self) -> Option<B> {
+        self.iter.next().map(&mut self.f)
+    }
+}
+
+pub struct Filter<I, P> {
+    iter: I,
+    predicate: P,
+}
+
+impl<I: StdIterator, P: FnMut(&I::Item) -> bool> StdIterator for Filter<I, P> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        while let Some(x) = self.iter.next() {
+            if (self.predicate)(&x) {
+                return Some(x);
+            }
+        }
+        None
+    }
+}
+
+pub struct Zip<A, B> {
+    a: A,
+    b: B,
+}
+
+impl<A: StdIterator, B: StdIterator> StdIterator for Zip<A, B> {
+    type Item = (A::Item, B::Item);
+
+    // stops as soon as either side runs out
+    fn next(&mut self) -> Option<Self::Item> {
+        let x = self.a.next()?;
+        let y = self.b.next()?;
+        Some((x, y))
+    }
+}
+
+pub struct Skip<I> {
+    iter: I,
+    n: usize,
+}
+
+impl<I: StdIterator> StdIterator for Skip<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        while self.n > 0 {
+            self.n -= 1;
+            self.iter.next()?;
+        }
+        self.iter.next()
+    }
+}
+
+pub struct Take<I> {
+    iter: I,
+    n: usize,
+}
+
+impl<I: StdIterator> StdIterator for Take<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        if self.n == 0 {
+            return None;
+        }
+        self.n -= 1;
+        self.iter.next()
+    }
+}
+
+pub struct Chain<A, B> {
+    // set to None once the first iterator is used up, so it isn't called again
+    a: Option<A>,
+    b: B,
+}
+
+impl<A: StdIterator, B: StdIterator<Item = A::Item>> StdIterator for Chain<A, B> {
+    type Item = A::Item;
+
+    fn next(&mut self) -> Option<A::Item> {
+        if let Some(a) = &mut self.a {
+            match a.next() {
+                None => self.a = None,
+                x => return x,
+            }
+        }
+        self.b.next()
+    }
+}
+
+pub struct Enumerate<I> {
+    iter: I,
+    count: usize,
+}
+
+impl<I: StdIterator> StdIterator for Enumerate<I> {
+    type Item = (usize, I::Item);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let x = self.iter.next()?;
+        let i = self.count;
+        self.count += 1;
+        Some((i, x))
+    }
+}
+
+pub struct Peekable<I: StdIterator> {
+    iter: I,
+    // Some(None) means we've already peeked and seen the end
+    peeked: Option<Option<I::Item>>,
+}
+
+impl<I: StdIterator> Peekable<I> {
+    pub fn peek(&mut self) -> Option<&I::Item> {
+        let iter = &mut self.iter;
+        self.peeked.get_or_insert_with(|| iter.next()).as_ref()
+    }
+}
+
+impl<I: StdIterator> StdIterator for Peekable<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        match self.peeked.take() {
+            Some(x) => x,
+            None => self.iter.next(),
+        }
+    }
+}
+
+pub struct FlatMap<I, U, F> {
+    iter: I,
+    f: F,
+    // the iterator returned by f for the current outer item
+    inner: Option<U>,
+}
+
+impl<I: StdIterator, U: StdIterator, F: FnMut(I::Item) -> U> StdIterator for FlatMap<I, U, F> {
+    type Item = U::Item;
+
+    fn next(&mut self) -> Option<U::Item> {
+        loop {
+            if let Some(inner) = &mut self.inner {
+                if let Some(x) = inner.next() {
+                    return Some(x);
+                }
+            }
+            self.inner = Some((self.f)(self.iter.next()?));
+        }
+    }
+}
+
+// bridges in both directions
+
+// wraps a StdIterator so it can be used anywhere a real Iterator is expected
+pub struct IntoStd<I> {
+    iter: I,
+}
+
+impl<I: StdIterator> Iterator for IntoStd<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        self.iter.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+// wraps anything that can be turned into a real Iterator so it can be used as a
+// StdIterator, e.g. from_std(vec![1, 2, 3]).map(|x| x * 2)
+pub struct FromStd<I> {
+    iter: I,
+}
+
+pub fn from_std<I: IntoIterator>(iter: I) -> FromStd<I::IntoIter> {
+    FromStd { iter: iter.into_iter() }
+}
+
+impl<I: Iterator> StdIterator for FromStd<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        self.iter.next()
+    }
 }
 
 pub fn manually_call_iter() {
@@ -67,42 +377,116 @@ fn iterator_adapter() {
 
 // using closures that capture their environment
 
-#[derive(PartialEq, Debug)]
-struct Shoe {
-    size: u32,
-    style: String,
+#[derive(PartialEq, Debug, Clone)]
+pub struct Shoe {
+    pub size: u32,
+    pub style: String,
 }
 
 fn shoes_in_size(shoes: Vec<Shoe>, shoe_size: u32) -> Vec<Shoe> {
     // captures shoe size from argument, takes ownership of Vector, returns new one
     shoes.into_iter().filter(|s| s.size == shoe_size).collect()
 }
+// shoe_inventory.rs builds a searchable store on top of this, which borrows rather than
+// consuming the shoes
 
 // creating your own iterator
+// Counter::new() counts 1 to 5, Counter::with_range lets you pick the start, end and step.
+// Rather than stepping a current value (which could overflow at u32::MAX) it works out up
+// front how many values there are, and hands out start + i * step for each index i.
+// Keeping a front and back index also makes it cheap to iterate from either end.
 struct Counter {
-    count: u32,
+    start: u32,
+    step: i64,
+    // indices of the next value from the front, and one past the next value from the back
+    front: u64,
+    back: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RangeEnd {
+    Inclusive,
+    Exclusive,
 }
 
 impl Counter {
     fn new() -> Counter {
-        Counter { count: 0 }
+        Counter::with_range(1, 5, 1, RangeEnd::Inclusive)
+    }
+
+    // a negative step counts down from start towards end, a step of zero would never
+    // reach the end so it panics, just like std's step_by(0)
+    fn with_range(start: u32, end: u32, step: i64, range_end: RangeEnd) -> Counter {
+        assert!(step != 0, "Counter step must not be zero");
+        let (start_wide, end_wide) = (start as i64, end as i64);
+        // the furthest value we're allowed to reach, and how far away it is
+        let last = match range_end {
+            RangeEnd::Inclusive => end_wide,
+            RangeEnd::Exclusive => end_wide - step.signum(),
+        };
+        let distance = (last - start_wide) * step.signum();
+        let len = if distance < 0 {
+            0
+        } else {
+            (distance as u64) / step.unsigned_abs() + 1
+        };
+        Counter { start, step, front: 0, back: len }
+    }
+
+    fn value_at(&self, index: u64) -> u32 {
+        // always within start..=end, so the cast back can't truncate
+        (self.start as i128 + index as i128 * self.step as i128) as u32
+    }
+
+    // the back end of the counter, which StdIterator has no method for. It's what rev()
+    // uses once the counter is bridged to std, see below
+    fn next_back(&mut self) -> Option<u32> {
+        if self.front < self.back {
+            self.back -= 1;
+            Some(self.value_at(self.back))
+        } else {
+            None
+        }
     }
 }
 
-impl Iterator for Counter {
+// still on our own StdIterator, which gives Counter all the provided adapters above
+impl StdIterator for Counter {
     type Item = u32; // Associated type for the iterator
     // means iterator will return u32 value
 
     fn next(&mut self) -> Option<Self::Item> { // references iterator associated type
-        if self.count < 5 {
-            self.count += 1;
-            Some(self.count)
+        if self.front < self.back {
+            self.front += 1;
+            Some(self.value_at(self.front - 1))
         } else {
             None
         }
     }
+
+    // exact, which lets collect allocate the right size and ExactSizeIterator work
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = (self.back - self.front) as usize;
+        (remaining, Some(remaining))
+    }
 }
 
+// StdIterator only has next, so the extra std traits go on the std side of the bridge:
+// Counter::new().into_std() is a real Iterator that can also go backwards and knows its length
+
+// gives us rev(), and next_back()
+impl DoubleEndedIterator for IntoStd<Counter> {
+    fn next_back(&mut self) -> Option<u32> {
+        self.iter.next_back()
+    }
+}
+
+// len() comes from size_hint, which is exact
+impl ExactSizeIterator for IntoStd<Counter> {}
+
+// once next returns None it keeps returning None, so fuse() is free
+impl FusedIterator for IntoStd<Counter> {}
+
 // let mut counter = Counter :: new();
 // counter.next == Some(1);
 // counter.next == Some(2);
@@ -118,15 +502,191 @@ fn using_other_iterator_trait_methods() {
         .filter(|x| x % 3 == 0)
         .sum();
     // sum == 18
+
+    // once bridged to std, Counter is double ended and knows its length, so more of the std
+    // methods work
+    let counter = Counter::with_range(0, 20, 2, RangeEnd::Exclusive).into_std();
+    assert_eq!(counter.len(), 10);
+    let evens_backwards: Vec<u32> = counter.rev().step_by(3).collect();
+    // evens_backwards == [18, 12, 6, 0]
 }
 
+pub fn configurable_counter() {
+    using_other_iterator_trait_methods();
 
+    println!("{:?}", Counter::new().collect::<Vec<_>>());
+    // steps that don't land exactly on the end, and counting down
+    println!("{:?}", Counter::with_range(0, 10, 3, RangeEnd::Inclusive).collect::<Vec<_>>());
+    println!("{:?}", Counter::with_range(5, 1, -2, RangeEnd::Inclusive).collect::<Vec<_>>());
+    // bridged to std it can run backwards too
+    let counter = Counter::with_range(0, 20, 2, RangeEnd::Exclusive).into_std();
+    println!("{} evens, backwards {:?}", counter.len(), counter.rev().collect::<Vec<_>>());
+}
 
+// every adapter on StdIterator should behave exactly like its std counterpart, so each test
+// runs the same pipeline through both and compares
+#[cfg(test)]
+mod tests {
+    use super::*;
 
+    const V: [i32; 6] = [1, 2, 3, 4, 5, 6];
 
+    fn ours() -> FromStd<std::vec::IntoIter<i32>> {
+        from_std(V.to_vec())
+    }
 
+    fn std() -> std::vec::IntoIter<i32> {
+        V.to_vec().into_iter()
+    }
 
+    #[test]
+    fn map_and_filter_match_std() {
+        assert_eq!(ours().map(|x| x * 2).collect::<Vec<_>>(), std().map(|x| x * 2).collect::<Vec<_>>());
+        assert_eq!(
+            ours().filter(|x| x % 2 == 0).collect::<Vec<_>>(),
+            std().filter(|x| x % 2 == 0).collect::<Vec<_>>()
+        );
+    }
 
+    #[test]
+    fn zip_and_chain_match_std() {
+        assert_eq!(
+            ours().zip(from_std(vec!["a", "b"])).collect::<Vec<_>>(),
+            std().zip(vec!["a", "b"]).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            ours().chain(from_std(vec![7, 8])).collect::<Vec<_>>(),
+            std().chain(vec![7, 8]).collect::<Vec<_>>()
+        );
+    }
 
+    #[test]
+    fn skip_and_take_match_std() {
+        assert_eq!(ours().skip(4).collect::<Vec<_>>(), std().skip(4).collect::<Vec<_>>());
+        assert_eq!(ours().skip(10).collect::<Vec<_>>(), std().skip(10).collect::<Vec<_>>());
+        assert_eq!(ours().take(2).collect::<Vec<_>>(), std().take(2).collect::<Vec<_>>());
+        assert_eq!(ours().take(10).collect::<Vec<_>>(), std().take(10).collect::<Vec<_>>());
+    }
 
+    #[test]
+    fn enumerate_and_flat_map_match_std() {
+        assert_eq!(ours().enumerate().collect::<Vec<_>>(), std().enumerate().collect::<Vec<_>>());
+        assert_eq!(
+            ours().flat_map(|x| from_std(0..x)).collect::<Vec<_>>(),
+            std().flat_map(|x| 0..x).collect::<Vec<_>>()
+        );
+    }
 
+    #[test]
+    fn consumers_match_std() {
+        assert_eq!(ours().fold(0, |acc, x| acc * 10 + x), std().fold(0, |acc, x| acc * 10 + x));
+        assert_eq!(ours().sum::<i32>(), std().sum::<i32>());
+        assert_eq!(ours().count(), std().count());
+        assert_eq!(
+            ours().map(|x| (x, x * x)).collect::<HashMap<_, _>>(),
+            std().map(|x| (x, x * x)).collect::<HashMap<_, _>>()
+        );
+    }
+
+    #[test]
+    fn peekable_matches_std() {
+        let mut ours_peekable = ours().peekable();
+        let mut std_peekable = std().peekable();
+        assert_eq!(ours_peekable.peek(), std_peekable.peek());
+        assert_eq!(ours_peekable.peek(), std_peekable.peek());
+        assert_eq!(ours_peekable.next(), std_peekable.next());
+        assert_eq!(ours_peekable.next(), std_peekable.next());
+    }
+
+    #[test]
+    fn empty_iterators() {
+        assert_eq!(from_std(Vec::<i32>::new()).count(), 0);
+        assert_eq!(from_std(Vec::<i32>::new()).peekable().peek(), None);
+    }
+
+    #[test]
+    fn into_std_bridges_back() {
+        assert_eq!(ours().into_std().step_by(2).collect::<Vec<_>>(), std().step_by(2).collect::<Vec<_>>());
+    }
+
+    // the Counter pipeline from above, which runs on StdIterator
+    #[test]
+    fn counter_pipeline() {
+        let sum: u32 = Counter::new()
+            .zip(Counter::new().skip(1))
+            .map(|(a, b)| a * b)
+            .filter(|x| x % 3 == 0)
+            .sum();
+        assert_eq!(sum, 18);
+    }
+
+    #[test]
+    fn counter_defaults_and_std_bridge() {
+        assert_eq!(Counter::new().collect::<Vec<_>>(), vec![1, 2, 3, 4, 5]);
+        assert_eq!(Counter::new().into_std().rev().collect::<Vec<_>>(), vec![5, 4, 3, 2, 1]);
+        assert_eq!(Counter::new().into_std().len(), 5);
+        assert_eq!(
+            Counter::with_range(0, 20, 2, RangeEnd::Exclusive).into_std().rev().step_by(3).collect::<Vec<_>>(),
+            vec![18, 12, 6, 0]
+        );
+    }
+
+    // steps that don't land exactly on the end
+    #[test]
+    fn counter_uneven_steps() {
+        assert_eq!(Counter::with_range(0, 10, 3, RangeEnd::Inclusive).collect::<Vec<_>>(), vec![0, 3, 6, 9]);
+        assert_eq!(Counter::with_range(0, 9, 3, RangeEnd::Inclusive).collect::<Vec<_>>(), vec![0, 3, 6, 9]);
+        assert_eq!(Counter::with_range(0, 9, 3, RangeEnd::Exclusive).collect::<Vec<_>>(), vec![0, 3, 6]);
+    }
+
+    #[test]
+    fn counter_negative_steps() {
+        assert_eq!(Counter::with_range(5, 1, -2, RangeEnd::Inclusive).collect::<Vec<_>>(), vec![5, 3, 1]);
+        assert_eq!(Counter::with_range(5, 1, -2, RangeEnd::Exclusive).collect::<Vec<_>>(), vec![5, 3]);
+        assert_eq!(Counter::with_range(5, 0, -1, RangeEnd::Exclusive).into_std().len(), 5);
+        assert_eq!(Counter::with_range(5, 1, -2, RangeEnd::Inclusive).into_std().rev().collect::<Vec<_>>(), vec![1, 3, 5]);
+    }
+
+    // empty when the end is on the wrong side of the start
+    #[test]
+    fn counter_empty_ranges() {
+        assert_eq!(Counter::with_range(5, 1, 1, RangeEnd::Inclusive).into_std().len(), 0);
+        assert_eq!(Counter::with_range(1, 5, -1, RangeEnd::Inclusive).into_std().len(), 0);
+        assert_eq!(Counter::with_range(3, 3, 1, RangeEnd::Exclusive).into_std().len(), 0);
+        assert_eq!(Counter::with_range(3, 3, 1, RangeEnd::Inclusive).collect::<Vec<_>>(), vec![3]);
+    }
+
+    // right up against the bounds of u32 without overflowing
+    #[test]
+    fn counter_u32_bounds() {
+        let top: Vec<u32> = Counter::with_range(u32::MAX - 2, u32::MAX, 1, RangeEnd::Inclusive).collect();
+        assert_eq!(top, vec![u32::MAX - 2, u32::MAX - 1, u32::MAX]);
+        let bottom: Vec<u32> = Counter::with_range(2, 0, -1, RangeEnd::Inclusive).collect();
+        assert_eq!(bottom, vec![2, 1, 0]);
+        assert_eq!(Counter::with_range(0, u32::MAX, 1, RangeEnd::Inclusive).into_std().len(), 1 << 32);
+        let huge_step: Vec<u32> = Counter::with_range(0, u32::MAX, i64::MAX, RangeEnd::Inclusive).collect();
+        assert_eq!(huge_step, vec![0]);
+        assert_eq!(Counter::with_range(u32::MAX, 0, i64::MIN, RangeEnd::Inclusive).into_std().len(), 1);
+    }
+
+    // meeting in the middle from both ends, and staying finished afterwards
+    #[test]
+    fn counter_from_both_ends() {
+        let mut counter = Counter::with_range(1, 4, 1, RangeEnd::Inclusive).into_std();
+        assert_eq!(counter.next(), Some(1));
+        assert_eq!(counter.next_back(), Some(4));
+        assert_eq!(counter.size_hint(), (2, Some(2)));
+        assert_eq!(counter.next_back(), Some(3));
+        assert_eq!(counter.next(), Some(2));
+        assert_eq!(counter.next(), None);
+        assert_eq!(counter.next_back(), None);
+        assert_eq!(counter.next(), None);
+    }
+
+    // like step_by(0)
+    #[test]
+    #[should_panic(expected = "step must not be zero")]
+    fn counter_zero_step() {
+        Counter::with_range(0, 5, 0, RangeEnd::Inclusive);
+    }
+}
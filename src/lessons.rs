@@ -0,0 +1,210 @@
+// A registry of every chapter that can be run from the command line.
+// Each entry pairs a name with a short description and the function that runs it,
+// so main doesn't need to import every this_main under an alias.
+
+use crate::{
+    address_pool, closures, control_flow, data_types, enums, fib, functions, generics, graph,
+    hash_maps, iterators, lifetimes, ownership, panics, par_iter, r#box, rc_reference_counted,
+    recoverable_errors, routing_table, shoe_inventory, slice, strings, structs, traits, tree,
+    variables_and_mutability, vectors,
+};
+
+pub struct Lesson {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub entry: fn(),
+    // chapters that panic on purpose to show what a panic looks like
+    pub expects_panic: bool,
+}
+
+// In book order, run --all goes through them top to bottom. A lesson goes with the chapter
+// it practises. The bigger projects built on a chapter wait until the chapters they lean on
+// have been covered, so the routing table and address pool, which build on the enums
+// chapter's addresses with traits and generics, come after chapter 10.
+pub const LESSONS: &[Lesson] = &[
+    Lesson {
+        name: "variables_and_mutability",
+        description: "let, mut, constants and shadowing",
+        entry: variables_and_mutability::this_main,
+        expects_panic: false,
+    },
+    Lesson {
+        name: "data_types",
+        description: "scalar and compound types",
+        entry: data_types::this_main,
+        expects_panic: true,
+    },
+    Lesson {
+        name: "functions",
+        description: "parameters, statements and expressions",
+        entry: functions::this_main,
+        expects_panic: false,
+    },
+    Lesson {
+        name: "control_flow",
+        description: "if, loop, while and for",
+        entry: control_flow::this_main,
+        expects_panic: false,
+    },
+    Lesson {
+        name: "fib",
+        description: "memoised fibonacci",
+        entry: fib::this_main,
+        expects_panic: false,
+    },
+    Lesson {
+        name: "ownership",
+        description: "moves, clones, references and borrowing",
+        entry: ownership::this_main,
+        expects_panic: false,
+    },
+    Lesson {
+        name: "slice",
+        description: "string slices and first_word",
+        entry: slice::this_main,
+        expects_panic: false,
+    },
+    Lesson {
+        name: "structs",
+        description: "defining and instantiating structs",
+        entry: structs::this_main,
+        expects_panic: false,
+    },
+    Lesson {
+        name: "enums::parsing_addresses",
+        description: "checked IPv4/IPv6 parsing and RFC 5952 output, compared with std::net",
+        entry: enums::parsing_addresses,
+        expects_panic: false,
+    },
+    Lesson {
+        name: "enums::subnet_maths",
+        description: "CIDR blocks: masks, host counts, splitting, aggregation and overlaps",
+        entry: enums::subnet_maths,
+        expects_panic: false,
+    },
+    Lesson {
+        name: "vectors",
+        description: "storing lists of values with Vec<T>",
+        entry: vectors::this_main,
+        expects_panic: false,
+    },
+    Lesson {
+        name: "strings",
+        description: "String, &str and utf-8",
+        entry: strings::this_main,
+        expects_panic: false,
+    },
+    Lesson {
+        name: "hash_maps",
+        description: "storing keys with associated values",
+        entry: hash_maps::this_main,
+        expects_panic: false,
+    },
+    Lesson {
+        name: "panics",
+        description: "unrecoverable errors with panic!",
+        entry: panics::this_main,
+        expects_panic: true,
+    },
+    Lesson {
+        name: "recoverable_errors",
+        description: "Result, matching on errors and ?",
+        entry: recoverable_errors::this_main,
+        expects_panic: false,
+    },
+    Lesson {
+        name: "generics",
+        description: "generic functions, structs and methods",
+        entry: generics::this_main,
+        expects_panic: false,
+    },
+    Lesson {
+        name: "traits",
+        description: "defining and implementing shared behaviour",
+        entry: traits::using_trait,
+        expects_panic: false,
+    },
+    Lesson {
+        name: "lifetimes",
+        description: "lifetime annotations on functions and structs",
+        entry: lifetimes::this_main,
+        expects_panic: false,
+    },
+    Lesson {
+        name: "routing_table",
+        description: "longest prefix match routing on a binary trie, for v4 and v6",
+        entry: routing_table::this_main,
+        expects_panic: false,
+    },
+    Lesson {
+        name: "address_pool",
+        description: "DHCP style leases, renewals and reservations with a clock we control",
+        entry: address_pool::this_main,
+        expects_panic: false,
+    },
+    Lesson {
+        name: "closures",
+        description: "closures, capturing the environment and the Cacher",
+        entry: closures::this_main,
+        expects_panic: false,
+    },
+    Lesson {
+        name: "iterators::manually_call_iter",
+        description: "calling next on an iterator by hand",
+        entry: iterators::manually_call_iter,
+        expects_panic: false,
+    },
+    Lesson {
+        name: "iterators::configurable_counter",
+        description: "a double ended Counter with a configurable range and step",
+        entry: iterators::configurable_counter,
+        expects_panic: false,
+    },
+    Lesson {
+        name: "shoe_inventory",
+        description: "borrowing queries, text queries and csv over a shoe inventory",
+        entry: shoe_inventory::this_main,
+        expects_panic: false,
+    },
+    Lesson {
+        name: "par_iter",
+        description: "iterator pipelines split across a thread pool",
+        entry: par_iter::this_main,
+        expects_panic: false,
+    },
+    Lesson {
+        name: "box::cons_list",
+        description: "a generic cons list on Box<T> with a non-recursive drop",
+        entry: r#box::cons_list,
+        expects_panic: false,
+    },
+    Lesson {
+        name: "rc_reference_counted",
+        description: "sharing tails with Rc, Weak back references and reference cycles",
+        entry: rc_reference_counted::this_main,
+        expects_panic: false,
+    },
+    Lesson {
+        name: "tree",
+        description: "a tree with Rc children and Weak parents",
+        entry: tree::this_main,
+        expects_panic: false,
+    },
+    Lesson {
+        name: "graph",
+        description: "a directed graph with Rc edges that cleans up its cycles",
+        entry: graph::this_main,
+        expects_panic: false,
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Lesson> {
+    LESSONS.iter().find(|l| l.name == name)
+}
+
+pub fn list() {
+    let width = LESSONS.iter().map(|l| l.name.len()).max().unwrap_or(0);
+    for lesson in LESSONS {
+        println!("{:width$}  {}", lesson.name, lesson.description, width = width);
+    }
+}
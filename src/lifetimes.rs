@@ -182,6 +182,19 @@ fn longest_with_announcement<'a, T>(
     }
 }
 
-
-
-
+pub fn this_main() {
+    dangling_ref();
+    borrow_checker();
+    valid_lifetime();
+    use_longest();
+    use_longest_with_scope();
+    use_lifetime_struct();
+    static_lifetime();
+
+    assert_eq!(longest_one_lifetime("first", "second one"), "first");
+    let excerpt = ImportantExcerpt { part: "Call me Ishmael" };
+    assert_eq!(excerpt.level(), 3);
+    assert_eq!(excerpt.announce_and_return_part("here it is"), "Call me Ishmael");
+    assert_eq!(longest_with_announcement("abcd", "xyz", "comparing two strings"), "abcd");
+    println!();
+}
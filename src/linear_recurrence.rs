@@ -0,0 +1,261 @@
+// A linear recurrence defines each term as a weighted sum of the k terms before it
+//     a(n) = c1 * a(n - 1) + c2 * a(n - 2) + ... + ck * a(n - k)
+// along with the first k terms to start it off. Fibonacci is the k = 2 case with both
+// weights set to 1, but the same machinery gives Lucas, Pell, Tribonacci and so on.
+
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+
+use crate::big_uint::BigUint;
+
+// how many terms nth_cached keeps, the 10_000th fibonacci number alone is about 7000 bits
+const MAX_CACHED_TERMS: usize = 10_000;
+
+#[derive(Clone, Debug)]
+pub struct LinearRecurrence {
+    name: &'static str,
+    // coefficients[0] multiplies a(n - 1), coefficients[1] multiplies a(n - 2) and so on
+    coefficients: Vec<BigUint>,
+    initial: Vec<BigUint>,
+    // every term computed by nth_cached so far, starting from a(0)
+    cache: Vec<BigUint>,
+}
+
+impl LinearRecurrence {
+    pub fn new(name: &'static str, coefficients: &[u64], initial: &[u64]) -> LinearRecurrence {
+        assert!(!coefficients.is_empty(), "a recurrence needs at least one coefficient");
+        assert_eq!(
+            coefficients.len(),
+            initial.len(),
+            "a recurrence of order k needs exactly k initial terms"
+        );
+        let initial: Vec<BigUint> = initial.iter().map(|&t| BigUint::from(t)).collect();
+        LinearRecurrence {
+            name,
+            coefficients: coefficients.iter().map(|&c| BigUint::from(c)).collect(),
+            cache: initial.clone(),
+            initial,
+        }
+    }
+
+    // 0, 1, 1, 2, 3, 5, 8, ...
+    pub fn fibonacci() -> LinearRecurrence {
+        LinearRecurrence::new("fibonacci", &[1, 1], &[0, 1])
+    }
+
+    // 2, 1, 3, 4, 7, 11, 18, ...
+    pub fn lucas() -> LinearRecurrence {
+        LinearRecurrence::new("lucas", &[1, 1], &[2, 1])
+    }
+
+    // 0, 0, 1, 1, 2, 4, 7, 13, ...
+    pub fn tribonacci() -> LinearRecurrence {
+        LinearRecurrence::new("tribonacci", &[1, 1, 1], &[0, 0, 1])
+    }
+
+    // 0, 1, 2, 5, 12, 29, 70, ...
+    pub fn pell() -> LinearRecurrence {
+        LinearRecurrence::new("pell", &[2, 1], &[0, 1])
+    }
+
+    // 1, 1, 1, 2, 2, 3, 4, 5, 7, ... where a(n) = a(n - 2) + a(n - 3)
+    pub fn padovan() -> LinearRecurrence {
+        LinearRecurrence::new("padovan", &[0, 1, 1], &[1, 1, 1])
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn order(&self) -> usize {
+        self.coefficients.len()
+    }
+
+    // walks forward from the initial terms, keeping only the last k terms around
+    pub fn nth(&self, n: u128) -> BigUint {
+        self.iter().nth(steps(n)).unwrap()
+    }
+
+    pub fn iter(&self) -> Terms<'_> {
+        Terms {
+            coefficients: &self.coefficients,
+            window: self.initial.iter().cloned().collect(),
+        }
+    }
+
+    // Like nth, but keeps the terms it computes so later lookups below n are free. Only the
+    // first MAX_CACHED_TERMS are kept, the terms grow as they go so keeping all of them would
+    // take memory that grows with the square of n. Past the cap it carries on from the last
+    // cached terms without keeping any more.
+    pub fn nth_cached(&mut self, n: u128) -> BigUint {
+        let n = steps(n);
+        while self.cache.len() <= n.min(MAX_CACHED_TERMS - 1) {
+            let next = next_term(&self.coefficients, self.cache.iter().rev());
+            self.cache.push(next);
+        }
+        if n < self.cache.len() {
+            return self.cache[n].clone();
+        }
+        let mut window: VecDeque<BigUint> = self.cache[self.cache.len() - self.order()..].iter().cloned().collect();
+        for _ in self.cache.len()..=n {
+            let next = next_term(&self.coefficients, window.iter().rev());
+            window.push_back(next);
+            window.pop_front();
+        }
+        window.pop_back().unwrap()
+    }
+
+    pub fn cached_terms(&self) -> usize {
+        self.cache.len()
+    }
+
+    // frees the cached terms, back to just the initial ones
+    pub fn clear_cache(&mut self) {
+        self.cache.truncate(self.order());
+        self.cache.shrink_to_fit();
+    }
+
+    // The companion matrix has the coefficients along its top row and ones just below the
+    // diagonal, e.g. for fibonacci [[1, 1], [1, 0]]. Multiplying it by the column of the
+    // last k terms shifts the window along by one, so raising it to the power n - k + 1
+    // and applying it to the initial terms jumps straight to a(n) in O(k^3 log n).
+    pub fn nth_matrix(&self, n: u128) -> BigUint {
+        let k = self.order();
+        if n < k as u128 {
+            return self.initial[n as usize].clone();
+        }
+        let mut companion = Matrix::zero(k);
+        for (j, c) in self.coefficients.iter().enumerate() {
+            companion.cells[0][j] = c.clone();
+        }
+        for i in 1..k {
+            companion.cells[i][i - 1] = BigUint::one();
+        }
+        let power = companion.pow(n - k as u128 + 1);
+        // the state column is [a(k - 1), ..., a(1), a(0)]
+        power.cells[0]
+            .iter()
+            .zip(self.initial.iter().rev())
+            .fold(BigUint::zero(), |acc, (p, t)| &acc + &(p * t))
+    }
+}
+
+// nth and nth_cached take one step per term, so n has to fit in a usize. Far past that
+// nth_matrix is the only one that would ever finish anyway.
+fn steps(n: u128) -> usize {
+    match usize::try_from(n) {
+        Ok(n) => n,
+        Err(_) => panic!("a({}) is too far along to walk to, use nth_matrix", n),
+    }
+}
+
+// previous yields the most recent term first
+fn next_term<'a>(
+    coefficients: &[BigUint],
+    previous: impl Iterator<Item = &'a BigUint>,
+) -> BigUint {
+    coefficients
+        .iter()
+        .zip(previous)
+        .fold(BigUint::zero(), |acc, (c, t)| &acc + &(c * t))
+}
+
+// an endless iterator over a(0), a(1), a(2), ...
+pub struct Terms<'a> {
+    coefficients: &'a [BigUint],
+    // the next k terms to hand out, oldest at the front
+    window: VecDeque<BigUint>,
+}
+
+impl Iterator for Terms<'_> {
+    type Item = BigUint;
+
+    fn next(&mut self) -> Option<BigUint> {
+        let following = next_term(self.coefficients, self.window.iter().rev());
+        self.window.push_back(following);
+        self.window.pop_front()
+    }
+}
+
+// a square matrix of BigUints, only as much as nth_matrix needs
+#[derive(Clone)]
+struct Matrix {
+    cells: Vec<Vec<BigUint>>,
+}
+
+impl Matrix {
+    fn zero(size: usize) -> Matrix {
+        Matrix { cells: vec![vec![BigUint::zero(); size]; size] }
+    }
+
+    fn identity(size: usize) -> Matrix {
+        let mut m = Matrix::zero(size);
+        for i in 0..size {
+            m.cells[i][i] = BigUint::one();
+        }
+        m
+    }
+
+    fn mul(&self, other: &Matrix) -> Matrix {
+        let size = self.cells.len();
+        let mut result = Matrix::zero(size);
+        for i in 0..size {
+            for j in 0..size {
+                result.cells[i][j] = (0..size)
+                    .filter(|&x| !self.cells[i][x].is_zero() && !other.cells[x][j].is_zero())
+                    .fold(BigUint::zero(), |acc, x| &acc + &(&self.cells[i][x] * &other.cells[x][j]));
+            }
+        }
+        result
+    }
+
+    // exponentiation by squaring
+    fn pow(&self, mut exp: u128) -> Matrix {
+        let mut result = Matrix::identity(self.cells.len());
+        let mut base = self.clone();
+        while exp > 0 {
+            if exp & 1 == 1 {
+                result = result.mul(&base);
+            }
+            exp >>= 1;
+            if exp > 0 {
+                base = base.mul(&base);
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nth_cached_matches_nth() {
+        let mut pell = LinearRecurrence::pell();
+        for n in [0, 1, 2, 10, 100, 7, 0] {
+            assert_eq!(pell.nth_cached(n), pell.nth(n));
+        }
+        assert_eq!(pell.cached_terms(), 101);
+    }
+
+    #[test]
+    fn nth_cached_stops_caching_at_the_cap() {
+        let mut tribonacci = LinearRecurrence::tribonacci();
+        let n = MAX_CACHED_TERMS as u128 + 5;
+        assert_eq!(tribonacci.nth_cached(n), tribonacci.nth(n));
+        assert_eq!(tribonacci.nth_cached(n - 1), tribonacci.nth(n - 1));
+        assert_eq!(tribonacci.cached_terms(), MAX_CACHED_TERMS);
+        assert_eq!(tribonacci.nth_cached(n), tribonacci.nth_matrix(n));
+    }
+
+    #[test]
+    fn clear_cache_keeps_the_initial_terms() {
+        let mut lucas = LinearRecurrence::lucas();
+        let before = lucas.nth_cached(50);
+        lucas.clear_cache();
+        assert_eq!(lucas.cached_terms(), 2);
+        assert_eq!(lucas.nth_cached(1), BigUint::from(1u32));
+        assert_eq!(lucas.nth_cached(50), before);
+    }
+}
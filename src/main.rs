@@ -1,26 +1,38 @@
-#![allow(unused_imports, dead_code, unused_variables, unused_assignments)]
-
-use crate::variables_and_mutability::this_main as v_and_m_main;
-use crate::data_types::this_main as data_types_main;
-use crate::functions::this_main as functions_main;
-use crate::control_flow::this_main as control_flow_main;
-use crate::fib::fib;
-use crate::ownership::this_main as ownership_main;
-use crate::slice::this_main as slice_main;
-use crate::structs::this_main as structs_main;
-use crate::vectors::this_main as vectors_main;
-use crate::iterators::manually_call_iter;
+#![allow(unused_imports, dead_code, unused_variables, unused_assignments, unconditional_panic)]
+// the chapters deliberately show the long-hand way of doing things before the idiomatic one
+#![allow(
+    clippy::out_of_bounds_indexing,
+    clippy::manual_map,
+    clippy::needless_return,
+    clippy::no_effect,
+    clippy::ptr_arg,
+    clippy::question_mark,
+    clippy::redundant_slicing,
+    clippy::single_match,
+    clippy::useless_conversion,
+    clippy::useless_vec,
+    clippy::vec_init_then_push
+)]
+
+use std::env;
+use std::fs;
+use std::process;
 
 mod variables_and_mutability;
 mod data_types;
 mod functions;
 mod control_flow;
 mod fib;
+mod big_uint;
+mod linear_recurrence;
 mod ownership;
 mod slice;
 mod structs;
 mod struct_example;
 mod enums;
+mod message;
+mod routing_table;
+mod address_pool;
 mod options;
 mod match_control_flow;
 mod module_system;
@@ -34,23 +46,127 @@ mod traits;
 mod lifetimes;
 mod testing;
 mod closures;
+mod cacher;
+mod persistent_cacher;
+mod workout;
 mod iterators;
+mod shoe_inventory;
+mod thread_pool;
+mod par_iter;
 mod cargo;
 mod smart_pointers;
 mod r#box;
 mod deref;
+mod my_rc;
 mod drop;
 mod rc_reference_counted;
+mod tree;
+mod graph;
+mod lessons;
+mod runner;
+
+const USAGE: &str = "usage: learn_rust list
+       learn_rust run [--format text|json] [--out <file>] (--all | <chapter>...)
+       learn_rust fib-bench [n...]
+       learn_rust ip <cidr> [split <prefix> | contains <address|cidr>]
+       learn_rust ip (aggregate | overlaps) <cidr>...
+       learn_rust route <file> <address>...
+       learn_rust route-bench [routes...]
+       learn_rust workout <command>...";
 
 fn main() {
-    // v_and_m_main();
-    // data_types_main();
-    // functions_main();
-    // control_flow_main();
-    // print!("{}", fib(100, &mut HashMap::new()));
-    // ownership_main();
-    // slice_main();
-    // structs_main();
-    // vectors_main();
-    manually_call_iter();
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("list") if args.len() == 1 => {
+            lessons::list();
+            Ok(true)
+        }
+        Some("run") => run_command(&args[1..]),
+        Some("fib-bench") => fib_bench_command(&args[1..]),
+        Some("workout") => workout::command(&args[1..]).map(|()| true),
+        Some("ip") => enums::ip_command(&args[1..]),
+        Some("route") => routing_table::command(&args[1..]),
+        Some("route-bench") => route_bench_command(&args[1..]),
+        _ => Err(String::from(USAGE)),
+    };
+    match result {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(message) => {
+            eprintln!("{}", message);
+            process::exit(2);
+        }
+    }
+}
+
+fn run_command(args: &[String]) -> Result<bool, String> {
+    let mut format = runner::Format::Text;
+    let mut out = None;
+    let mut all = false;
+    let mut chosen = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--all" => all = true,
+            "--format" => {
+                format = match args.next().map(String::as_str) {
+                    Some("text") => runner::Format::Text,
+                    Some("json") => runner::Format::Json,
+                    _ => return Err(String::from("--format expects 'text' or 'json'")),
+                }
+            }
+            "--out" => match args.next() {
+                Some(path) => out = Some(path),
+                None => return Err(String::from("--out expects a file name")),
+            },
+            name => match lessons::find(name) {
+                Some(lesson) => chosen.push(lesson),
+                None => return Err(format!("unknown chapter '{}', try `learn_rust list`", name)),
+            },
+        }
+    }
+    if all {
+        chosen = lessons::LESSONS.iter().collect();
+    }
+    if chosen.is_empty() {
+        return Err(String::from(USAGE));
+    }
+    // the chapters println! as they run, so on stdout the JSON would be mixed in with them
+    if let (runner::Format::Json, None) = (&format, out) {
+        return Err(String::from("--format json needs --out <file>, the chapters print to stdout as they run"));
+    }
+
+    let report = runner::run(&chosen);
+    let rendered = report.render(&format);
+    match out {
+        Some(path) => fs::write(path, rendered + "\n")
+            .map_err(|e| format!("could not write report to {}: {}", path, e))?,
+        None => println!("{}", rendered),
+    }
+    Ok(report.is_ok())
+}
+
+fn fib_bench_command(args: &[String]) -> Result<bool, String> {
+    let ns = if args.is_empty() {
+        vec![10, 20, 30, 100, 1_000, 5_000, 10_000, 100_000]
+    } else {
+        args.iter()
+            .map(|a| a.parse().map_err(|_| format!("'{}' is not a valid n", a)))
+            .collect::<Result<Vec<u128>, String>>()?
+    };
+    fib::bench(&ns);
+    Ok(true)
+}
+
+fn route_bench_command(args: &[String]) -> Result<bool, String> {
+    let sizes = if args.is_empty() {
+        vec![1_000, 10_000, 100_000]
+    } else {
+        args.iter()
+            .map(|a| a.parse().map_err(|_| format!("'{}' is not a valid number of routes", a)))
+            .collect::<Result<Vec<usize>, String>>()?
+    };
+    routing_table::bench(&sizes);
+    Ok(true)
 }
@@ -0,0 +1,442 @@
+// The Message enum from the enums and box chapters, shared by both, and two ways of sending
+// it somewhere else: a binary frame for programs and a line of text for people.
+//
+// A binary frame is a 4 byte big endian length, then that many bytes: a one byte tag saying
+// which variant it is, then the variant's fields.
+//
+//   tag 0  Quit
+//   tag 1  Move          x, y as 4 byte big endian signed numbers
+//   tag 2  Write         the text as UTF-8, up to the end of the frame
+//   tag 3  ChangeColour  three 4 byte big endian signed numbers
+//
+// The length comes first so a reader knows how much to wait for, data off a socket turns up
+// in whatever size pieces it likes.
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Quit,
+    // no associated data
+    Move { x: i32, y: i32 },
+    // includes an anonymous struct
+    Write(String),
+    // includes a single string
+    ChangeColour(i32, i32, i32), // includes three i32 values
+}
+
+const QUIT: u8 = 0;
+const MOVE: u8 = 1;
+const WRITE: u8 = 2;
+const CHANGE_COLOUR: u8 = 3;
+
+// the most a frame can say it holds, so a bad or hostile length can't make the reader
+// wait for, or allocate, gigabytes
+pub const MAX_FRAME_LEN: usize = 64 * 1024;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FrameError {
+    // a frame needs at least the tag
+    Empty,
+    Oversized { len: usize, max: usize },
+    UnknownTag(u8),
+    // the tag was fine but what came after it wasn't
+    BadPayload { tag: u8, reason: String },
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FrameError::Empty => write!(f, "frame has no tag"),
+            FrameError::Oversized { len, max } => write!(f, "frame of {} bytes is over the limit of {}", len, max),
+            FrameError::UnknownTag(tag) => write!(f, "unknown message tag {}", tag),
+            FrameError::BadPayload { tag, reason } => write!(f, "bad payload for tag {}: {}", tag, reason),
+        }
+    }
+}
+
+impl Message {
+    pub fn to_frame(&self) -> Result<Vec<u8>, FrameError> {
+        let mut body = Vec::new();
+        match self {
+            Message::Quit => body.push(QUIT),
+            Message::Move { x, y } => {
+                body.push(MOVE);
+                body.extend_from_slice(&x.to_be_bytes());
+                body.extend_from_slice(&y.to_be_bytes());
+            }
+            Message::Write(text) => {
+                body.push(WRITE);
+                body.extend_from_slice(text.as_bytes());
+            }
+            Message::ChangeColour(r, g, b) => {
+                body.push(CHANGE_COLOUR);
+                for colour in [r, g, b] {
+                    body.extend_from_slice(&colour.to_be_bytes());
+                }
+            }
+        }
+        // better to refuse now than send something the other end will throw away
+        if body.len() > MAX_FRAME_LEN {
+            return Err(FrameError::Oversized { len: body.len(), max: MAX_FRAME_LEN });
+        }
+        let mut frame = (body.len() as u32).to_be_bytes().to_vec();
+        frame.extend(body);
+        Ok(frame)
+    }
+
+    // the part of a frame after the length
+    fn from_body(body: &[u8]) -> Result<Message, FrameError> {
+        let (&tag, fields) = body.split_first().ok_or(FrameError::Empty)?;
+        let numbers = |count: usize| -> Result<Vec<i32>, FrameError> {
+            if fields.len() != count * 4 {
+                let reason = format!("expected {} bytes, found {}", count * 4, fields.len());
+                return Err(FrameError::BadPayload { tag, reason });
+            }
+            Ok(fields.chunks(4).map(|c| i32::from_be_bytes([c[0], c[1], c[2], c[3]])).collect())
+        };
+        match tag {
+            QUIT => numbers(0).map(|_| Message::Quit),
+            MOVE => numbers(2).map(|n| Message::Move { x: n[0], y: n[1] }),
+            WRITE => match String::from_utf8(fields.to_vec()) {
+                Ok(text) => Ok(Message::Write(text)),
+                Err(e) => Err(FrameError::BadPayload { tag, reason: e.to_string() }),
+            },
+            CHANGE_COLOUR => numbers(3).map(|n| Message::ChangeColour(n[0], n[1], n[2])),
+            _ => Err(FrameError::UnknownTag(tag)),
+        }
+    }
+}
+
+// Turns bytes into messages however they arrive: half a frame, several at once, one byte
+// at a time. Push whatever was read, then call next_message until it returns Ok(None).
+//
+// A frame with a bad tag or payload is dropped whole and reported, and the next one is read
+// as normal. So is an oversized frame, except its bytes are thrown away as they come rather
+// than being kept.
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+    max_len: usize,
+    // how much of an oversized frame is still to be thrown away
+    skipping: usize,
+}
+
+impl FrameDecoder {
+    pub fn new() -> FrameDecoder {
+        FrameDecoder::with_max_len(MAX_FRAME_LEN)
+    }
+
+    pub fn with_max_len(max_len: usize) -> FrameDecoder {
+        FrameDecoder { buffer: Vec::new(), max_len, skipping: 0 }
+    }
+
+    pub fn push(&mut self, mut bytes: &[u8]) {
+        let skipped = self.skipping.min(bytes.len());
+        self.skipping -= skipped;
+        bytes = &bytes[skipped..];
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    pub fn next_message(&mut self) -> Result<Option<Message>, FrameError> {
+        if self.buffer.len() < 4 {
+            return Ok(None);
+        }
+        let len = u32::from_be_bytes([self.buffer[0], self.buffer[1], self.buffer[2], self.buffer[3]]) as usize;
+        if len > self.max_len {
+            // drop what's here of it, and the rest as push sees it
+            let here = self.buffer.len().min(4 + len);
+            self.skipping = 4 + len - here;
+            self.buffer.drain(..here);
+            return Err(FrameError::Oversized { len, max: self.max_len });
+        }
+        if self.buffer.len() < 4 + len {
+            return Ok(None);
+        }
+        let frame: Vec<u8> = self.buffer.drain(..4 + len).collect();
+        Message::from_body(&frame[4..]).map(Some)
+    }
+
+    // bytes of a frame that hasn't finished arriving
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+}
+
+// The text format, one message per line, for typing in or reading in a log:
+//   QUIT
+//   MOVE 3 4
+//   WRITE hello there
+//   CHANGE_COLOUR 255 0 128
+// Everything after "WRITE " is the text, with backslashes, newlines and carriage returns
+// escaped as \\, \n and \r so it stays on one line. Commands can be in any case.
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseMessageError {
+    Empty,
+    UnknownCommand(String),
+    WrongArgCount { command: &'static str, expected: usize, found: usize },
+    BadNumber(String),
+    BadEscape(String),
+}
+
+impl fmt::Display for ParseMessageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseMessageError::Empty => write!(f, "empty line"),
+            ParseMessageError::UnknownCommand(c) => write!(f, "unknown command '{}'", c),
+            ParseMessageError::WrongArgCount { command, expected, found } => {
+                write!(f, "{} takes {} numbers, found {}", command, expected, found)
+            }
+            ParseMessageError::BadNumber(n) => write!(f, "'{}' is not a number", n),
+            ParseMessageError::BadEscape(e) => write!(f, "unknown escape '{}'", e),
+        }
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(text: &str) -> Result<String, ParseMessageError> {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => unescaped.push('\\'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(other) => return Err(ParseMessageError::BadEscape(format!("\\{}", other))),
+            None => return Err(ParseMessageError::BadEscape(String::from("\\"))),
+        }
+    }
+    Ok(unescaped)
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Message::Quit => write!(f, "QUIT"),
+            Message::Move { x, y } => write!(f, "MOVE {} {}", x, y),
+            // no trailing space for an empty message, it'd be too easy to lose
+            Message::Write(text) if text.is_empty() => write!(f, "WRITE"),
+            Message::Write(text) => write!(f, "WRITE {}", escape(text)),
+            Message::ChangeColour(r, g, b) => write!(f, "CHANGE_COLOUR {} {} {}", r, g, b),
+        }
+    }
+}
+
+impl FromStr for Message {
+    type Err = ParseMessageError;
+
+    fn from_str(line: &str) -> Result<Message, ParseMessageError> {
+        // only the line ending is trimmed, spaces at the end of a WRITE are part of the text
+        let line = line.trim_end_matches(['\n', '\r']);
+        let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+        if command.is_empty() {
+            return Err(ParseMessageError::Empty);
+        }
+        let command = command.to_ascii_uppercase();
+        let numbers = |command: &'static str, expected: usize| -> Result<Vec<i32>, ParseMessageError> {
+            let args: Vec<&str> = rest.split_whitespace().collect();
+            if args.len() != expected {
+                return Err(ParseMessageError::WrongArgCount { command, expected, found: args.len() });
+            }
+            args.iter().map(|a| a.parse().map_err(|_| ParseMessageError::BadNumber(a.to_string()))).collect()
+        };
+        match command.as_str() {
+            "QUIT" => numbers("QUIT", 0).map(|_| Message::Quit),
+            "MOVE" => numbers("MOVE", 2).map(|n| Message::Move { x: n[0], y: n[1] }),
+            "WRITE" => unescape(rest).map(Message::Write),
+            "CHANGE_COLOUR" => numbers("CHANGE_COLOUR", 3).map(|n| Message::ChangeColour(n[0], n[1], n[2])),
+            _ => Err(ParseMessageError::UnknownCommand(command)),
+        }
+    }
+}
+
+// every variant through both formats and back, including the awkward values, then the
+// decoder fed in every way a socket might feed it
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages() -> Vec<Message> {
+        vec![
+            Message::Quit,
+            Message::Move { x: 3, y: 4 },
+            Message::Move { x: i32::MIN, y: i32::MAX },
+            Message::Write(String::new()),
+            Message::Write(String::from("hello")),
+            Message::Write(String::from("  spaces  at both ends  ")),
+            Message::Write(String::from("two\nlines, a \\ and a \r")),
+            Message::Write(String::from("WRITE \\n is not a newline")),
+            Message::Write(String::from("héllo wörld 🦀")),
+            Message::ChangeColour(255, 0, 128),
+            Message::ChangeColour(-1, i32::MIN, i32::MAX),
+        ]
+    }
+
+    #[test]
+    fn every_message_round_trips() {
+        for message in &messages() {
+            let line = message.to_string();
+            assert!(!line.contains('\n'), "{:?} took more than one line", message);
+            assert_eq!(line.parse::<Message>().as_ref(), Ok(message), "{}", line);
+
+            let frame = message.to_frame().unwrap();
+            let mut decoder = FrameDecoder::new();
+            decoder.push(&frame);
+            assert_eq!(decoder.next_message(), Ok(Some(message.clone())));
+            assert_eq!(decoder.next_message(), Ok(None));
+        }
+    }
+
+    #[test]
+    fn known_encodings() {
+        assert_eq!(Message::Move { x: 3, y: 4 }.to_string(), "MOVE 3 4");
+        assert_eq!(Message::Move { x: 3, y: 4 }.to_frame().unwrap(), vec![0, 0, 0, 9, 1, 0, 0, 0, 3, 0, 0, 0, 4]);
+        assert_eq!("move 3 4\r\n".parse(), Ok(Message::Move { x: 3, y: 4 }));
+        assert_eq!("WRITE".parse(), Ok(Message::Write(String::new())));
+    }
+
+    // one frame arriving over three reads: part of the length, the rest of the length and
+    // part of the body, then the end of the body
+    #[test]
+    fn frame_split_across_reads() {
+        let frame = Message::Move { x: 3, y: 4 }.to_frame().unwrap();
+        let mut decoder = FrameDecoder::new();
+        decoder.push(&frame[..2]);
+        assert_eq!(decoder.next_message(), Ok(None));
+        decoder.push(&frame[2..7]);
+        assert_eq!(decoder.next_message(), Ok(None));
+        assert_eq!(decoder.buffered(), 7);
+        decoder.push(&frame[7..]);
+        assert_eq!(decoder.next_message(), Ok(Some(Message::Move { x: 3, y: 4 })));
+        assert_eq!(decoder.buffered(), 0);
+    }
+
+    // all of them in one stream, arriving in pieces of every size from 1 to 7 bytes, so
+    // frames get split at every possible point
+    #[test]
+    fn stream_in_pieces() {
+        let messages = messages();
+        let stream: Vec<u8> = messages.iter().flat_map(|m| m.to_frame().unwrap()).collect();
+        for piece in 1..=7 {
+            let mut decoder = FrameDecoder::new();
+            let mut decoded = Vec::new();
+            for chunk in stream.chunks(piece) {
+                decoder.push(chunk);
+                while let Some(message) = decoder.next_message().unwrap() {
+                    decoded.push(message);
+                }
+            }
+            assert_eq!(decoded, messages);
+            assert_eq!(decoder.buffered(), 0);
+        }
+    }
+
+    // a length prefix past the limit is refused on its own, without waiting for (or keeping)
+    // a body that may never come
+    #[test]
+    fn oversized_length_prefix() {
+        let mut decoder = FrameDecoder::new();
+        decoder.push(&u32::MAX.to_be_bytes());
+        assert_eq!(
+            decoder.next_message(),
+            Err(FrameError::Oversized { len: u32::MAX as usize, max: MAX_FRAME_LEN })
+        );
+        assert_eq!(decoder.buffered(), 0);
+        decoder.push(&[0; 100]);
+        assert_eq!(decoder.buffered(), 0);
+
+        let too_big = Message::Write("x".repeat(MAX_FRAME_LEN));
+        assert_eq!(too_big.to_frame(), Err(FrameError::Oversized { len: MAX_FRAME_LEN + 1, max: MAX_FRAME_LEN }));
+    }
+
+    // an oversized frame is refused as soon as its length is seen, and skipped over
+    // while the good frames either side still come through
+    #[test]
+    fn oversized_frame_is_skipped() {
+        let big = Message::Write("x".repeat(100));
+        let mut decoder = FrameDecoder::with_max_len(50);
+        let mut stream = Message::Quit.to_frame().unwrap();
+        stream.extend(big.to_frame().unwrap());
+        stream.extend(Message::Move { x: 1, y: 2 }.to_frame().unwrap());
+        let mut results = Vec::new();
+        for chunk in stream.chunks(10) {
+            decoder.push(chunk);
+            loop {
+                match decoder.next_message() {
+                    Ok(None) => break,
+                    result => results.push(result),
+                }
+            }
+            assert!(decoder.buffered() <= 50);
+        }
+        assert_eq!(
+            results,
+            vec![Ok(Some(Message::Quit)), Err(FrameError::Oversized { len: 101, max: 50 }), Ok(Some(Message::Move { x: 1, y: 2 }))]
+        );
+    }
+
+    #[test]
+    fn unknown_tag_byte() {
+        for tag in [CHANGE_COLOUR + 1, 0x7f, 0xff] {
+            let mut decoder = FrameDecoder::new();
+            decoder.push(&[0, 0, 0, 3, tag, 1, 2]);
+            assert_eq!(decoder.next_message(), Err(FrameError::UnknownTag(tag)));
+            assert_eq!(decoder.buffered(), 0);
+        }
+    }
+
+    // bad frames are dropped whole, and the next one still decodes
+    #[test]
+    fn bad_frames_are_dropped_whole() {
+        let bad_frames: Vec<(Vec<u8>, FrameError)> = vec![
+            (vec![0, 0, 0, 0], FrameError::Empty),
+            (vec![0, 0, 0, 1, 9], FrameError::UnknownTag(9)),
+            (vec![0, 0, 0, 2, QUIT, 0], FrameError::BadPayload { tag: QUIT, reason: String::from("expected 0 bytes, found 1") }),
+            (vec![0, 0, 0, 5, MOVE, 0, 0, 0, 1], FrameError::BadPayload { tag: MOVE, reason: String::from("expected 8 bytes, found 4") }),
+            (vec![0, 0, 0, 3, WRITE, 0xff, 0xfe], FrameError::BadPayload { tag: WRITE, reason: String::from("invalid utf-8 sequence of 1 bytes from index 0") }),
+        ];
+        for (frame, error) in bad_frames {
+            let mut decoder = FrameDecoder::new();
+            decoder.push(&frame);
+            decoder.push(&Message::Quit.to_frame().unwrap());
+            assert_eq!(decoder.next_message(), Err(error));
+            assert_eq!(decoder.next_message(), Ok(Some(Message::Quit)));
+        }
+    }
+
+    #[test]
+    fn bad_lines() {
+        let bad_lines = [
+            ("", ParseMessageError::Empty),
+            (" MOVE 1 2", ParseMessageError::Empty),
+            ("JUMP 1 2", ParseMessageError::UnknownCommand(String::from("JUMP"))),
+            ("MOVE 1", ParseMessageError::WrongArgCount { command: "MOVE", expected: 2, found: 1 }),
+            ("QUIT now", ParseMessageError::WrongArgCount { command: "QUIT", expected: 0, found: 1 }),
+            ("CHANGE_COLOUR 1 2 x", ParseMessageError::BadNumber(String::from("x"))),
+            ("MOVE 1 99999999999", ParseMessageError::BadNumber(String::from("99999999999"))),
+            ("WRITE a \\t b", ParseMessageError::BadEscape(String::from("\\t"))),
+            ("WRITE ends in \\", ParseMessageError::BadEscape(String::from("\\"))),
+        ];
+        for (line, error) in bad_lines {
+            assert_eq!(line.parse::<Message>(), Err(error), "{:?}", line);
+        }
+        assert_eq!("JUMP 1 2".parse::<Message>().unwrap_err().to_string(), "unknown command 'JUMP'");
+    }
+}
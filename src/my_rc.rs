@@ -0,0 +1,461 @@
+// Our own Rc, to see what std's is doing underneath.
+// MyBox in deref.rs only showed the Deref half of a smart pointer. This one also owns a heap
+// allocation that it shares: the value lives next to two counters, and every MyRc points at
+// the same block.
+//
+//   strong: how many MyRc are alive. When it hits 0 the value is dropped
+//   weak:   how many MyWeak are alive, plus one shared by all the MyRc together. When it
+//           hits 0 nothing can look at the block any more, so the memory is freed
+//
+// Keeping the value and the memory separate is what lets a MyWeak outlive the value: it
+// still needs the counters to find out the value is gone.
+
+use std::alloc::{self, Layout};
+use std::cell::Cell;
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem::ManuallyDrop;
+use std::ops::Deref;
+use std::ptr::{self, NonNull};
+
+struct RcBox<T> {
+    strong: Cell<usize>,
+    weak: Cell<usize>,
+    // ManuallyDrop because we decide when the value is dropped, not the RcBox
+    value: ManuallyDrop<T>,
+}
+
+pub struct MyRc<T> {
+    ptr: NonNull<RcBox<T>>,
+    // tells the compiler that dropping a MyRc may drop a T, which a raw pointer doesn't say
+    phantom: PhantomData<RcBox<T>>,
+}
+
+pub struct MyWeak<T> {
+    ptr: NonNull<RcBox<T>>,
+}
+
+thread_local! {
+    // how many RcBox blocks haven't been freed yet, so the tests can check for leaks
+    static LIVE: Cell<usize> = const { Cell::new(0) };
+}
+
+pub fn live_allocations() -> usize {
+    LIVE.with(|live| live.get())
+}
+
+fn allocate<T>(value: T) -> NonNull<RcBox<T>> {
+    let layout = Layout::new::<RcBox<T>>();
+    // SAFETY: the layout is never zero sized, RcBox always has the two counters in it
+    let raw = unsafe { alloc::alloc(layout) } as *mut RcBox<T>;
+    let ptr = match NonNull::new(raw) {
+        Some(ptr) => ptr,
+        None => alloc::handle_alloc_error(layout),
+    };
+    // the new MyRc is the one strong, and together the strong ones hold one weak
+    let inner = RcBox { strong: Cell::new(1), weak: Cell::new(1), value: ManuallyDrop::new(value) };
+    // SAFETY: the memory was just allocated with the right layout and nothing else has it
+    unsafe { ptr.as_ptr().write(inner) };
+    LIVE.with(|live| live.set(live.get() + 1));
+    ptr
+}
+
+// only called once the last weak (including the one the strong ones share) is gone, by then
+// the value has been dropped or moved out, so there's only the memory left to give back
+unsafe fn deallocate<T>(ptr: NonNull<RcBox<T>>) {
+    alloc::dealloc(ptr.as_ptr() as *mut u8, Layout::new::<RcBox<T>>());
+    LIVE.with(|live| live.set(live.get() - 1));
+}
+
+// drops one weak and frees the memory if it was the last
+unsafe fn release_weak<T>(ptr: NonNull<RcBox<T>>) {
+    let inner = ptr.as_ref();
+    inner.weak.set(inner.weak.get() - 1);
+    if inner.weak.get() == 0 {
+        deallocate(ptr);
+    }
+}
+
+impl<T> MyRc<T> {
+    pub fn new(value: T) -> MyRc<T> {
+        MyRc { ptr: allocate(value), phantom: PhantomData }
+    }
+
+    fn inner(&self) -> &RcBox<T> {
+        // SAFETY: while any MyRc is alive the strong count is above 0, so the block is there
+        unsafe { self.ptr.as_ref() }
+    }
+
+    // these take the pointer as this: &Self rather than self, like std, so they can't be
+    // confused with methods on the T that MyRc derefs to
+    pub fn strong_count(this: &Self) -> usize {
+        this.inner().strong.get()
+    }
+
+    // the weak that all the strong ones share isn't a MyWeak, so it isn't counted
+    pub fn weak_count(this: &Self) -> usize {
+        this.inner().weak.get() - 1
+    }
+
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        this.ptr == other.ptr
+    }
+
+    pub fn downgrade(this: &Self) -> MyWeak<T> {
+        let inner = this.inner();
+        inner.weak.set(inner.weak.get() + 1);
+        MyWeak { ptr: this.ptr }
+    }
+
+    // gives the value back if this was the only MyRc, otherwise hands the MyRc back unchanged
+    pub fn try_unwrap(this: Self) -> Result<T, Self> {
+        if MyRc::strong_count(&this) != 1 {
+            return Err(this);
+        }
+        let this = ManuallyDrop::new(this);
+        let inner = this.inner();
+        inner.strong.set(0);
+        // SAFETY: strong is now 0, so nothing else will read or drop the value
+        let value = unsafe { ptr::read(&*inner.value) };
+        // any MyWeak left now sees a strong count of 0 and can't upgrade
+        unsafe { release_weak(this.ptr) };
+        Ok(value)
+    }
+
+    // only hands out &mut when nothing else could be looking at the value
+    pub fn get_mut(this: &mut Self) -> Option<&mut T> {
+        if MyRc::strong_count(this) == 1 && MyRc::weak_count(this) == 0 {
+            // SAFETY: this is the only pointer of any kind to the block
+            Some(unsafe { &mut this.ptr.as_mut().value })
+        } else {
+            None
+        }
+    }
+
+    // clone on write: if the value is shared, this MyRc gets a copy of its own first
+    pub fn make_mut(this: &mut Self) -> &mut T
+    where
+        T: Clone,
+    {
+        if MyRc::strong_count(this) != 1 {
+            // the other MyRc keep the old value
+            *this = MyRc::new((**this).clone());
+        } else if MyRc::weak_count(this) != 0 {
+            // nobody else holds the value, only MyWeak can see it. No need to clone, move it
+            // into a new block and leave the MyWeak behind looking at a dead one
+            let inner = this.inner();
+            inner.strong.set(0);
+            // SAFETY: strong is 0, so the old block won't touch its value again
+            let value = unsafe { ptr::read(&*inner.value) };
+            let old = this.ptr;
+            // write rather than assign, assigning would run Drop on the old MyRc
+            unsafe {
+                ptr::write(this, MyRc::new(value));
+                release_weak(old);
+            }
+        }
+        // SAFETY: by now strong is 1 and weak is 0, so this is the only pointer
+        unsafe { &mut this.ptr.as_mut().value }
+    }
+}
+
+impl<T> Clone for MyRc<T> {
+    fn clone(&self) -> MyRc<T> {
+        let inner = self.inner();
+        inner.strong.set(inner.strong.get() + 1);
+        MyRc { ptr: self.ptr, phantom: PhantomData }
+    }
+}
+
+impl<T> Deref for MyRc<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner().value
+    }
+}
+
+impl<T> Drop for MyRc<T> {
+    fn drop(&mut self) {
+        let inner = self.inner();
+        inner.strong.set(inner.strong.get() - 1);
+        if inner.strong.get() != 0 {
+            return;
+        }
+        // the order here matters. The value is dropped first, while the shared weak still
+        // keeps the memory around, because the value might hold a MyWeak to its own block
+        // (see the parent pointers in tree.rs). Dropping that MyWeak mustn't free the memory
+        // out from under us. Only after that do we give up the shared weak.
+        unsafe {
+            ManuallyDrop::drop(&mut self.ptr.as_mut().value);
+            release_weak(self.ptr);
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for MyRc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T> MyWeak<T> {
+    fn inner(&self) -> &RcBox<T> {
+        // SAFETY: a MyWeak holds one of the weak counts, so the block hasn't been freed,
+        // although the value in it might have been dropped
+        unsafe { self.ptr.as_ref() }
+    }
+
+    pub fn upgrade(&self) -> Option<MyRc<T>> {
+        let inner = self.inner();
+        if inner.strong.get() == 0 {
+            return None;
+        }
+        inner.strong.set(inner.strong.get() + 1);
+        Some(MyRc { ptr: self.ptr, phantom: PhantomData })
+    }
+
+    pub fn strong_count(&self) -> usize {
+        self.inner().strong.get()
+    }
+
+    // like std, once the value is gone there's nothing to count weak references to
+    pub fn weak_count(&self) -> usize {
+        let inner = self.inner();
+        if inner.strong.get() == 0 {
+            0
+        } else {
+            inner.weak.get() - 1
+        }
+    }
+}
+
+impl<T> Clone for MyWeak<T> {
+    fn clone(&self) -> MyWeak<T> {
+        let inner = self.inner();
+        inner.weak.set(inner.weak.get() + 1);
+        MyWeak { ptr: self.ptr }
+    }
+}
+
+impl<T> Drop for MyWeak<T> {
+    fn drop(&mut self) {
+        // SAFETY: this MyWeak's count is still included, so the block is there
+        unsafe { release_weak(self.ptr) };
+    }
+}
+
+// Each test runs the same steps on MyRc and std's Rc and checks every count, every result and
+// every drop matches. Each one ends by checking there are no blocks left, a double free or a
+// leak would show up there (or, for a double free, as a crash, or as an error under
+// `cargo miri test`).
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::{Rc, Weak};
+
+    // Records when values are dropped, so both versions can be checked to drop at the same
+    // moment. Each one gets its own log, so the test can compare the two afterwards.
+    struct Noisy {
+        name: &'static str,
+        log: Rc<RefCell<Vec<&'static str>>>,
+    }
+
+    impl Drop for Noisy {
+        fn drop(&mut self) {
+            self.log.borrow_mut().push(self.name);
+        }
+    }
+
+    // A value that holds a weak pointer to the block it lives in, which is what trips up a Drop
+    // that frees the memory before dropping the value
+    struct MySelfRef {
+        me: RefCell<Option<MyWeak<MySelfRef>>>,
+    }
+
+    struct TheirSelfRef {
+        me: RefCell<Option<Weak<TheirSelfRef>>>,
+    }
+
+    // clone, counts and drop
+    #[test]
+    fn clone_counts_and_drop() {
+        let start = live_allocations();
+        {
+            let mine = MyRc::new(5);
+            let theirs = Rc::new(5);
+            let (mine2, theirs2) = (mine.clone(), theirs.clone());
+            assert_eq!(*mine2, *theirs2);
+            assert_eq!(MyRc::strong_count(&mine), Rc::strong_count(&theirs));
+            assert!(MyRc::ptr_eq(&mine, &mine2));
+            assert_eq!(live_allocations(), start + 1);
+            drop(mine2);
+            drop(theirs2);
+            assert_eq!(MyRc::strong_count(&mine), Rc::strong_count(&theirs));
+        }
+        assert_eq!(live_allocations(), start);
+    }
+
+    // downgrade and upgrade, before and after the value goes
+    #[test]
+    fn downgrade_and_upgrade() {
+        let start = live_allocations();
+        {
+            let mine = MyRc::new(String::from("hello"));
+            let theirs = Rc::new(String::from("hello"));
+            let (my_weak, their_weak) = (MyRc::downgrade(&mine), Rc::downgrade(&theirs));
+            let (my_weak2, their_weak2) = (my_weak.clone(), their_weak.clone());
+            assert_eq!(MyRc::weak_count(&mine), Rc::weak_count(&theirs));
+            assert_eq!(my_weak.weak_count(), their_weak.weak_count());
+            assert_eq!(my_weak.strong_count(), their_weak.strong_count());
+            {
+                let (up, their_up) = (my_weak.upgrade().unwrap(), their_weak.upgrade().unwrap());
+                assert_eq!(*up, *their_up);
+                assert_eq!(MyRc::strong_count(&mine), Rc::strong_count(&theirs));
+            }
+            drop(mine);
+            drop(theirs);
+            // the value's gone, but the block is still there for the weak ones to look at
+            assert_eq!(my_weak.upgrade().is_none(), their_weak.upgrade().is_none());
+            assert_eq!(my_weak.strong_count(), their_weak.strong_count());
+            assert_eq!(my_weak.weak_count(), their_weak.weak_count());
+            assert_eq!(live_allocations(), start + 1);
+            drop(my_weak);
+            assert_eq!(live_allocations(), start + 1);
+            drop(my_weak2);
+            drop(their_weak2);
+        }
+        assert_eq!(live_allocations(), start);
+    }
+
+    // the value is dropped when the last strong goes, not the last weak
+    #[test]
+    fn value_drops_with_the_last_strong() {
+        let start = live_allocations();
+        {
+            let my_log = Rc::new(RefCell::new(Vec::new()));
+            let their_log = Rc::new(RefCell::new(Vec::new()));
+            let mine = MyRc::new(Noisy { name: "value", log: Rc::clone(&my_log) });
+            let theirs = Rc::new(Noisy { name: "value", log: Rc::clone(&their_log) });
+            let my_weak = MyRc::downgrade(&mine);
+            let their_weak = Rc::downgrade(&theirs);
+            drop(mine);
+            drop(theirs);
+            assert_eq!(*my_log.borrow(), vec!["value"]);
+            assert_eq!(*my_log.borrow(), *their_log.borrow());
+            drop(my_weak);
+            drop(their_weak);
+            // still only dropped once
+            assert_eq!(*my_log.borrow(), *their_log.borrow());
+        }
+        assert_eq!(live_allocations(), start);
+    }
+
+    // try_unwrap
+    #[test]
+    fn try_unwrap() {
+        let start = live_allocations();
+        {
+            let mine = MyRc::new(String::from("only"));
+            let theirs = Rc::new(String::from("only"));
+            let (mine2, theirs2) = (mine.clone(), theirs.clone());
+            // shared, so it comes back untouched
+            let mine = MyRc::try_unwrap(mine).unwrap_err();
+            let theirs = Rc::try_unwrap(theirs).unwrap_err();
+            assert_eq!(MyRc::strong_count(&mine), Rc::strong_count(&theirs));
+            drop(mine2);
+            drop(theirs2);
+            let my_weak = MyRc::downgrade(&mine);
+            let their_weak = Rc::downgrade(&theirs);
+            assert_eq!(MyRc::try_unwrap(mine).unwrap(), Rc::try_unwrap(theirs).unwrap());
+            assert_eq!(my_weak.upgrade().is_none(), their_weak.upgrade().is_none());
+        }
+        assert_eq!(live_allocations(), start);
+    }
+
+    // get_mut and make_mut
+    #[test]
+    fn get_mut_and_make_mut() {
+        let start = live_allocations();
+        {
+            let mut mine = MyRc::new(vec![1]);
+            let mut theirs = Rc::new(vec![1]);
+            MyRc::get_mut(&mut mine).unwrap().push(2);
+            Rc::get_mut(&mut theirs).unwrap().push(2);
+
+            // shared with another strong: get_mut refuses, make_mut copies
+            let (other, their_other) = (mine.clone(), theirs.clone());
+            assert_eq!(MyRc::get_mut(&mut mine).is_none(), Rc::get_mut(&mut theirs).is_none());
+            MyRc::make_mut(&mut mine).push(3);
+            Rc::make_mut(&mut theirs).push(3);
+            assert_eq!((*mine).clone(), (*theirs).clone());
+            assert_eq!(*other, *their_other);
+            assert!(!MyRc::ptr_eq(&mine, &other));
+            assert_eq!(MyRc::strong_count(&other), Rc::strong_count(&their_other));
+            assert_eq!(live_allocations(), start + 2);
+
+            // only weak ones: make_mut moves the value and the weak ones lose it
+            let (my_weak, their_weak) = (MyRc::downgrade(&mine), Rc::downgrade(&theirs));
+            assert_eq!(MyRc::get_mut(&mut mine).is_none(), Rc::get_mut(&mut theirs).is_none());
+            MyRc::make_mut(&mut mine).push(4);
+            Rc::make_mut(&mut theirs).push(4);
+            assert_eq!(*mine, *theirs);
+            assert_eq!(my_weak.upgrade().is_none(), their_weak.upgrade().is_none());
+            assert_eq!(MyRc::weak_count(&mine), Rc::weak_count(&theirs));
+
+            // not shared at all: make_mut changes it in place
+            let before = &*mine as *const Vec<i32>;
+            MyRc::make_mut(&mut mine).push(5);
+            assert_eq!(&*mine as *const Vec<i32>, before);
+            // other's block, the old one the weak ones still point at, and the one mine moved to
+            assert_eq!(live_allocations(), start + 3);
+        }
+        assert_eq!(live_allocations(), start);
+    }
+
+    // a value that points at its own block. Dropping it drops the weak pointer inside,
+    // which would free the block while it's still being dropped if Drop did things in the
+    // wrong order
+    #[test]
+    fn value_pointing_at_its_own_block() {
+        let start = live_allocations();
+        {
+            let mine = MyRc::new(MySelfRef { me: RefCell::new(None) });
+            *mine.me.borrow_mut() = Some(MyRc::downgrade(&mine));
+            let theirs = Rc::new(TheirSelfRef { me: RefCell::new(None) });
+            *theirs.me.borrow_mut() = Some(Rc::downgrade(&theirs));
+            assert_eq!(MyRc::weak_count(&mine), Rc::weak_count(&theirs));
+            let mine_again = mine.me.borrow().as_ref().unwrap().upgrade().unwrap();
+            assert!(MyRc::ptr_eq(&mine, &mine_again));
+        }
+        assert_eq!(live_allocations(), start);
+    }
+
+    // a long chain of clones and weak pointers, dropped in an awkward order
+    #[test]
+    fn awkward_drop_order() {
+        let start = live_allocations();
+        {
+            let mine = MyRc::new(0u8);
+            let theirs = Rc::new(0u8);
+            let mut strong: Vec<_> = (0..100).map(|_| (mine.clone(), theirs.clone())).collect();
+            let mut weak: Vec<_> = (0..100).map(|_| (MyRc::downgrade(&mine), Rc::downgrade(&theirs))).collect();
+            drop(mine);
+            drop(theirs);
+            while !strong.is_empty() || !weak.is_empty() {
+                if weak.len() >= strong.len() && !weak.is_empty() {
+                    weak.remove(weak.len() / 2);
+                } else {
+                    strong.swap_remove(0);
+                }
+                if let Some((w, tw)) = weak.first() {
+                    assert_eq!(w.strong_count(), tw.strong_count());
+                    assert_eq!(w.weak_count(), tw.weak_count());
+                    assert_eq!(w.upgrade().is_none(), tw.upgrade().is_none());
+                }
+            }
+        }
+        assert_eq!(live_allocations(), start);
+    }
+}
@@ -0,0 +1,210 @@
+// Parallel versions of the iterator pipelines from the iterators chapter.
+// slice.par_iter() gives a ParIter, which supports map and filter like a normal iterator,
+// and nothing runs until collect or reduce is called. Then the slice is cut into chunks,
+// each chunk is run through the whole pipeline on a pool thread, and the chunk results are
+// stitched back together in their original order.
+//
+// The closures are shared between threads, so they have to be Sync, and the items they
+// produce have to be Send to come back from the workers.
+
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::thread_pool::ThreadPool;
+
+// chunks per thread, more than one so a slow chunk doesn't leave the other threads idle
+const CHUNKS_PER_THREAD: usize = 4;
+
+// the pipeline starts out as Some, every item passes through unchanged
+type Identity<'a, T> = fn(&'a T) -> Option<&'a T>;
+
+pub trait ParallelSlice<T: Sync> {
+    fn par_iter(&self) -> ParIter<'_, T, Identity<'_, T>>;
+    fn par_iter_in<'a>(&'a self, pool: &'a ThreadPool) -> ParIter<'a, T, Identity<'a, T>>;
+}
+
+// Vec<T> derefs to [T], so vec.par_iter() works as well
+impl<T: Sync> ParallelSlice<T> for [T] {
+    fn par_iter(&self) -> ParIter<'_, T, Identity<'_, T>> {
+        self.par_iter_in(ThreadPool::global())
+    }
+
+    fn par_iter_in<'a>(&'a self, pool: &'a ThreadPool) -> ParIter<'a, T, Identity<'a, T>> {
+        ParIter { pool, items: self, f: Some }
+    }
+}
+
+// f is the whole pipeline so far, applied to one item of the slice. It returns None when
+// a filter has thrown the item away
+pub struct ParIter<'a, T, F> {
+    pool: &'a ThreadPool,
+    items: &'a [T],
+    f: F,
+}
+
+impl<'a, T, U, F> ParIter<'a, T, F>
+where
+    T: Sync,
+    U: Send,
+    F: Fn(&'a T) -> Option<U> + Sync,
+{
+    pub fn map<V, G>(self, g: G) -> ParIter<'a, T, impl Fn(&'a T) -> Option<V> + Sync>
+    where
+        G: Fn(U) -> V + Sync,
+    {
+        let f = self.f;
+        ParIter { pool: self.pool, items: self.items, f: move |x| f(x).map(&g) }
+    }
+
+    pub fn filter<P>(self, predicate: P) -> ParIter<'a, T, impl Fn(&'a T) -> Option<U> + Sync>
+    where
+        P: Fn(&U) -> bool + Sync,
+    {
+        let f = self.f;
+        ParIter { pool: self.pool, items: self.items, f: move |x| f(x).filter(&predicate) }
+    }
+
+    pub fn collect(self) -> Vec<U> {
+        let f = &self.f;
+        self.run_chunks(|chunk| chunk.iter().filter_map(f).collect::<Vec<U>>())
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+
+    // op must be associative, and identity must leave any value unchanged when combined
+    // with it, as each chunk is reduced on its own and the chunk results are combined
+    // afterwards, e.g. reduce(|| 0, |a, b| a + b)
+    pub fn reduce<ID, OP>(self, identity: ID, op: OP) -> U
+    where
+        ID: Fn() -> U + Sync,
+        OP: Fn(U, U) -> U + Sync,
+    {
+        let f = &self.f;
+        let (identity, op) = (&identity, &op);
+        self.run_chunks(|chunk| chunk.iter().filter_map(f).fold(identity(), op))
+            .into_iter()
+            .fold(identity(), op)
+    }
+
+    fn run_chunks<R, C>(&self, per_chunk: C) -> Vec<R>
+    where
+        R: Send,
+        C: Fn(&'a [T]) -> R + Sync,
+    {
+        if self.items.is_empty() {
+            return Vec::new();
+        }
+        let chunks = (self.pool.size() * CHUNKS_PER_THREAD).min(self.items.len());
+        let chunk_size = self.items.len().div_ceil(chunks);
+        let per_chunk = &per_chunk;
+        let jobs = self
+            .items
+            .chunks(chunk_size)
+            .map(|chunk| Box::new(move || per_chunk(chunk)) as Box<dyn FnOnce() -> R + Send + '_>)
+            .collect();
+        self.pool.scoped_map(jobs)
+    }
+}
+
+pub fn this_main() {
+    // the Counter pipeline from the iterators chapter, over a much longer range
+    let numbers: Vec<u64> = (1..=100_000).collect();
+    let sequential: u64 = numbers
+        .iter()
+        .zip(numbers.iter().skip(1))
+        .map(|(a, b)| a * b)
+        .filter(|x| x % 3 == 0)
+        .sum();
+    let pairs: Vec<(u64, u64)> = numbers.iter().copied().zip(numbers.iter().copied().skip(1)).collect();
+    let parallel = pairs
+        .par_iter()
+        .map(|(a, b)| a * b)
+        .filter(|x| x % 3 == 0)
+        .reduce(|| 0, |a, b| a + b);
+    println!("sum of products divisible by 3: {} in order, {} in parallel", sequential, parallel);
+
+    // results come back in the order of the input, whichever thread finishes first, and the
+    // closures can borrow from the surrounding scope
+    let pool = ThreadPool::new(3);
+    let offset = 10;
+    let shifted = numbers[..5].par_iter_in(&pool).map(|x| x + offset).collect();
+    println!("{:?}", shifted);
+
+    // a panic on a worker comes back to the caller
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        numbers.par_iter_in(&pool).map(|&x| if x == 500 { panic!("bad item {}", x) } else { x }).collect()
+    }));
+    if let Err(payload) = result {
+        println!("caught: {}", payload.downcast_ref::<String>().unwrap());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_as_sequential() {
+        let numbers: Vec<u64> = (1..=10_000).collect();
+        let sequential: u64 = numbers.iter().map(|x| x * x).filter(|x| x % 3 == 0).sum();
+        let parallel = numbers.par_iter().map(|x| x * x).filter(|x| x % 3 == 0).reduce(|| 0, |a, b| a + b);
+        assert_eq!(parallel, sequential);
+    }
+
+    #[test]
+    fn collect_keeps_input_order() {
+        let pool = ThreadPool::new(3);
+        let numbers: Vec<u64> = (0..1_000).collect();
+        let squares = numbers.par_iter_in(&pool).map(|x| x * x).collect();
+        assert_eq!(squares, numbers.iter().map(|x| x * x).collect::<Vec<_>>());
+        let evens = numbers.par_iter_in(&pool).filter(|x| *x % 2 == 0).collect();
+        assert_eq!(evens, numbers.iter().filter(|x| *x % 2 == 0).collect::<Vec<_>>());
+    }
+
+    // nothing to cut into chunks, reduce gives back the identity
+    #[test]
+    fn empty_slice() {
+        let empty: Vec<u64> = Vec::new();
+        assert_eq!(empty.par_iter().map(|x| x + 1).collect(), Vec::<u64>::new());
+        assert_eq!(empty.par_iter().map(|x| *x).reduce(|| 7, |a, b| a + b), 7);
+    }
+
+    // fewer items than threads, so there's only one chunk
+    #[test]
+    fn single_item() {
+        let pool = ThreadPool::new(4);
+        let one = [21u64];
+        assert_eq!(one.par_iter_in(&pool).map(|x| x * 2).collect(), vec![42]);
+        assert_eq!(one.par_iter_in(&pool).map(|x| *x).reduce(|| 0, |a, b| a + b), 21);
+        assert_eq!(one.par_iter_in(&pool).filter(|x| **x > 100).collect(), Vec::<&u64>::new());
+    }
+
+    #[test]
+    fn closures_borrow_the_scope() {
+        let numbers = vec![1u64, 2, 3, 4, 5];
+        let offset = 10;
+        assert_eq!(numbers.par_iter().map(|x| x + offset).collect(), vec![11, 12, 13, 14, 15]);
+    }
+
+    // the panic comes back to the caller, and the pool keeps working after
+    #[test]
+    fn panics_reach_the_caller() {
+        let pool = ThreadPool::new(3);
+        let numbers: Vec<u64> = (1..=1_000).collect();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            numbers.par_iter_in(&pool).map(|&x| if x == 500 { panic!("bad item {}", x) } else { x }).collect()
+        }));
+        let payload = result.unwrap_err();
+        assert_eq!(payload.downcast_ref::<String>().map(String::as_str), Some("bad item 500"));
+        assert_eq!(numbers.par_iter_in(&pool).filter(|x| *x % 2 == 0).collect().len(), 500);
+    }
+
+    // a par_iter inside another one runs on the worker it's already on
+    #[test]
+    fn nested_par_iter() {
+        let pool = ThreadPool::new(2);
+        let rows: Vec<Vec<u64>> = (0..8).map(|i| (0..100).map(|j| i * 100 + j).collect()).collect();
+        let sums = rows.par_iter_in(&pool).map(|row| row.par_iter_in(&pool).map(|x| *x).reduce(|| 0, |a, b| a + b)).collect();
+        assert_eq!(sums, rows.iter().map(|row| row.iter().sum()).collect::<Vec<u64>>());
+    }
+}
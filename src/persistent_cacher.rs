@@ -0,0 +1,455 @@
+// A Cacher that keeps its results in a file, so they survive the process exiting.
+//
+// The file starts with a header, the magic bytes "LRCACHE" and a format version, followed
+// by one record per computed value, appended as they are calculated:
+//
+//     key length (u32) | key bytes | value length (u32) | value bytes | checksum (u32)
+//
+// all integers little endian. A value length of u32::MAX marks a removed key (with no value
+// bytes), and when a key appears more than once the last record wins. Compacting rewrites
+// the file with a single record for each live key.
+//
+// If anything about the file looks wrong (bad header, unknown version, a truncated record,
+// a checksum mismatch or bytes the codecs can't decode) the whole file is discarded and the
+// cache starts empty, it is only a cache after all.
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs::{self, File, OpenOptions};
+use std::hash::Hash;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 7] = b"LRCACHE";
+const VERSION: u8 = 1;
+const REMOVED: u32 = u32::MAX;
+
+// turns keys or values into bytes and back, pass your own to store other types
+pub trait Codec<T> {
+    fn encode(&self, value: &T) -> Vec<u8>;
+    // None if the bytes aren't a valid encoding
+    fn decode(&self, bytes: &[u8]) -> Option<T>;
+}
+
+// fixed width little endian integers
+pub struct IntCodec;
+
+macro_rules! int_codec {
+    ($($t:ty),*) => {
+        $(
+            impl Codec<$t> for IntCodec {
+                fn encode(&self, value: &$t) -> Vec<u8> {
+                    value.to_le_bytes().to_vec()
+                }
+
+                fn decode(&self, bytes: &[u8]) -> Option<$t> {
+                    Some(<$t>::from_le_bytes(bytes.try_into().ok()?))
+                }
+            }
+        )*
+    };
+}
+
+int_codec!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+// utf-8 text
+pub struct StringCodec;
+
+impl Codec<String> for StringCodec {
+    fn encode(&self, value: &String) -> Vec<u8> {
+        value.as_bytes().to_vec()
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Option<String> {
+        String::from_utf8(bytes.to_vec()).ok()
+    }
+}
+
+pub struct PersistentCacher<T, K, V, KC, VC>
+where
+    T: Fn(K) -> V,
+{
+    calculation: T,
+    key_codec: KC,
+    value_codec: VC,
+    values: HashMap<K, V>,
+    path: PathBuf,
+    file: File,
+    // true if the file was corrupt when opened and had to be thrown away
+    recovered: bool,
+}
+
+impl<T, K, V, KC, VC> PersistentCacher<T, K, V, KC, VC>
+where
+    T: Fn(K) -> V,
+    K: Hash + Eq + Clone,
+    V: Clone,
+    KC: Codec<K>,
+    VC: Codec<V>,
+{
+    // loads whatever was saved at path, creating the file if it doesn't exist yet
+    pub fn open(
+        path: impl AsRef<Path>,
+        calculation: T,
+        key_codec: KC,
+        value_codec: VC,
+    ) -> io::Result<PersistentCacher<T, K, V, KC, VC>> {
+        let path = path.as_ref().to_path_buf();
+        let (values, recovered) = match fs::read(&path) {
+            Ok(bytes) => match decode_file(&bytes, &key_codec, &value_codec) {
+                Some(values) => (values, false),
+                None => (HashMap::new(), true),
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (HashMap::new(), false),
+            Err(e) => return Err(e),
+        };
+        if recovered || !path.exists() {
+            write_header(&path)?;
+        }
+        let file = OpenOptions::new().append(true).open(&path)?;
+        Ok(PersistentCacher { calculation, key_codec, value_codec, values, path, file, recovered })
+    }
+
+    pub fn value(&mut self, arg: K) -> io::Result<V> {
+        if let Some(v) = self.values.get(&arg) {
+            return Ok(v.clone());
+        }
+        let v = (self.calculation)(arg.clone());
+        let record = encode_record(&self.key_codec.encode(&arg), Some(&self.value_codec.encode(&v)));
+        self.file.write_all(&record)?;
+        self.values.insert(arg, v.clone());
+        Ok(v)
+    }
+
+    // forgets a value so the next call to value recalculates it
+    pub fn remove(&mut self, arg: &K) -> io::Result<()> {
+        if self.values.remove(arg).is_some() {
+            self.file.write_all(&encode_record(&self.key_codec.encode(arg), None))?;
+        }
+        Ok(())
+    }
+
+    // rewrites the file with one record per cached value, dropping overwritten and removed
+    // entries. The new file is written alongside and renamed over the old one, so a crash
+    // part way through leaves the old file in place.
+    pub fn compact(&mut self) -> io::Result<()> {
+        let mut temp_name = self.path.clone().into_os_string();
+        temp_name.push(".compact");
+        let temp_path = PathBuf::from(temp_name);
+
+        let mut bytes = header();
+        for (k, v) in &self.values {
+            bytes.extend(encode_record(&self.key_codec.encode(k), Some(&self.value_codec.encode(v))));
+        }
+        fs::write(&temp_path, &bytes)?;
+        fs::rename(&temp_path, &self.path)?;
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn recovered_from_corruption(&self) -> bool {
+        self.recovered
+    }
+}
+
+fn header() -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(VERSION);
+    bytes
+}
+
+fn write_header(path: &Path) -> io::Result<()> {
+    fs::write(path, header())
+}
+
+// a value of None writes a removal marker
+fn encode_record(key: &[u8], value: Option<&[u8]>) -> Vec<u8> {
+    let mut record = Vec::with_capacity(12 + key.len() + value.map_or(0, |v| v.len()));
+    record.extend_from_slice(&(key.len() as u32).to_le_bytes());
+    record.extend_from_slice(key);
+    match value {
+        Some(value) => {
+            record.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            record.extend_from_slice(value);
+        }
+        None => record.extend_from_slice(&REMOVED.to_le_bytes()),
+    }
+    let checksum = fnv1a(&record);
+    record.extend_from_slice(&checksum.to_le_bytes());
+    record
+}
+
+// None means the file is corrupt
+fn decode_file<K, V>(
+    bytes: &[u8],
+    key_codec: &impl Codec<K>,
+    value_codec: &impl Codec<V>,
+) -> Option<HashMap<K, V>>
+where
+    K: Hash + Eq,
+{
+    let rest = bytes.strip_prefix(&MAGIC[..])?;
+    let (&version, mut rest) = rest.split_first()?;
+    if version != VERSION {
+        return None;
+    }
+
+    let mut values = HashMap::new();
+    while !rest.is_empty() {
+        let record = rest;
+        let (key, after_key) = take_field(rest)?;
+        let (value_len, after_len) = take_u32(after_key)?;
+        let (value, after_value) = if value_len == REMOVED {
+            (None, after_len)
+        } else {
+            let value_len = value_len as usize;
+            if after_len.len() < value_len {
+                return None;
+            }
+            (Some(&after_len[..value_len]), &after_len[value_len..])
+        };
+        let (checksum, after_record) = take_u32(after_value)?;
+        if fnv1a(&record[..record.len() - after_value.len()]) != checksum {
+            return None;
+        }
+
+        let key = key_codec.decode(key)?;
+        match value {
+            Some(value) => {
+                values.insert(key, value_codec.decode(value)?);
+            }
+            None => {
+                values.remove(&key);
+            }
+        }
+        rest = after_record;
+    }
+    Some(values)
+}
+
+fn take_u32(bytes: &[u8]) -> Option<(u32, &[u8])> {
+    if bytes.len() < 4 {
+        return None;
+    }
+    let (n, rest) = bytes.split_at(4);
+    Some((u32::from_le_bytes(n.try_into().unwrap()), rest))
+}
+
+// a u32 length followed by that many bytes
+fn take_field(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (len, rest) = take_u32(bytes)?;
+    let len = len as usize;
+    if rest.len() < len {
+        return None;
+    }
+    Some(rest.split_at(len))
+}
+
+// 32 bit FNV-1a, a simple hash that's plenty to catch a torn or flipped record
+fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, &b| (hash ^ b as u32).wrapping_mul(0x0100_0193))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    // with u32 keys and values: key length, key, value length, value and checksum, four
+    // bytes each. A removal has no value
+    const RECORD: usize = 20;
+    const REMOVAL: usize = 16;
+    const HEADER: usize = 8;
+
+    type Cache<'a> = PersistentCacher<Box<dyn Fn(u32) -> u32 + 'a>, u32, u32, IntCodec, IntCodec>;
+
+    // a fresh path for each test, so they can run at the same time
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("learn_rust_persistent_cacher_{}_{}", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    // counts how often the calculation really runs
+    fn open<'a>(path: &Path, calls: &'a Cell<u32>) -> Cache<'a> {
+        let calculation = move |x: u32| {
+            calls.set(calls.get() + 1);
+            x * 10
+        };
+        PersistentCacher::open(path, Box::new(calculation) as Box<dyn Fn(u32) -> u32>, IntCodec, IntCodec).unwrap()
+    }
+
+    fn record(key: u32, value: Option<u32>) -> Vec<u8> {
+        encode_record(&key.to_le_bytes(), value.map(u32::to_le_bytes).as_ref().map(|v| &v[..]))
+    }
+
+    #[test]
+    fn new_file_gets_a_header() {
+        let path = temp_path("new");
+        let calls = Cell::new(0);
+        let cacher = open(&path, &calls);
+        assert!(cacher.is_empty());
+        assert!(!cacher.recovered_from_corruption());
+        assert_eq!(fs::read(&path).unwrap(), b"LRCACHE\x01");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn values_survive_reopening() {
+        let path = temp_path("reopen");
+        let calls = Cell::new(0);
+        {
+            let mut cacher = open(&path, &calls);
+            assert_eq!(cacher.value(1).unwrap(), 10);
+            assert_eq!(cacher.value(2).unwrap(), 20);
+            assert_eq!(cacher.value(1).unwrap(), 10);
+        }
+        let mut cacher = open(&path, &calls);
+        assert_eq!(cacher.len(), 2);
+        assert_eq!(cacher.value(1).unwrap(), 10);
+        assert_eq!(cacher.value(2).unwrap(), 20);
+        assert_eq!(calls.get(), 2);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn removals_survive_reopening() {
+        let path = temp_path("remove");
+        let calls = Cell::new(0);
+        {
+            let mut cacher = open(&path, &calls);
+            cacher.value(1).unwrap();
+            cacher.value(2).unwrap();
+            cacher.remove(&1).unwrap();
+            // removing something that isn't there writes nothing
+            cacher.remove(&3).unwrap();
+        }
+        assert_eq!(fs::read(&path).unwrap().len(), HEADER + 2 * RECORD + REMOVAL);
+        let mut cacher = open(&path, &calls);
+        assert_eq!(cacher.len(), 1);
+        assert_eq!(cacher.value(1).unwrap(), 10);
+        assert_eq!(calls.get(), 3);
+        fs::remove_file(&path).unwrap();
+    }
+
+    // the file is thrown away and rewritten with just a header, and the cache carries on
+    fn assert_recovers(name: &str, damage: impl FnOnce(&mut Vec<u8>)) {
+        let path = temp_path(name);
+        let calls = Cell::new(0);
+        {
+            let mut cacher = open(&path, &calls);
+            cacher.value(1).unwrap();
+            cacher.value(2).unwrap();
+        }
+        let mut bytes = fs::read(&path).unwrap();
+        damage(&mut bytes);
+        fs::write(&path, bytes).unwrap();
+
+        let mut cacher = open(&path, &calls);
+        assert!(cacher.recovered_from_corruption(), "{}", name);
+        assert!(cacher.is_empty());
+        assert_eq!(fs::read(&path).unwrap(), header());
+        assert_eq!(cacher.value(3).unwrap(), 30);
+        drop(cacher);
+
+        let cacher = open(&path, &calls);
+        assert!(!cacher.recovered_from_corruption());
+        assert_eq!(cacher.len(), 1);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        // the first byte of the last record's value
+        assert_recovers("checksum", |bytes| {
+            let at = bytes.len() - 8;
+            bytes[at] ^= 0xff;
+        });
+    }
+
+    #[test]
+    fn truncated_final_record() {
+        assert_recovers("truncated", |bytes| bytes.truncate(bytes.len() - 2));
+        // cut off partway through the length of the value
+        assert_recovers("truncated_length", |bytes| bytes.truncate(HEADER + RECORD + 10));
+    }
+
+    #[test]
+    fn bad_header() {
+        assert_recovers("magic", |bytes| bytes[0] = b'X');
+        assert_recovers("version", |bytes| bytes[7] = VERSION + 1);
+        assert_recovers("header_only_magic", |bytes| bytes.truncate(7));
+    }
+
+    // the checksum is fine but the codec can't make sense of the bytes
+    #[test]
+    fn undecodable_record() {
+        let path = temp_path("undecodable");
+        let mut bytes = header();
+        bytes.extend(encode_record(&1u32.to_le_bytes(), Some(&[0xff, 0xfe])));
+        fs::write(&path, bytes).unwrap();
+        let cacher = PersistentCacher::open(&path, |x: u32| x.to_string(), IntCodec, StringCodec).unwrap();
+        assert!(cacher.recovered_from_corruption());
+        assert!(cacher.is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+
+    // the last record for a key wins, and a removal marker takes it away again
+    #[test]
+    fn last_record_wins() {
+        let path = temp_path("last_wins");
+        let mut bytes = header();
+        for record in [record(1, Some(5)), record(2, Some(6)), record(1, Some(7)), record(2, None)] {
+            bytes.extend(record);
+        }
+        fs::write(&path, bytes).unwrap();
+        let calls = Cell::new(0);
+        let mut cacher = open(&path, &calls);
+        assert_eq!(cacher.len(), 1);
+        assert_eq!(cacher.value(1).unwrap(), 7);
+        assert_eq!(calls.get(), 0);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn compact_keeps_one_record_per_live_key() {
+        let path = temp_path("compact");
+        let calls = Cell::new(0);
+        {
+            let mut cacher = open(&path, &calls);
+            for key in 1..=3 {
+                cacher.value(key).unwrap();
+            }
+            cacher.remove(&2).unwrap();
+            cacher.remove(&3).unwrap();
+            cacher.value(3).unwrap();
+            assert_eq!(fs::read(&path).unwrap().len(), HEADER + 4 * RECORD + 2 * REMOVAL);
+
+            cacher.compact().unwrap();
+            assert_eq!(fs::read(&path).unwrap().len(), HEADER + 2 * RECORD);
+            // still appends to the new file
+            cacher.value(4).unwrap();
+            assert_eq!(fs::read(&path).unwrap().len(), HEADER + 3 * RECORD);
+        }
+        let mut temp_name = path.clone().into_os_string();
+        temp_name.push(".compact");
+        assert!(!PathBuf::from(temp_name).exists());
+
+        let mut cacher = open(&path, &calls);
+        assert_eq!(cacher.len(), 3);
+        assert!(!cacher.recovered_from_corruption());
+        for key in [1, 3, 4] {
+            assert_eq!(cacher.value(key).unwrap(), key * 10);
+        }
+        assert_eq!(calls.get(), 5);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,404 @@
+/*
+Rc<T>, the reference counted smart pointer, lets one value have several owners.
+It keeps a count of the references to the value, and only cleans it up once the count
+drops to zero. Rc::clone doesn't deep copy the data, it only bumps the count.
+
+It is only for use in single threaded code, and only gives out immutable references.
+
+The Box cons list can't share a tail between two lists, because a Box has exactly one
+owner. e.g. with a = 5 -> 10 -> Nil, we can't make both b = 3 -> a and c = 4 -> a.
+With Rc every list that uses a as its tail owns a share of it instead.
+ */
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt;
+use std::rc::{Rc, Weak};
+
+// a persistent list, adding to the front makes a new list and leaves the old one as it is,
+// so any number of lists can be built on top of the same tail
+pub struct List<T> {
+    head: Option<Rc<Node<T>>>,
+}
+
+pub struct Node<T> {
+    value: T,
+    next: Option<Rc<Node<T>>>,
+    // back references to the nodes built directly on top of this one. They're Weak so a
+    // tail doesn't keep the lists built on it alive, otherwise every node would be in a
+    // cycle with its own tail and nothing would ever be freed
+    extended_by: RefCell<Vec<Weak<Node<T>>>>,
+}
+
+impl<T> List<T> {
+    pub fn new() -> List<T> {
+        List { head: None }
+    }
+
+    // a new list with value in front of this one, this list becomes its shared tail
+    pub fn prepend(&self, value: T) -> List<T> {
+        let node = Rc::new(Node {
+            value,
+            next: self.head.clone(),
+            extended_by: RefCell::new(Vec::new()),
+        });
+        if let Some(head) = &self.head {
+            let mut extended_by = head.extended_by.borrow_mut();
+            // clear out references to lists that have since been dropped
+            extended_by.retain(|weak| weak.strong_count() > 0);
+            extended_by.push(Rc::downgrade(&node));
+        }
+        List { head: Some(node) }
+    }
+
+    // the list without its first value, shares every node with this one
+    pub fn tail(&self) -> List<T> {
+        List { head: self.head.as_ref().and_then(|node| node.next.clone()) }
+    }
+
+    pub fn head(&self) -> Option<&T> {
+        self.head.as_ref().map(|node| &node.value)
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { next: self.head.as_deref() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.is_none()
+    }
+
+    // how many owners each node has, front to back. Anything above 1 is shared with
+    // another list (or another clone of this one)
+    pub fn strong_counts(&self) -> Vec<usize> {
+        let mut counts = Vec::new();
+        let mut next = self.head.as_ref();
+        while let Some(node) = next {
+            counts.push(Rc::strong_count(node));
+            next = node.next.as_ref();
+        }
+        counts
+    }
+
+    // the number of nodes at the end of both lists that are the very same nodes in memory,
+    // found with Rc::ptr_eq rather than by comparing values
+    pub fn shared_len(&self, other: &List<T>) -> usize {
+        let mine: HashSet<*const Node<T>> = self.nodes().map(Rc::as_ptr).collect();
+        other.nodes().filter(|n| mine.contains(&Rc::as_ptr(n))).count()
+    }
+
+    // the lists still alive that were made by prepending to this one, found by upgrading
+    // the weak back references. Any that have been dropped upgrade to None and are skipped
+    pub fn extensions(&self) -> Vec<List<T>> {
+        match &self.head {
+            Some(head) => head
+                .extended_by
+                .borrow()
+                .iter()
+                .filter_map(Weak::upgrade)
+                .map(|node| List { head: Some(node) })
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn nodes(&self) -> impl Iterator<Item = &Rc<Node<T>>> {
+        std::iter::successors(self.head.as_ref(), |node| node.next.as_ref())
+    }
+}
+
+impl<T> Default for List<T> {
+    fn default() -> List<T> {
+        List::new()
+    }
+}
+
+// cloning a list is cheap, it's one more owner of the same head node
+impl<T> Clone for List<T> {
+    fn clone(&self) -> List<T> {
+        List { head: self.head.clone() }
+    }
+}
+
+// like the Box list, the generated drop would recurse once per node. Here a node can only
+// be unhooked if we are its last owner, once we reach a node someone else still holds, the
+// rest of the list is theirs and we stop
+impl<T> Drop for List<T> {
+    fn drop(&mut self) {
+        let mut next = self.head.take();
+        while let Some(node) = next {
+            match Rc::try_unwrap(node) {
+                Ok(mut node) => next = node.next.take(),
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+pub struct Iter<'a, T> {
+    next: Option<&'a Node<T>>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        let node = self.next?;
+        self.next = node.next.as_deref();
+        Some(&node.value)
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for List<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for value in self.iter() {
+            write!(f, "{} -> ", value)?;
+        }
+        write!(f, "Nil")
+    }
+}
+
+fn sharing_a_tail() {
+    let a = List::new().prepend(10).prepend(5);
+    println!("count after creating a = {}", Rc::strong_count(a.head.as_ref().unwrap()));
+    let b = a.prepend(3);
+    println!("count after creating b = {:?}", a.strong_counts());
+    {
+        let c = a.prepend(4);
+        println!("count after creating c = {:?}", a.strong_counts());
+        println!("lists built on a = {}", a.extensions().len());
+    }
+    // c's node was freed when it went out of scope, so its weak back reference is dead
+    println!("count after c goes out of scope = {:?}", a.strong_counts());
+    println!("lists built on a = {}", a.extensions().len());
+}
+
+// Reference cycles can leak memory
+// Rust makes it hard to leak memory, but not impossible. If two values hold an Rc to each
+// other the strong count of each can never reach zero, so neither is ever dropped.
+// This is the list from the book that lets you change what a Cons points to afterwards.
+
+#[derive(Debug)]
+pub enum CycleList {
+    Cons(i32, RefCell<Rc<CycleList>>),
+    Nil,
+}
+
+impl CycleList {
+    pub fn tail(&self) -> Option<&RefCell<Rc<CycleList>>> {
+        match self {
+            CycleList::Cons(_, item) => Some(item),
+            CycleList::Nil => None,
+        }
+    }
+}
+
+// Follows the strong references from start, remembering the address of every node seen.
+// Coming back to an address we've already visited means the list loops round on itself,
+// and the length of the loop is returned. Reaching Nil means there's no cycle.
+pub fn find_cycle(start: &Rc<CycleList>) -> Option<usize> {
+    let mut seen: Vec<*const CycleList> = Vec::new();
+    let mut current = Rc::clone(start);
+    loop {
+        let address = Rc::as_ptr(&current);
+        if let Some(position) = seen.iter().position(|&seen| seen == address) {
+            return Some(seen.len() - position);
+        }
+        seen.push(address);
+        let next = match current.tail() {
+            Some(next) => Rc::clone(&next.borrow()),
+            None => return None,
+        };
+        current = next;
+    }
+}
+
+fn creating_a_reference_cycle() -> Weak<CycleList> {
+    let a = Rc::new(CycleList::Cons(5, RefCell::new(Rc::new(CycleList::Nil))));
+    println!("a initial rc count = {}", Rc::strong_count(&a));
+
+    let b = Rc::new(CycleList::Cons(10, RefCell::new(Rc::clone(&a))));
+    println!("a rc count after b creation = {}", Rc::strong_count(&a));
+    println!("b initial rc count = {}", Rc::strong_count(&b));
+    println!("cycle from b = {:?}", find_cycle(&b));
+
+    if let Some(link) = a.tail() {
+        *link.borrow_mut() = Rc::clone(&b); // a now points to b, which points to a
+    }
+    println!("b rc count after changing a = {}", Rc::strong_count(&b));
+    println!("a rc count after changing a = {}", Rc::strong_count(&a));
+    // println!("a next item = {:?}", a.tail()); <- would overflow the stack, Debug
+    // follows the cycle forever
+    println!("cycle from a = {:?}", find_cycle(&a));
+
+    // a weak reference doesn't own anything, so it can tell us whether a was freed
+    Rc::downgrade(&a)
+} // a and b go out of scope here, but each still has a strong count of 1
+
+pub fn this_main() {
+    sharing_a_tail();
+
+    let a = creating_a_reference_cycle();
+    // if a had been freed this would be None
+    let leaked = a.upgrade().expect("the cycle should have kept a alive");
+    println!("a leaked with strong count {}", Rc::strong_count(&leaked) - 1);
+
+    // breaking the cycle by hand lets both nodes be freed
+    if let Some(link) = leaked.tail() {
+        *link.borrow_mut() = Rc::new(CycleList::Nil);
+    }
+    drop(leaked);
+    println!("a freed once the cycle is broken: {}", a.upgrade().is_none());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(values: &[i32]) -> List<i32> {
+        values.iter().rev().fold(List::new(), |list, &value| list.prepend(value))
+    }
+
+    // a Cons whose tail is filled in afterwards
+    fn cons(value: i32) -> Rc<CycleList> {
+        Rc::new(CycleList::Cons(value, RefCell::new(Rc::new(CycleList::Nil))))
+    }
+
+    fn link(from: &Rc<CycleList>, to: &Rc<CycleList>) {
+        *from.tail().unwrap().borrow_mut() = Rc::clone(to);
+    }
+
+    #[test]
+    fn prepend_head_and_tail() {
+        let a = list(&[5, 10]);
+        assert_eq!(a.to_string(), "5 -> 10 -> Nil");
+        assert_eq!(a.head(), Some(&5));
+        assert_eq!(a.len(), 2);
+        assert_eq!(a.tail().to_string(), "10 -> Nil");
+        assert!(a.tail().tail().is_empty());
+        // the tail of an empty list is empty too
+        assert!(List::<i32>::default().tail().is_empty());
+        assert_eq!(List::<i32>::new().head(), None);
+    }
+
+    // prepending leaves the old list as it was
+    #[test]
+    fn lists_are_persistent() {
+        let a = list(&[5, 10]);
+        let b = a.prepend(3);
+        let c = a.prepend(4);
+        assert_eq!(a.iter().collect::<Vec<_>>(), vec![&5, &10]);
+        assert_eq!(b.iter().collect::<Vec<_>>(), vec![&3, &5, &10]);
+        assert_eq!(c.iter().collect::<Vec<_>>(), vec![&4, &5, &10]);
+    }
+
+    #[test]
+    fn shared_tail_counts() {
+        let a = list(&[5, 10]);
+        assert_eq!(a.strong_counts(), vec![1, 1]);
+        let b = a.prepend(3);
+        // a and b both own the 5, only the 5 owns the 10
+        assert_eq!(a.strong_counts(), vec![2, 1]);
+        assert_eq!(b.strong_counts(), vec![1, 2, 1]);
+        let clone = b.clone();
+        assert_eq!(b.strong_counts(), vec![2, 2, 1]);
+        drop(clone);
+        drop(b);
+        assert_eq!(a.strong_counts(), vec![1, 1]);
+    }
+
+    // same values isn't enough, they have to be the same nodes
+    #[test]
+    fn shared_len_compares_nodes() {
+        let a = list(&[5, 10]);
+        let b = a.prepend(3);
+        assert_eq!(b.shared_len(&a), 2);
+        assert_eq!(a.shared_len(&b), 2);
+        assert_eq!(b.shared_len(&b.tail().tail()), 1);
+        assert_eq!(list(&[3, 5, 10]).shared_len(&b), 0);
+        assert_eq!(a.shared_len(&List::new()), 0);
+    }
+
+    // dropped lists are skipped when the weak references are upgraded
+    #[test]
+    fn extensions_follow_weak_references() {
+        let a = list(&[5, 10]);
+        let b = a.prepend(3);
+        {
+            let c = a.prepend(4);
+            let heads: Vec<i32> = a.extensions().iter().map(|l| *l.head().unwrap()).collect();
+            assert_eq!(heads, vec![3, 4]);
+            assert_eq!(c.extensions().len(), 0);
+        }
+        let heads: Vec<i32> = a.extensions().iter().map(|l| *l.head().unwrap()).collect();
+        assert_eq!(heads, vec![3]);
+        assert_eq!(b.tail().extensions().len(), 1);
+        assert!(List::<i32>::new().extensions().is_empty());
+    }
+
+    // dropping a list only frees the nodes nobody else holds
+    #[test]
+    fn drop_stops_at_shared_nodes() {
+        let a = list(&[5, 10]);
+        let b = a.prepend(3);
+        drop(a);
+        assert_eq!(b.to_string(), "3 -> 5 -> 10 -> Nil");
+        assert_eq!(b.strong_counts(), vec![1, 1, 1]);
+    }
+
+    // each of these would overflow the stack if drop recursed once per node
+    #[test]
+    fn long_lists_dont_overflow_the_stack() {
+        let mut long = List::new();
+        for i in 0..1_000_000 {
+            long = long.prepend(i);
+        }
+        let shorter = long.tail().tail();
+        assert_eq!(long.shared_len(&shorter), 999_998);
+        drop(long);
+        assert_eq!(shorter.len(), 999_998);
+        drop(shorter);
+    }
+
+    #[test]
+    fn find_cycle_without_a_cycle() {
+        assert_eq!(find_cycle(&Rc::new(CycleList::Nil)), None);
+        let (a, b) = (cons(1), cons(2));
+        link(&a, &b);
+        assert_eq!(find_cycle(&a), None);
+    }
+
+    #[test]
+    fn find_cycle_lengths() {
+        let a = cons(1);
+        link(&a, &a);
+        assert_eq!(find_cycle(&a), Some(1));
+        link(&a, &Rc::new(CycleList::Nil));
+
+        // 1 -> 2 -> 3 -> 4 -> back to 2, the 1 leads into the loop but isn't part of it
+        let nodes: Vec<Rc<CycleList>> = (1..=4).map(cons).collect();
+        for pair in nodes.windows(2) {
+            link(&pair[0], &pair[1]);
+        }
+        link(&nodes[3], &nodes[1]);
+        assert_eq!(find_cycle(&nodes[0]), Some(3));
+        assert_eq!(find_cycle(&nodes[2]), Some(3));
+        link(&nodes[3], &Rc::new(CycleList::Nil));
+    }
+
+    // the cycle keeps both nodes alive after every outside owner is gone
+    #[test]
+    fn cycles_leak_until_broken() {
+        let a = creating_a_reference_cycle();
+        let leaked = a.upgrade().unwrap();
+        // the cycle's own reference plus ours
+        assert_eq!(Rc::strong_count(&leaked), 2);
+        link(&leaked, &Rc::new(CycleList::Nil));
+        drop(leaked);
+        assert!(a.upgrade().is_none());
+    }
+}
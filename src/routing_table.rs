@@ -0,0 +1,584 @@
+// A routing table: given an address, which way should a packet for it go?
+// Every route covers a CIDR block, and an address can be inside several of them (0.0.0.0/0
+// covers everything). The rule is longest prefix match, the most specific route wins.
+//
+// Checking every route would be slow with a lot of them, so the routes live in a binary trie.
+// Each level of the trie is one bit of the address, going left for 0 and right for 1, and a
+// route is stored at the node its prefix leads to. A lookup walks down the address's bits,
+// remembering the last route it passed, so it takes at most 32 (or 128) steps no matter how
+// many routes there are.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::enums::{Cidr, IpAddrWithTypes, IpV4Addr, IpV6Addr};
+use crate::fib::format_duration;
+use crate::workout::Rng;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    pub cidr: Cidr,
+    // directly connected networks don't need a gateway, only an interface
+    pub next_hop: Option<IpAddrWithTypes>,
+    pub interface: Option<String>,
+}
+
+// written the same way the route file has them
+impl fmt::Display for Route {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.cidr)?;
+        if let Some(next_hop) = &self.next_hop {
+            write!(f, " via {}", next_hop)?;
+        }
+        if let Some(interface) = &self.interface {
+            write!(f, " dev {}", interface)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct TrieNode {
+    // indexes into Trie::nodes, for a 0 bit and a 1 bit
+    children: [Option<usize>; 2],
+    route: Option<Route>,
+}
+
+// The nodes are kept in a Vec and point at each other by index, which saves a Box per node and
+// keeps them close together in memory. Nodes freed by remove go on a list to be reused.
+struct Trie {
+    width: u8,
+    nodes: Vec<TrieNode>,
+    free: Vec<usize>,
+}
+
+impl Trie {
+    fn new(width: u8) -> Trie {
+        Trie { width, nodes: vec![TrieNode::default()], free: Vec::new() }
+    }
+
+    // the bit of the address at depth i, counting from the most significant
+    fn bit(&self, bits: u128, i: u8) -> usize {
+        ((bits >> (self.width - 1 - i)) & 1) as usize
+    }
+
+    fn new_node(&mut self) -> usize {
+        match self.free.pop() {
+            Some(index) => index,
+            None => {
+                self.nodes.push(TrieNode::default());
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    fn insert(&mut self, route: Route) -> Option<Route> {
+        let bits = route.cidr.network().bits();
+        let mut node = 0;
+        for i in 0..route.cidr.prefix() {
+            let bit = self.bit(bits, i);
+            node = match self.nodes[node].children[bit] {
+                Some(child) => child,
+                None => {
+                    let child = self.new_node();
+                    self.nodes[node].children[bit] = Some(child);
+                    child
+                }
+            };
+        }
+        self.nodes[node].route.replace(route)
+    }
+
+    fn remove(&mut self, cidr: &Cidr) -> Option<Route> {
+        let bits = cidr.network().bits();
+        // the nodes on the way down, with the bit taken to leave each one
+        let mut path = Vec::with_capacity(usize::from(cidr.prefix()));
+        let mut node = 0;
+        for i in 0..cidr.prefix() {
+            let bit = self.bit(bits, i);
+            path.push((node, bit));
+            node = self.nodes[node].children[bit]?;
+        }
+        let removed = self.nodes[node].route.take()?;
+        // nodes left with no route and nothing below them are dead weight, unlink them on the
+        // way back up. The root always stays
+        while let Some((parent, bit)) = path.pop() {
+            let dead = &self.nodes[node];
+            if dead.route.is_some() || dead.children.iter().any(Option::is_some) {
+                break;
+            }
+            self.nodes[parent].children[bit] = None;
+            self.free.push(node);
+            node = parent;
+        }
+        Some(removed)
+    }
+
+    fn get(&self, cidr: &Cidr) -> Option<&Route> {
+        let bits = cidr.network().bits();
+        let mut node = 0;
+        for i in 0..cidr.prefix() {
+            node = self.nodes[node].children[self.bit(bits, i)]?;
+        }
+        self.nodes[node].route.as_ref()
+    }
+
+    fn lookup(&self, bits: u128) -> Option<&Route> {
+        let mut node = 0;
+        let mut best = self.nodes[0].route.as_ref();
+        for i in 0..self.width {
+            match self.nodes[node].children[self.bit(bits, i)] {
+                Some(child) => node = child,
+                None => break,
+            }
+            if let Some(route) = &self.nodes[node].route {
+                best = Some(route);
+            }
+        }
+        best
+    }
+
+    // every route, shortest prefixes first along each branch, left branch before right
+    fn routes(&self) -> Vec<&Route> {
+        let mut routes = Vec::new();
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+            routes.extend(node.route.as_ref());
+            // right pushed first so left comes off the stack first
+            stack.extend(node.children.iter().rev().flatten());
+        }
+        routes
+    }
+}
+
+pub struct RoutingTable {
+    v4: Trie,
+    v6: Trie,
+    len: usize,
+}
+
+#[derive(Debug)]
+pub enum RouteFileError {
+    Io(io::Error),
+    Line { line: usize, reason: String },
+}
+
+impl fmt::Display for RouteFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RouteFileError::Io(e) => write!(f, "{}", e),
+            RouteFileError::Line { line, reason } => write!(f, "line {}: {}", line, reason),
+        }
+    }
+}
+
+impl From<io::Error> for RouteFileError {
+    fn from(e: io::Error) -> RouteFileError {
+        RouteFileError::Io(e)
+    }
+}
+
+impl RoutingTable {
+    pub fn new() -> RoutingTable {
+        RoutingTable { v4: Trie::new(32), v6: Trie::new(128), len: 0 }
+    }
+
+    fn trie(&self, width: u8) -> &Trie {
+        if width == 32 { &self.v4 } else { &self.v6 }
+    }
+
+    fn trie_mut(&mut self, width: u8) -> &mut Trie {
+        if width == 32 { &mut self.v4 } else { &mut self.v6 }
+    }
+
+    // replaces and returns any route that was already there for the same block
+    pub fn insert(&mut self, route: Route) -> Option<Route> {
+        let old = self.trie_mut(route.cidr.network().width()).insert(route);
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    pub fn remove(&mut self, cidr: &Cidr) -> Option<Route> {
+        let removed = self.trie_mut(cidr.network().width()).remove(cidr);
+        if removed.is_some() {
+            self.len -= 1;
+        }
+        removed
+    }
+
+    // the route for exactly this block, not whichever would be used for its addresses
+    pub fn get(&self, cidr: &Cidr) -> Option<&Route> {
+        self.trie(cidr.network().width()).get(cidr)
+    }
+
+    // the most specific route that covers the address
+    pub fn lookup(&self, addr: IpAddrWithTypes) -> Option<&Route> {
+        self.trie(addr.width()).lookup(addr.bits())
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // v4 routes then v6, in address order
+    pub fn routes(&self) -> Vec<&Route> {
+        let mut routes = self.v4.routes();
+        routes.extend(self.v6.routes());
+        routes
+    }
+
+    // One route per line, like `ip route` prints them:
+    //   10.0.0.0/8 via 192.168.1.1 dev eth0
+    //   192.168.1.0/24 dev eth0
+    // Blank lines and lines starting with # are skipped. A later line for the same block
+    // replaces an earlier one.
+    pub fn from_text(text: &str) -> Result<RoutingTable, RouteFileError> {
+        let mut table = RoutingTable::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let route = parse_route(line).map_err(|reason| RouteFileError::Line { line: i + 1, reason })?;
+            table.insert(route);
+        }
+        Ok(table)
+    }
+
+    pub fn load(path: &Path) -> Result<RoutingTable, RouteFileError> {
+        RoutingTable::from_text(&fs::read_to_string(path)?)
+    }
+
+    pub fn to_text(&self) -> String {
+        self.routes().iter().map(|route| format!("{}\n", route)).collect()
+    }
+}
+
+fn parse_route(line: &str) -> Result<Route, String> {
+    let mut words = line.split_whitespace();
+    let cidr = words.next().unwrap();
+    let cidr: Cidr = cidr.parse().map_err(|e| format!("{}: {}", cidr, e))?;
+    let mut route = Route { cidr, next_hop: None, interface: None };
+    while let Some(word) = words.next() {
+        let value = words.next().ok_or_else(|| format!("'{}' expects a value", word))?;
+        match word {
+            "via" if route.next_hop.is_none() => {
+                route.next_hop = Some(value.parse().map_err(|e| format!("{}: {}", value, e))?);
+            }
+            "dev" if route.interface.is_none() => route.interface = Some(value.to_string()),
+            "via" | "dev" => return Err(format!("'{}' given twice", word)),
+            _ => return Err(format!("unknown option '{}', expected via or dev", word)),
+        }
+    }
+    if route.next_hop.is_none() && route.interface.is_none() {
+        return Err(String::from("a route needs a via or a dev"));
+    }
+    Ok(route)
+}
+
+const USAGE: &str = "usage: learn_rust route <file> <address>...";
+
+// the route subcommand, looks addresses up in a route file. Ok(false) if any had no route
+pub fn command(args: &[String]) -> Result<bool, String> {
+    let (path, addresses) = match args {
+        [path, addresses @ ..] if !addresses.is_empty() => (path, addresses),
+        _ => return Err(USAGE.to_string()),
+    };
+    let table = RoutingTable::load(Path::new(path)).map_err(|e| format!("{}: {}", path, e))?;
+    let mut all_routed = true;
+    for address in addresses {
+        let addr: IpAddrWithTypes = address.parse().map_err(|e| format!("{}: {}", address, e))?;
+        match table.lookup(addr) {
+            Some(route) => println!("{} -> {}", addr, route),
+            None => {
+                println!("{} -> no route", addr);
+                all_routed = false;
+            }
+        }
+    }
+    Ok(all_routed)
+}
+
+// what the trie saves us from, checking every route
+fn linear_lookup(routes: &[Route], addr: IpAddrWithTypes) -> Option<&Route> {
+    routes.iter().filter(|route| route.cidr.contains(addr)).max_by_key(|route| route.cidr.prefix())
+}
+
+// a made up table that looks a bit like a real one: mostly v4, mostly /16 to /24
+fn random_route(rng: &mut Rng) -> Route {
+    let cidr = if rng.below(5) == 0 {
+        // somewhere in 2000::/3, where the global v6 addresses are
+        let bits = (0b001 << 125) | (u128::from(rng.next_u64()) << 64 | u128::from(rng.next_u64())) >> 3;
+        Cidr::new(IpAddrWithTypes::V6(IpV6Addr::from(bits)), 16 + rng.below(49) as u8)
+    } else {
+        let prefix = match rng.below(10) {
+            0 => 8 + rng.below(8),
+            1 => 25 + rng.below(8),
+            _ => 16 + rng.below(9),
+        };
+        Cidr::new(IpAddrWithTypes::V4(IpV4Addr::from(rng.next_u64() as u32)), prefix as u8)
+    };
+    let interface = format!("eth{}", rng.below(4));
+    Route { cidr: cidr.unwrap(), next_hop: None, interface: Some(interface) }
+}
+
+fn random_address(rng: &mut Rng) -> IpAddrWithTypes {
+    if rng.below(5) == 0 {
+        let bits = (0b001 << 125) | (u128::from(rng.next_u64()) << 64 | u128::from(rng.next_u64())) >> 3;
+        IpAddrWithTypes::V6(IpV6Addr::from(bits))
+    } else {
+        IpAddrWithTypes::V4(IpV4Addr::from(rng.next_u64() as u32))
+    }
+}
+
+// Times building a table of each size and looking addresses up in it, against a linear scan
+// of the same routes. The scan gets far fewer lookups, it's thousands of times slower.
+pub fn bench(sizes: &[usize]) {
+    const TRIE_LOOKUPS: u32 = 1_000_000;
+    const SCAN_LOOKUPS: u32 = 200;
+    println!("{:>10} {:>15} {:>15} {:>15}", "routes", "build", "trie lookup", "linear lookup");
+    for &size in sizes {
+        let mut rng = Rng::new(size as u64);
+        let routes: Vec<Route> = (0..size).map(|_| random_route(&mut rng)).collect();
+
+        let start = Instant::now();
+        let mut table = RoutingTable::new();
+        for route in &routes {
+            table.insert(route.clone());
+        }
+        let build = start.elapsed();
+
+        let addresses: Vec<IpAddrWithTypes> = (0..TRIE_LOOKUPS).map(|_| random_address(&mut rng)).collect();
+        let start = Instant::now();
+        let found = addresses.iter().filter(|&&addr| table.lookup(addr).is_some()).count();
+        let trie = start.elapsed() / TRIE_LOOKUPS;
+
+        // the scan keeps the first of several routes for the same block and the table keeps
+        // the last, so only the blocks are compared
+        let start = Instant::now();
+        for &addr in &addresses[..SCAN_LOOKUPS as usize] {
+            let expected = linear_lookup(&routes, addr).map(|route| route.cidr);
+            assert_eq!(table.lookup(addr).map(|route| route.cidr), expected, "trie disagrees for {}", addr);
+        }
+        let linear = start.elapsed() / SCAN_LOOKUPS;
+
+        println!("{:>10} {:>15} {:>15} {:>15}", size, format_duration(build), format_duration(trie), format_duration(linear));
+        // keeps the lookups from being optimised away
+        assert!(found <= addresses.len());
+    }
+}
+
+pub fn this_main() {
+    let text = "\
+# the default route, for anything more specific routes don't cover
+0.0.0.0/0        via 192.168.1.254 dev eth0
+10.0.0.0/8       via 192.168.1.1   dev eth0
+10.1.0.0/16      via 192.168.1.2   dev eth0
+10.1.2.0/24      dev eth1
+192.168.1.0/24   dev eth0
+2001:db8::/32    via fe80::1       dev eth2
+2001:db8:1::/48  dev eth3
+";
+    let mut table = RoutingTable::from_text(text).unwrap();
+    let addresses = ["10.1.2.3", "10.1.3.3", "8.8.8.8", "2001:db8:1::5", "2002::1"];
+    let show = |table: &RoutingTable| {
+        for address in &addresses {
+            match table.lookup(address.parse().unwrap()) {
+                Some(route) => println!("{} -> {}", address, route),
+                None => println!("{} -> no route", address),
+            }
+        }
+    };
+    show(&table);
+
+    // removing a route sends its addresses to the next most specific one
+    let removed = table.remove(&"10.1.0.0/16".parse().unwrap()).unwrap();
+    println!("removed {}", removed);
+    show(&table);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TABLE: &str = "\
+# the default route, for anything more specific routes don't cover
+0.0.0.0/0        via 192.168.1.254 dev eth0
+10.0.0.0/8       via 192.168.1.1   dev eth0
+10.1.0.0/16      via 192.168.1.2   dev eth0
+10.1.2.0/24      dev eth1
+192.168.1.0/24   dev eth0
+2001:db8::/32    via fe80::1       dev eth2
+2001:db8:1::/48  dev eth3
+";
+
+    fn lookup(table: &RoutingTable, addr: &str) -> Option<String> {
+        table.lookup(addr.parse().unwrap()).map(|route| route.cidr.to_string())
+    }
+
+    fn cidr(text: &str) -> Cidr {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn longest_prefix_match() {
+        let table = RoutingTable::from_text(TABLE).unwrap();
+        assert_eq!(table.len(), 7);
+        assert_eq!(lookup(&table, "10.1.2.3").as_deref(), Some("10.1.2.0/24"));
+        assert_eq!(lookup(&table, "10.1.3.3").as_deref(), Some("10.1.0.0/16"));
+        assert_eq!(lookup(&table, "10.200.0.1").as_deref(), Some("10.0.0.0/8"));
+        assert_eq!(lookup(&table, "8.8.8.8").as_deref(), Some("0.0.0.0/0"));
+        assert_eq!(lookup(&table, "2001:db8:1::5").as_deref(), Some("2001:db8:1::/48"));
+        assert_eq!(lookup(&table, "2001:db8:2::5").as_deref(), Some("2001:db8::/32"));
+        // there's no v6 default route, and v4 routes don't apply to v6 addresses
+        assert_eq!(lookup(&table, "2002::1"), None);
+        assert_eq!(table.lookup("10.1.2.3".parse().unwrap()).unwrap().interface.as_deref(), Some("eth1"));
+    }
+
+    // the whole address is a route too
+    #[test]
+    fn host_routes() {
+        let mut table = RoutingTable::new();
+        table.insert(parse_route("10.0.0.1/32 dev lo").unwrap());
+        table.insert(parse_route("::1/128 dev lo").unwrap());
+        assert_eq!(lookup(&table, "10.0.0.1").as_deref(), Some("10.0.0.1/32"));
+        assert_eq!(lookup(&table, "10.0.0.0"), None);
+        assert_eq!(lookup(&table, "::1").as_deref(), Some("::1/128"));
+        assert_eq!(lookup(&table, "::2"), None);
+    }
+
+    #[test]
+    fn get_is_exact() {
+        let table = RoutingTable::from_text(TABLE).unwrap();
+        assert_eq!(table.get(&cidr("10.1.0.0/16")).unwrap().next_hop, Some("192.168.1.2".parse().unwrap()));
+        assert_eq!(table.get(&cidr("10.1.0.0/17")), None);
+        assert_eq!(table.get(&cidr("10.1.0.0/15")), None);
+    }
+
+    #[test]
+    fn insert_replaces_the_same_block() {
+        let mut table = RoutingTable::new();
+        assert_eq!(table.insert(parse_route("10.0.0.0/8 dev eth0").unwrap()), None);
+        let old = table.insert(parse_route("10.0.0.0/8 dev eth1").unwrap()).unwrap();
+        assert_eq!(old.interface.as_deref(), Some("eth0"));
+        assert_eq!(table.len(), 1);
+        assert_eq!(table.get(&cidr("10.0.0.0/8")).unwrap().interface.as_deref(), Some("eth1"));
+    }
+
+    // removing a route sends its addresses to the next most specific one
+    #[test]
+    fn remove_falls_back_to_a_shorter_prefix() {
+        let mut table = RoutingTable::from_text(TABLE).unwrap();
+        let removed = table.remove(&cidr("10.1.0.0/16")).unwrap();
+        assert_eq!(removed.next_hop, Some("192.168.1.2".parse().unwrap()));
+        assert_eq!(lookup(&table, "10.1.3.3").as_deref(), Some("10.0.0.0/8"));
+        // the more specific route below it is still there
+        assert_eq!(lookup(&table, "10.1.2.3").as_deref(), Some("10.1.2.0/24"));
+        assert_eq!(table.remove(&cidr("10.1.0.0/16")), None);
+        assert_eq!(table.remove(&cidr("10.9.0.0/16")), None);
+        assert_eq!(table.len(), 6);
+    }
+
+    // dead nodes go on the free list and the next insert takes them back
+    #[test]
+    fn removed_nodes_are_reused() {
+        let mut table = RoutingTable::new();
+        table.insert(parse_route("10.0.0.0/8 dev eth0").unwrap());
+        table.insert(parse_route("10.1.2.0/24 dev eth1").unwrap());
+        let nodes = table.v4.nodes.len();
+        assert_eq!(nodes, 25);
+
+        // only the 16 nodes below the /8 are dead, the /8's own node still has a route
+        table.remove(&cidr("10.1.2.0/24")).unwrap();
+        assert_eq!(table.v4.free.len(), 16);
+        table.insert(parse_route("10.200.0.0/16 dev eth2").unwrap());
+        assert_eq!(table.v4.free.len(), 8);
+        assert_eq!(table.v4.nodes.len(), nodes);
+        assert_eq!(lookup(&table, "10.200.1.1").as_deref(), Some("10.200.0.0/16"));
+
+        table.remove(&cidr("10.200.0.0/16")).unwrap();
+        table.remove(&cidr("10.0.0.0/8")).unwrap();
+        assert!(table.is_empty());
+        assert_eq!(table.v4.free.len(), nodes - 1);
+        assert_eq!(lookup(&table, "10.200.1.1"), None);
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let table = RoutingTable::from_text(TABLE).unwrap();
+        let text = table.to_text();
+        assert_eq!(text.lines().next(), Some("0.0.0.0/0 via 192.168.1.254 dev eth0"));
+        assert_eq!(text.lines().last(), Some("2001:db8:1::/48 dev eth3"));
+        let again = RoutingTable::from_text(&text).unwrap();
+        assert_eq!(again.to_text(), text);
+        assert_eq!(again.routes(), table.routes());
+    }
+
+    // bad lines say which line and what's wrong
+    #[test]
+    fn from_text_errors() {
+        let bad = [
+            ("10.0.0.0/8 via 1.2.3.4\n10.0.0.0 dev eth0", 2, "10.0.0.0: expected <address>/<prefix length>"),
+            ("10.0.0.0/8", 1, "a route needs a via or a dev"),
+            ("\n\n10.0.0.0/8 via", 3, "'via' expects a value"),
+            ("10.0.0.0/8 gw 1.2.3.4", 1, "unknown option 'gw', expected via or dev"),
+            ("10.0.0.0/8 dev a dev b", 1, "'dev' given twice"),
+            ("10.0.0.0/8 via 1.2.3", 1, "1.2.3: expected 4 octets, found 3"),
+        ];
+        for (text, line, reason) in bad {
+            match RoutingTable::from_text(text) {
+                Err(RouteFileError::Line { line: l, reason: r }) => assert_eq!((l, r.as_str()), (line, reason)),
+                other => panic!("expected an error on line {} for {:?}, got {:?}", line, text, other.map(|t| t.len())),
+            }
+        }
+        match RoutingTable::from_text("# fine\n10.0.0.0/8 via") {
+            Err(error) => assert_eq!(error.to_string(), "line 2: 'via' expects a value"),
+            Ok(_) => panic!("a route with no value for via should be an error"),
+        }
+    }
+
+    // lots of random inserts and removes, with every lookup checked against a linear scan
+    #[test]
+    fn random_changes_match_a_linear_scan() {
+        let mut rng = Rng::new(1812);
+        let mut table = RoutingTable::new();
+        let mut routes: Vec<Route> = Vec::new();
+        for _ in 0..3_000 {
+            if rng.below(3) == 0 && !routes.is_empty() {
+                let route = routes.swap_remove(rng.below(routes.len() as u32) as usize);
+                assert_eq!(table.remove(&route.cidr), Some(route));
+            } else {
+                let route = random_route(&mut rng);
+                let old = table.insert(route.clone());
+                assert_eq!(old.is_some(), routes.iter().any(|r| r.cidr == route.cidr));
+                routes.retain(|r| r.cidr != route.cidr);
+                routes.push(route);
+            }
+            assert_eq!(table.len(), routes.len());
+            let addr = random_address(&mut rng);
+            assert_eq!(table.lookup(addr), linear_lookup(&routes, addr));
+            // and an address inside one of the routes, a random one rarely hits anything
+            if let Some(route) = routes.get(rng.below(routes.len().max(1) as u32) as usize) {
+                let addr = route.cidr.network();
+                assert_eq!(table.lookup(addr), linear_lookup(&routes, addr));
+            }
+        }
+        // the trie gives its dead nodes back for reuse
+        for route in routes.drain(..) {
+            table.remove(&route.cidr);
+        }
+        assert!(table.is_empty());
+        assert_eq!(table.v4.nodes.len(), table.v4.free.len() + 1);
+        assert_eq!(table.v6.nodes.len(), table.v6.free.len() + 1);
+    }
+}
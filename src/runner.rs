@@ -0,0 +1,219 @@
+// Runs lessons behind a panic boundary so one failing chapter doesn't stop the rest.
+// Some chapters panic on purpose (indexing past the end of a vector or array), those
+// are flagged in the registry and reported as an expected panic rather than a failure.
+
+use std::any::Any;
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::panic;
+
+use crate::lessons::Lesson;
+
+pub struct PanicReport {
+    pub message: String,
+    pub location: Option<String>,
+    pub backtrace: Option<String>,
+}
+
+pub enum Outcome {
+    Passed,
+    ExpectedPanic(PanicReport),
+    UnexpectedPanic(PanicReport),
+    // the lesson was flagged as panicking but returned normally
+    MissingPanic,
+}
+
+impl Outcome {
+    pub fn is_ok(&self) -> bool {
+        matches!(self, Outcome::Passed | Outcome::ExpectedPanic(_))
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Outcome::Passed => "pass",
+            Outcome::ExpectedPanic(_) => "expected panic",
+            Outcome::UnexpectedPanic(_) => "unexpected panic",
+            Outcome::MissingPanic => "missing panic",
+        }
+    }
+
+    fn panic_report(&self) -> Option<&PanicReport> {
+        match self {
+            Outcome::ExpectedPanic(report) | Outcome::UnexpectedPanic(report) => Some(report),
+            _ => None,
+        }
+    }
+}
+
+pub struct LessonResult {
+    pub name: &'static str,
+    pub outcome: Outcome,
+}
+
+pub struct Report {
+    pub results: Vec<LessonResult>,
+}
+
+pub enum Format {
+    Text,
+    Json,
+}
+
+thread_local! {
+    // the hook runs on the panicking thread, before unwinding reaches catch_unwind
+    static LAST_PANIC: RefCell<Option<PanicReport>> = const { RefCell::new(None) };
+}
+
+pub fn run(lessons: &[&Lesson]) -> Report {
+    // swap out the default hook, which prints straight to stderr, for one that records
+    // the panic so it can go in the report
+    let previous_hook = panic::take_hook();
+    panic::set_hook(Box::new(|info| {
+        let message = payload_message(info.payload());
+        let location = info
+            .location()
+            .map(|l| format!("{}:{}:{}", l.file(), l.line(), l.column()));
+        // only captured when RUST_BACKTRACE or RUST_LIB_BACKTRACE is set
+        let backtrace = Backtrace::capture();
+        let backtrace = match backtrace.status() {
+            BacktraceStatus::Captured => Some(backtrace.to_string()),
+            _ => None,
+        };
+        LAST_PANIC.with(|p| {
+            *p.borrow_mut() = Some(PanicReport { message, location, backtrace })
+        });
+    }));
+
+    let mut results = Vec::new();
+    for lesson in lessons {
+        println!("== {} ==", lesson.name);
+        // a panic caught inside an earlier lesson still leaves its report behind
+        LAST_PANIC.with(|p| p.borrow_mut().take());
+        let outcome = match panic::catch_unwind(lesson.entry) {
+            Ok(()) if lesson.expects_panic => Outcome::MissingPanic,
+            Ok(()) => Outcome::Passed,
+            Err(payload) => {
+                // the hook only sees panics on this thread. One from another thread that was
+                // passed on with resume_unwind skips the hook, but its payload still says why
+                let report = LAST_PANIC.with(|p| p.borrow_mut().take()).unwrap_or_else(|| PanicReport {
+                    message: payload_message(payload.as_ref()),
+                    location: None,
+                    backtrace: None,
+                });
+                if lesson.expects_panic {
+                    Outcome::ExpectedPanic(report)
+                } else {
+                    Outcome::UnexpectedPanic(report)
+                }
+            }
+        };
+        println!();
+        results.push(LessonResult { name: lesson.name, outcome });
+    }
+
+    panic::set_hook(previous_hook);
+    Report { results }
+}
+
+// panic!("...") and panic!("{}", x) give a &str or a String, anything else can't be shown
+fn payload_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        String::from("<non-string panic payload>")
+    }
+}
+
+impl Report {
+    pub fn is_ok(&self) -> bool {
+        self.results.iter().all(|r| r.outcome.is_ok())
+    }
+
+    pub fn render(&self, format: &Format) -> String {
+        match format {
+            Format::Text => self.to_text(),
+            Format::Json => self.to_json(),
+        }
+    }
+
+    fn to_text(&self) -> String {
+        let mut out = String::from("== summary ==\n");
+        for result in &self.results {
+            let status = if result.outcome.is_ok() { "pass" } else { "FAIL" };
+            writeln!(out, "{:<5} {} ({})", status, result.name, result.outcome.label()).unwrap();
+            if let Some(report) = result.outcome.panic_report() {
+                writeln!(out, "      message:  {}", report.message).unwrap();
+                if let Some(location) = &report.location {
+                    writeln!(out, "      location: {}", location).unwrap();
+                }
+                if let Some(backtrace) = &report.backtrace {
+                    writeln!(out, "      backtrace:").unwrap();
+                    for line in backtrace.lines() {
+                        writeln!(out, "        {}", line).unwrap();
+                    }
+                }
+            }
+        }
+        let failed = self.results.iter().filter(|r| !r.outcome.is_ok()).count();
+        write!(out, "{} passed, {} failed", self.results.len() - failed, failed).unwrap();
+        out
+    }
+
+    fn to_json(&self) -> String {
+        let mut out = String::from("{\"results\":[");
+        for (i, result) in self.results.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            write!(
+                out,
+                "{{\"name\":{},\"outcome\":{},\"ok\":{}",
+                json_string(result.name),
+                json_string(result.outcome.label()),
+                result.outcome.is_ok()
+            )
+            .unwrap();
+            if let Some(report) = result.outcome.panic_report() {
+                write!(
+                    out,
+                    ",\"panic\":{{\"message\":{},\"location\":{},\"backtrace\":{}}}",
+                    json_string(&report.message),
+                    json_optional(&report.location),
+                    json_optional(&report.backtrace)
+                )
+                .unwrap();
+            }
+            out.push('}');
+        }
+        write!(out, "],\"ok\":{}}}", self.is_ok()).unwrap();
+        out
+    }
+}
+
+fn json_optional(value: &Option<String>) -> String {
+    match value {
+        Some(s) => json_string(s),
+        None => String::from("null"),
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
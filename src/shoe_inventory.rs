@@ -0,0 +1,467 @@
+// An inventory of shoes that can be searched without giving up ownership of them.
+// shoes_in_size in the iterators chapter takes the Vec by value and can only match one
+// size, here every query borrows the store and hands back references, so it can be asked
+// as many questions as we like.
+//
+// Queries can be built in code, or parsed from a short text form, e.g.
+//     size>=8 size<11 style~boot sort:-size page:2 per_page:5
+// size takes =, <, <=, > and >=. style=x matches a style exactly and style~x matches any
+// style containing x, both ignoring case. sort: takes size or style, with a leading - to
+// sort descending, styles sort ignoring case too. page: is counted from 1 and defaults to 10 results per page.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::{Bound, RangeBounds};
+
+use crate::iterators::Shoe;
+
+const DEFAULT_PER_PAGE: usize = 10;
+
+#[derive(Debug, Default)]
+pub struct Inventory {
+    shoes: Vec<Shoe>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortKey {
+    Size,
+    Style,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum StyleMatch {
+    Exact(String),
+    Contains(String),
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Query {
+    // inclusive bounds, a missing bound means no limit
+    min_size: Option<u32>,
+    max_size: Option<u32>,
+    style: Option<StyleMatch>,
+    sort: Option<(SortKey, bool)>, // the bool is true for descending
+    page: Option<usize>,           // counted from 0
+    per_page: usize,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct QueryError(pub String);
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid query: {}", self.0)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct CsvError {
+    pub line: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for CsvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+impl Query {
+    pub fn new() -> Query {
+        Query { per_page: DEFAULT_PER_PAGE, ..Query::default() }
+    }
+
+    pub fn size(mut self, range: impl RangeBounds<u32>) -> Query {
+        self.min_size = None;
+        self.max_size = None;
+        self = match range.start_bound() {
+            Bound::Included(&n) => self.tighten_size(">=", n),
+            Bound::Excluded(&n) => self.tighten_size(">", n),
+            Bound::Unbounded => self,
+        };
+        match range.end_bound() {
+            Bound::Included(&n) => self.tighten_size("<=", n),
+            Bound::Excluded(&n) => self.tighten_size("<", n),
+            Bound::Unbounded => self,
+        }
+    }
+
+    pub fn style_is(mut self, style: &str) -> Query {
+        self.style = Some(StyleMatch::Exact(style.to_lowercase()));
+        self
+    }
+
+    pub fn style_contains(mut self, text: &str) -> Query {
+        self.style = Some(StyleMatch::Contains(text.to_lowercase()));
+        self
+    }
+
+    pub fn sort_by(mut self, key: SortKey, descending: bool) -> Query {
+        self.sort = Some((key, descending));
+        self
+    }
+
+    // page is counted from 0
+    pub fn page(mut self, page: usize, per_page: usize) -> Query {
+        self.page = Some(page);
+        self.per_page = per_page;
+        self
+    }
+
+    pub fn parse(text: &str) -> Result<Query, QueryError> {
+        let mut query = Query::new();
+        let mut page = None;
+        for token in text.split_whitespace() {
+            if let Some(rest) = token.strip_prefix("size") {
+                let (op, n) = split_operator(rest)
+                    .ok_or_else(|| QueryError(format!("'{}' needs one of = < <= > >=", token)))?;
+                let n: u32 = n
+                    .parse()
+                    .map_err(|_| QueryError(format!("'{}' is not a valid size", n)))?;
+                query = query.tighten_size(op, n);
+            } else if let Some(style) = token.strip_prefix("style=") {
+                query = query.style_is(style);
+            } else if let Some(style) = token.strip_prefix("style~") {
+                query = query.style_contains(style);
+            } else if let Some(key) = token.strip_prefix("sort:") {
+                let (key, descending) = match key.strip_prefix('-') {
+                    Some(key) => (key, true),
+                    None => (key, false),
+                };
+                let key = match key {
+                    "size" => SortKey::Size,
+                    "style" => SortKey::Style,
+                    _ => return Err(QueryError(format!("can't sort by '{}'", key))),
+                };
+                query = query.sort_by(key, descending);
+            } else if let Some(n) = token.strip_prefix("page:") {
+                page = match n.parse::<usize>() {
+                    Ok(n) if n > 0 => Some(n - 1),
+                    _ => return Err(QueryError(format!("'{}' is not a page number", n))),
+                };
+            } else if let Some(n) = token.strip_prefix("per_page:") {
+                query.per_page = match n.parse() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err(QueryError(format!("'{}' is not a page size", n))),
+                };
+            } else {
+                return Err(QueryError(format!("unknown term '{}'", token)));
+            }
+        }
+        query.page = page;
+        Ok(query)
+    }
+
+    // narrows the size bounds, so size>=8 size<11 keeps both limits
+    fn tighten_size(mut self, op: &str, n: u32) -> Query {
+        // size<0 or size>4294967295 can't match anything, a minimum above the maximum
+        // makes sure nothing does
+        const NOTHING: (Option<u32>, Option<u32>) = (Some(1), Some(0));
+        let (min, max) = match op {
+            "=" => (Some(n), Some(n)),
+            ">=" => (Some(n), None),
+            "<=" => (None, Some(n)),
+            ">" => n.checked_add(1).map_or(NOTHING, |n| (Some(n), None)),
+            _ => n.checked_sub(1).map_or(NOTHING, |n| (None, Some(n))),
+        };
+        if let Some(min) = min {
+            self.min_size = Some(self.min_size.map_or(min, |m| m.max(min)));
+        }
+        if let Some(max) = max {
+            self.max_size = Some(self.max_size.map_or(max, |m| m.min(max)));
+        }
+        self
+    }
+
+    fn matches(&self, shoe: &Shoe) -> bool {
+        let style = shoe.style.to_lowercase();
+        self.min_size.is_none_or(|min| shoe.size >= min)
+            && self.max_size.is_none_or(|max| shoe.size <= max)
+            && match &self.style {
+                None => true,
+                Some(StyleMatch::Exact(s)) => style == *s,
+                Some(StyleMatch::Contains(s)) => style.contains(s.as_str()),
+            }
+    }
+}
+
+// compares styles without allocating a lowercase copy of each one for every comparison
+fn lowercase(style: &str) -> impl Iterator<Item = char> + '_ {
+    style.chars().flat_map(char::to_lowercase)
+}
+
+// splits "<=8" into ("<=", "8"), two character operators are checked first
+fn split_operator(s: &str) -> Option<(&str, &str)> {
+    ["<=", ">=", "=", "<", ">"]
+        .iter()
+        .find(|op| s.starts_with(*op))
+        .map(|op| (*op, &s[op.len()..]))
+}
+
+impl Inventory {
+    pub fn new() -> Inventory {
+        Inventory::default()
+    }
+
+    pub fn add(&mut self, shoe: Shoe) {
+        self.shoes.push(shoe);
+    }
+
+    pub fn len(&self) -> usize {
+        self.shoes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shoes.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Shoe> {
+        self.shoes.iter()
+    }
+
+    // the borrowing version of shoes_in_size, generalised to a range
+    pub fn in_sizes(&self, range: impl RangeBounds<u32>) -> impl Iterator<Item = &Shoe> {
+        self.shoes.iter().filter(move |s| range.contains(&s.size))
+    }
+
+    pub fn query(&self, query: &Query) -> Vec<&Shoe> {
+        let mut found: Vec<&Shoe> = self.shoes.iter().filter(|s| query.matches(s)).collect();
+        if let Some((key, descending)) = query.sort {
+            // sort_by is stable, so shoes that compare equal keep their inventory order
+            found.sort_by(|a, b| {
+                let ordering = match key {
+                    SortKey::Size => a.size.cmp(&b.size),
+                    SortKey::Style => lowercase(&a.style).cmp(lowercase(&b.style)),
+                };
+                if descending { ordering.reverse() } else { ordering }
+            });
+        }
+        match query.page {
+            // saturating, a page far past the end is just empty
+            Some(page) => found.into_iter().skip(page.saturating_mul(query.per_page)).take(query.per_page).collect(),
+            None => found,
+        }
+    }
+
+    // styles are counted ignoring case, like a query matches them, so Boot and boot are
+    // one style. The keys are lowercase
+    pub fn count_by_style(&self) -> BTreeMap<String, usize> {
+        let mut counts = BTreeMap::new();
+        for shoe in &self.shoes {
+            *counts.entry(shoe.style.to_lowercase()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    // a header line, then one "size,style" record per shoe. Styles containing commas, quotes
+    // or line breaks are wrapped in quotes with any quotes doubled, as most spreadsheets
+    // expect, so a record can run over more than one line
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("size,style\n");
+        for shoe in &self.shoes {
+            let style = if shoe.style.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", shoe.style.replace('"', "\"\""))
+            } else {
+                shoe.style.clone()
+            };
+            csv.push_str(&format!("{},{}\n", shoe.size, style));
+        }
+        csv
+    }
+
+    pub fn from_csv(csv: &str) -> Result<Inventory, CsvError> {
+        let mut records = records(csv);
+        match records.next() {
+            Some(Ok((_, header))) if header.trim() == "size,style" => {}
+            _ => return Err(CsvError { line: 1, reason: String::from("expected a size,style header") }),
+        }
+        let mut inventory = Inventory::new();
+        for record in records {
+            let (line, record) = record?;
+            if record.trim().is_empty() {
+                continue;
+            }
+            let error = |reason: &str| CsvError { line, reason: reason.to_string() };
+            let (size, style) = record.split_once(',').ok_or_else(|| error("expected two fields"))?;
+            let size = size.trim().parse().map_err(|_| error("size is not a number"))?;
+            let style = parse_csv_field(style).ok_or_else(|| error("badly quoted style"))?;
+            inventory.add(Shoe { size, style });
+        }
+        Ok(inventory)
+    }
+}
+
+// Splits csv into records along with the line each one starts on. A record is usually one
+// line, but a line break inside a quoted field belongs to the field, so the record carries
+// on. Doubled quotes come in pairs, so an odd number of quotes so far means we're still
+// inside a quoted field.
+fn records(csv: &str) -> impl Iterator<Item = Result<(usize, String), CsvError>> + '_ {
+    let mut lines = csv.strip_suffix('\n').unwrap_or(csv).split('\n').enumerate();
+    std::iter::from_fn(move || {
+        let (i, first) = lines.next()?;
+        let mut record = first.to_string();
+        while record.matches('"').count() % 2 == 1 {
+            match lines.next() {
+                Some((_, next)) => {
+                    record.push('\n');
+                    record.push_str(next);
+                }
+                None => {
+                    return Some(Err(CsvError { line: i + 1, reason: String::from("quoted style is never closed") }))
+                }
+            }
+        }
+        // a \r\n line ending, any \r inside quotes is kept
+        if record.ends_with('\r') {
+            record.pop();
+        }
+        Some(Ok((i + 1, record)))
+    })
+}
+
+// undoes the quoting done by to_csv
+fn parse_csv_field(field: &str) -> Option<String> {
+    let quoted = match field.strip_prefix('"') {
+        None => return if field.contains('"') { None } else { Some(field.to_string()) },
+        Some(rest) => rest.strip_suffix('"')?,
+    };
+    let mut value = String::new();
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        if c == '"' {
+            // a lone quote inside a quoted field is an error, a doubled one is a quote
+            if chars.next() != Some('"') {
+                return None;
+            }
+        }
+        value.push(c);
+    }
+    Some(value)
+}
+
+pub fn this_main() {
+    let csv = "size,style\n10,sneaker\n13,sandal\n10,boot\n8,Ankle Boot\n9,\"boot, \"\"chelsea\"\"\"\n";
+    let inventory = Inventory::from_csv(csv).unwrap();
+
+    // the same question asked in code and as text
+    let built = Query::new().size(8..11).style_contains("boot").sort_by(SortKey::Size, false);
+    let parsed = Query::parse("size>=8 size<11 style~boot sort:size").unwrap();
+    for shoe in inventory.query(&parsed) {
+        println!("size {} {}", shoe.size, shoe.style);
+    }
+
+    // the inventory is only borrowed, so it can be queried again
+    println!("{} in size 10", inventory.in_sizes(10..=10).count());
+    for (style, count) in inventory.count_by_style() {
+        println!("{}: {}", style, count);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CSV: &str = "size,style\n10,sneaker\n13,sandal\n10,boot\n8,Ankle Boot\n9,\"boot, \"\"chelsea\"\"\"\n";
+
+    fn inventory() -> Inventory {
+        Inventory::from_csv(CSV).unwrap()
+    }
+
+    fn sizes(shoes: &[&Shoe]) -> Vec<u32> {
+        shoes.iter().map(|s| s.size).collect()
+    }
+
+    fn styles<'a>(shoes: &[&'a Shoe]) -> Vec<&'a str> {
+        shoes.iter().map(|s| s.style.as_str()).collect()
+    }
+
+    #[test]
+    fn csv_round_trip_with_quotes() {
+        let inventory = inventory();
+        assert_eq!(inventory.len(), 5);
+        assert_eq!(inventory.iter().last().unwrap().style, "boot, \"chelsea\"");
+        assert_eq!(inventory.to_csv(), CSV);
+    }
+
+    #[test]
+    fn built_and_parsed_queries_agree() {
+        let built = Query::new().size(8..11).style_contains("boot").sort_by(SortKey::Size, false);
+        let parsed = Query::parse("size>=8 size<11 style~boot sort:size").unwrap();
+        assert_eq!(built, parsed);
+        assert_eq!(sizes(&inventory().query(&parsed)), vec![8, 9, 10]);
+    }
+
+    #[test]
+    fn size_and_style_matching() {
+        let inventory = inventory();
+        assert_eq!(inventory.query(&Query::parse("style=BOOT").unwrap()).len(), 1);
+        assert_eq!(inventory.in_sizes(10..=10).count(), 2);
+        assert_eq!(inventory.query(&Query::parse("size<0").unwrap()).len(), 0);
+        assert_eq!(inventory.query(&Query::new().size(..0)).len(), 0);
+        assert_eq!(inventory.query(&Query::parse("size>4294967295").unwrap()).len(), 0);
+    }
+
+    #[test]
+    fn sorting_and_paging() {
+        let query = Query::parse("sort:-size page:2 per_page:2").unwrap();
+        assert_eq!(sizes(&inventory().query(&query)), vec![10, 9]);
+        // stable, the two size 10s keep their inventory order
+        let query = Query::parse("size=10 sort:size").unwrap();
+        assert_eq!(styles(&inventory().query(&query)), vec!["sneaker", "boot"]);
+    }
+
+    #[test]
+    fn bad_queries() {
+        assert_eq!(Query::parse("colour=red"), Err(QueryError(String::from("unknown term 'colour=red'"))));
+        assert_eq!(Query::parse("size~8"), Err(QueryError(String::from("'size~8' needs one of = < <= > >="))));
+        assert_eq!(Query::parse("sort:colour"), Err(QueryError(String::from("can't sort by 'colour'"))));
+        assert_eq!(Query::parse("page:0"), Err(QueryError(String::from("'0' is not a page number"))));
+    }
+
+    // a quoted style can run over a line break, \r\n inside quotes is kept as it is
+    #[test]
+    fn csv_styles_over_line_breaks() {
+        let mut two_lines = Inventory::new();
+        two_lines.add(Shoe { size: 7, style: String::from("boot\nwith a second line") });
+        two_lines.add(Shoe { size: 6, style: String::from("crlf\r\nstyle") });
+        let csv = two_lines.to_csv();
+        assert_eq!(csv, "size,style\n7,\"boot\nwith a second line\"\n6,\"crlf\r\nstyle\"\n");
+        let read_back = Inventory::from_csv(&csv).unwrap();
+        let read_back: Vec<&str> = read_back.iter().map(|s| s.style.as_str()).collect();
+        assert_eq!(read_back, vec!["boot\nwith a second line", "crlf\r\nstyle"]);
+    }
+
+    // and the record after one still has the right line number
+    #[test]
+    fn csv_error_lines() {
+        let error = Inventory::from_csv("size,style\r\n7,\"two\nlines\"\r\nbig,boot\r\n").unwrap_err();
+        assert_eq!(error, CsvError { line: 4, reason: String::from("size is not a number") });
+        let error = Inventory::from_csv("size,style\n7,boot\n8,\"never\nclosed\n").unwrap_err();
+        assert_eq!(error, CsvError { line: 3, reason: String::from("quoted style is never closed") });
+        let error = Inventory::from_csv("size,style\n7 boot\n").unwrap_err();
+        assert_eq!(error, CsvError { line: 2, reason: String::from("expected two fields") });
+        let error = Inventory::from_csv("size,style\n7,bo\"ot\"\n").unwrap_err();
+        assert_eq!(error, CsvError { line: 2, reason: String::from("badly quoted style") });
+        let error = Inventory::from_csv("style,size\n").unwrap_err();
+        assert_eq!(error, CsvError { line: 1, reason: String::from("expected a size,style header") });
+    }
+
+    // styles are grouped and sorted ignoring case, the same way queries match them
+    #[test]
+    fn styles_ignore_case() {
+        let mixed = Inventory::from_csv("size,style\n9,boot\n8,Ankle\n7,Boot\n6,BOOT\n").unwrap();
+        let sorted = mixed.query(&Query::new().sort_by(SortKey::Style, false));
+        assert_eq!(styles(&sorted), vec!["Ankle", "boot", "Boot", "BOOT"]);
+        assert_eq!(mixed.count_by_style().get("boot"), Some(&3));
+        assert_eq!(mixed.count_by_style().len(), 2);
+    }
+
+    // pages far past the end are empty rather than overflowing
+    #[test]
+    fn huge_page_numbers() {
+        let inventory = inventory();
+        assert!(inventory.query(&Query::parse("page:18446744073709551615").unwrap()).is_empty());
+        assert!(inventory.query(&Query::new().page(usize::MAX, 10)).is_empty());
+        assert!(inventory.query(&Query::new().page(usize::MAX / 2, 3)).is_empty());
+    }
+}
@@ -0,0 +1,305 @@
+// A fixed-size pool of worker threads, in the shape of the one from the final chapter of
+// the book. Jobs are boxed closures sent down a channel, and whichever worker is free
+// takes the next one off the shared receiver.
+//
+// On top of execute, which needs 'static jobs, scoped_map runs a batch of jobs that may
+// borrow from the caller's stack and waits for every one to finish before returning.
+// That's what lets par_iter work on borrowed slices.
+
+use std::any::Any;
+use std::cell::Cell;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+pub struct ThreadPool {
+    workers: Vec<Worker>,
+    // wrapped in an Option so drop can close the channel before joining the workers
+    sender: Option<mpsc::Sender<Job>>,
+}
+
+struct Worker {
+    id: usize,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+thread_local! {
+    // set on pool threads, so a job that starts more parallel work runs it inline rather
+    // than queueing behind itself and deadlocking
+    static IS_WORKER: Cell<bool> = const { Cell::new(false) };
+}
+
+impl ThreadPool {
+    // panics if size is zero
+    pub fn new(size: usize) -> ThreadPool {
+        assert!(size > 0, "a thread pool needs at least one thread");
+        let (sender, receiver) = mpsc::channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..size).map(|id| Worker::new(id, Arc::clone(&receiver))).collect();
+        ThreadPool { workers, sender: Some(sender) }
+    }
+
+    // one pool shared by the whole program, with a thread per core
+    pub fn global() -> &'static ThreadPool {
+        static POOL: OnceLock<ThreadPool> = OnceLock::new();
+        POOL.get_or_init(|| {
+            ThreadPool::new(thread::available_parallelism().map_or(4, |n| n.get()))
+        })
+    }
+
+    pub fn size(&self) -> usize {
+        self.workers.len()
+    }
+
+    pub fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.sender.as_ref().unwrap().send(Box::new(f)).unwrap();
+    }
+
+    // Runs every job on the pool and returns their results in the order the jobs were
+    // given. If any job panics, the rest still run to completion and then the first
+    // panic is resumed on the calling thread.
+    pub fn scoped_map<'a, R: Send + 'a>(&self, jobs: Vec<Box<dyn FnOnce() -> R + Send + 'a>>) -> Vec<R> {
+        if IS_WORKER.with(Cell::get) {
+            return jobs.into_iter().map(|job| job()).collect();
+        }
+
+        let count = jobs.len();
+        let (sender, receiver) = mpsc::channel::<(usize, thread::Result<R>)>();
+        for (i, job) in jobs.into_iter().enumerate() {
+            let sender = sender.clone();
+            let job: Box<dyn FnOnce() + Send + 'a> = Box::new(move || {
+                let result = panic::catch_unwind(AssertUnwindSafe(job));
+                // the receiver only goes away once every result has arrived
+                sender.send((i, result)).unwrap();
+            });
+            // SAFETY: the job may borrow data that lives for 'a, which is shorter than
+            // 'static. That's fine because we don't return until every job has sent its
+            // result, so the borrows are all finished before the caller can end 'a.
+            // Panics are caught inside the job, so each one always sends exactly once,
+            // and the workers themselves never die.
+            let job: Job = unsafe { std::mem::transmute::<Box<dyn FnOnce() + Send + 'a>, Job>(job) };
+            self.sender.as_ref().unwrap().send(job).unwrap();
+        }
+        drop(sender);
+
+        let mut results: Vec<Option<R>> = (0..count).map(|_| None).collect();
+        let mut first_panic: Option<(usize, Box<dyn Any + Send>)> = None;
+        for _ in 0..count {
+            let (i, result) = receiver.recv().unwrap();
+            match result {
+                Ok(r) => results[i] = Some(r),
+                Err(payload) => {
+                    if first_panic.as_ref().is_none_or(|(j, _)| i < *j) {
+                        first_panic = Some((i, payload));
+                    }
+                }
+            }
+        }
+        if let Some((_, payload)) = first_panic {
+            panic::resume_unwind(payload);
+        }
+        results.into_iter().map(Option::unwrap).collect()
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        // closing the channel makes recv fail in every worker, which ends its loop
+        drop(self.sender.take());
+        for worker in &mut self.workers {
+            if let Some(thread) = worker.thread.take() {
+                thread.join().unwrap();
+            }
+        }
+    }
+}
+
+impl Worker {
+    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Worker {
+        let thread = thread::Builder::new()
+            .name(format!("pool-worker-{}", id))
+            .spawn(move || {
+                IS_WORKER.with(|w| w.set(true));
+                loop {
+                    // the lock is released at the end of this statement, before the job
+                    // runs, so other workers can pick up jobs in the meantime
+                    let job = receiver.lock().unwrap().recv();
+                    match job {
+                        // a panicking execute job shouldn't take the worker down with it
+                        Ok(job) => {
+                            let _ = panic::catch_unwind(AssertUnwindSafe(job));
+                        }
+                        Err(_) => break,
+                    }
+                }
+            })
+            .unwrap();
+        Worker { id, thread: Some(thread) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    type ScopedJob<'a, R> = Box<dyn FnOnce() -> R + Send + 'a>;
+
+    fn panic_message(payload: Box<dyn Any + Send>) -> String {
+        match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => payload.downcast_ref::<&str>().unwrap().to_string(),
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one thread")]
+    fn no_threads() {
+        ThreadPool::new(0);
+    }
+
+    #[test]
+    fn execute_runs_jobs() {
+        let pool = ThreadPool::new(2);
+        let (sender, receiver) = mpsc::channel();
+        for i in 0..10 {
+            let sender = sender.clone();
+            pool.execute(move || sender.send(i).unwrap());
+        }
+        let mut got: Vec<i32> = receiver.iter().take(10).collect();
+        got.sort();
+        assert_eq!(got, (0..10).collect::<Vec<_>>());
+    }
+
+    // the worker catches the panic and carries on with the next job
+    #[test]
+    fn execute_panics_dont_kill_workers() {
+        let pool = ThreadPool::new(1);
+        pool.execute(|| panic!("job failed"));
+        let (sender, receiver) = mpsc::channel();
+        pool.execute(move || sender.send("still here").unwrap());
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok("still here"));
+    }
+
+    // dropping the pool waits for the jobs already queued
+    #[test]
+    fn drop_finishes_queued_jobs() {
+        let done = Arc::new(AtomicUsize::new(0));
+        let pool = ThreadPool::new(2);
+        for _ in 0..6 {
+            let done = Arc::clone(&done);
+            pool.execute(move || {
+                thread::sleep(Duration::from_millis(10));
+                done.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        drop(pool);
+        assert_eq!(done.load(Ordering::SeqCst), 6);
+    }
+
+    #[test]
+    fn scoped_map_with_no_jobs() {
+        let pool = ThreadPool::new(2);
+        assert_eq!(pool.scoped_map::<u32>(Vec::new()), Vec::<u32>::new());
+    }
+
+    // the early jobs are the slow ones, so they finish last
+    #[test]
+    fn scoped_map_keeps_job_order() {
+        let pool = ThreadPool::new(4);
+        let jobs: Vec<ScopedJob<usize>> = (0..8usize)
+            .map(|i| {
+                Box::new(move || {
+                    thread::sleep(Duration::from_millis(5 * (8 - i as u64)));
+                    i * 10
+                }) as ScopedJob<usize>
+            })
+            .collect();
+        assert_eq!(pool.scoped_map(jobs), vec![0, 10, 20, 30, 40, 50, 60, 70]);
+    }
+
+    // This is what the transmute relies on: the jobs borrow locals, and every one of them has
+    // finished with its borrow by the time scoped_map returns, so the locals can be used (and
+    // dropped) straight after.
+    #[test]
+    fn scoped_map_jobs_borrow_locals() {
+        let pool = ThreadPool::new(3);
+        let words = vec![String::from("a"), String::from("bb"), String::from("ccc")];
+        let seen = Mutex::new(Vec::new());
+        let jobs: Vec<ScopedJob<usize>> = words
+            .iter()
+            .map(|word| {
+                let seen = &seen;
+                Box::new(move || {
+                    thread::sleep(Duration::from_millis(10));
+                    seen.lock().unwrap().push(word.as_str());
+                    word.len()
+                }) as ScopedJob<usize>
+            })
+            .collect();
+        assert_eq!(pool.scoped_map(jobs), vec![1, 2, 3]);
+        let mut seen = seen.into_inner().unwrap();
+        seen.sort();
+        assert_eq!(seen, vec!["a", "bb", "ccc"]);
+        drop(words);
+    }
+
+    // Even when a job panics, scoped_map waits for the rest before resuming the panic, so
+    // none of them is still using a borrow after the caller's frame has gone. The panic that
+    // comes back is from the earliest job, not the first to finish.
+    #[test]
+    fn scoped_map_panics_after_every_job_finishes() {
+        let pool = ThreadPool::new(4);
+        let finished = AtomicUsize::new(0);
+        let jobs: Vec<ScopedJob<()>> = (0..8)
+            .map(|i| {
+                let finished = &finished;
+                Box::new(move || {
+                    match i {
+                        // the later panic happens first
+                        1 => {
+                            thread::sleep(Duration::from_millis(30));
+                            panic!("job 1");
+                        }
+                        5 => panic!("job 5"),
+                        _ => thread::sleep(Duration::from_millis(20)),
+                    }
+                    finished.fetch_add(1, Ordering::SeqCst);
+                }) as ScopedJob<()>
+            })
+            .collect();
+        let payload = panic::catch_unwind(AssertUnwindSafe(|| pool.scoped_map(jobs))).unwrap_err();
+        assert_eq!(panic_message(payload), "job 1");
+        assert_eq!(finished.load(Ordering::SeqCst), 6);
+
+        // and the workers are all still there
+        let jobs: Vec<ScopedJob<usize>> = (0..4usize).map(|i| Box::new(move || i) as ScopedJob<usize>).collect();
+        assert_eq!(pool.scoped_map(jobs), vec![0, 1, 2, 3]);
+    }
+
+    // With one worker, a job that waited on jobs queued behind it would wait forever. On a
+    // worker thread they run inline instead.
+    #[test]
+    fn nested_scoped_map_runs_inline() {
+        let pool = ThreadPool::new(1);
+        let pool_ref = &pool;
+        let outer: Vec<ScopedJob<Vec<String>>> = vec![Box::new(move || {
+            let inner: Vec<ScopedJob<String>> = (0..3)
+                .map(|i| {
+                    Box::new(move || format!("{} {}", i, thread::current().name().unwrap())) as ScopedJob<String>
+                })
+                .collect();
+            pool_ref.scoped_map(inner)
+        })];
+        let names = pool.scoped_map(outer).remove(0);
+        assert_eq!(names, vec!["0 pool-worker-0", "1 pool-worker-0", "2 pool-worker-0"]);
+        assert!(!IS_WORKER.with(Cell::get));
+    }
+}
@@ -0,0 +1,199 @@
+// A tree where every node can reach both its children and its parent.
+// Parents own their children through Rc, and the children are in a RefCell so they can be
+// changed after the node has been shared. A child only holds a Weak reference back to its
+// parent, if it held an Rc the two would keep each other alive forever.
+// Dropping the root drops its Rc to each child, and since nothing else owns them (the Weak
+// parent pointers don't count), the whole tree is freed.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::rc::{Rc, Weak};
+
+pub struct Node<T> {
+    pub value: T,
+    parent: RefCell<Weak<Node<T>>>,
+    children: RefCell<Vec<Rc<Node<T>>>>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TreeError {
+    // the new child is the parent itself or one of its ancestors
+    WouldCreateCycle,
+}
+
+impl fmt::Display for TreeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TreeError::WouldCreateCycle => write!(f, "a node can't become a child of its own descendant"),
+        }
+    }
+}
+
+impl<T> Node<T> {
+    pub fn new(value: T) -> Rc<Node<T>> {
+        Rc::new(Node {
+            value,
+            parent: RefCell::new(Weak::new()),
+            children: RefCell::new(Vec::new()),
+        })
+    }
+
+    // None for the root, or once the parent has been dropped
+    pub fn parent(&self) -> Option<Rc<Node<T>>> {
+        self.parent.borrow().upgrade()
+    }
+
+    pub fn children(&self) -> Vec<Rc<Node<T>>> {
+        self.children.borrow().clone()
+    }
+
+    // adds child to the end of this node's children. A child that already has a parent is
+    // moved, so this is also how a node is re-parented
+    pub fn add_child(self: &Rc<Self>, child: Rc<Node<T>>) -> Result<(), TreeError> {
+        if self.ancestors().any(|a| Rc::ptr_eq(&a, &child)) {
+            return Err(TreeError::WouldCreateCycle);
+        }
+        child.detach();
+        *child.parent.borrow_mut() = Rc::downgrade(self);
+        self.children.borrow_mut().push(child);
+        Ok(())
+    }
+
+    // returns false if child wasn't one of this node's children
+    pub fn remove_child(&self, child: &Rc<Node<T>>) -> bool {
+        let mut children = self.children.borrow_mut();
+        match children.iter().position(|c| Rc::ptr_eq(c, child)) {
+            Some(i) => {
+                let removed = children.remove(i);
+                *removed.parent.borrow_mut() = Weak::new();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // takes this node (and everything below it) out of its parent, if it has one
+    pub fn detach(self: &Rc<Self>) {
+        if let Some(parent) = self.parent() {
+            parent.remove_child(self);
+        }
+    }
+
+    // this node, then its parent, then its parent's parent, up to the root
+    pub fn ancestors(self: &Rc<Self>) -> impl Iterator<Item = Rc<Node<T>>> {
+        std::iter::successors(Some(Rc::clone(self)), |node| node.parent())
+    }
+
+    // pre-order, a node comes before its children. Uses a stack rather than recursion,
+    // pushing children in reverse so the first child is visited first
+    pub fn depth_first(self: &Rc<Self>) -> Vec<Rc<Node<T>>> {
+        let mut order = Vec::new();
+        let mut stack = vec![Rc::clone(self)];
+        while let Some(node) = stack.pop() {
+            stack.extend(node.children.borrow().iter().rev().cloned());
+            order.push(node);
+        }
+        order
+    }
+
+    // level by level, using a queue
+    pub fn breadth_first(self: &Rc<Self>) -> Vec<Rc<Node<T>>> {
+        let mut order = Vec::new();
+        let mut queue = VecDeque::from(vec![Rc::clone(self)]);
+        while let Some(node) = queue.pop_front() {
+            queue.extend(node.children.borrow().iter().cloned());
+            order.push(node);
+        }
+        order
+    }
+}
+
+// printing the parent would print its children, which would print us again, so only the
+// value and the children are shown
+impl<T: fmt::Debug> fmt::Debug for Node<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Node")
+            .field("value", &self.value)
+            .field("children", &self.children.borrow())
+            .finish()
+    }
+}
+
+fn values<T: Copy>(nodes: &[Rc<Node<T>>]) -> Vec<T> {
+    nodes.iter().map(|n| n.value).collect()
+}
+
+//        1
+//      /   \
+//     2     3
+//    / \     \
+//   4   5     6
+fn example_tree() -> (Rc<Node<i32>>, Vec<Rc<Node<i32>>>) {
+    let root = Node::new(1);
+    let nodes: Vec<Rc<Node<i32>>> = (2..=6).map(Node::new).collect();
+    root.add_child(Rc::clone(&nodes[0])).unwrap();
+    root.add_child(Rc::clone(&nodes[1])).unwrap();
+    nodes[0].add_child(Rc::clone(&nodes[2])).unwrap();
+    nodes[0].add_child(Rc::clone(&nodes[3])).unwrap();
+    nodes[1].add_child(Rc::clone(&nodes[4])).unwrap();
+    (root, nodes)
+}
+
+pub fn this_main() {
+    let (root, nodes) = example_tree();
+
+    assert_eq!(values(&root.depth_first()), vec![1, 2, 4, 5, 3, 6]);
+    assert_eq!(values(&root.breadth_first()), vec![1, 2, 3, 4, 5, 6]);
+    assert_eq!(nodes[2].parent().map(|p| p.value), Some(2));
+    assert_eq!(values(&nodes[3].ancestors().collect::<Vec<_>>()), vec![5, 2, 1]);
+
+    // moving 5 under 3
+    nodes[1].add_child(Rc::clone(&nodes[3])).unwrap();
+    assert_eq!(values(&root.depth_first()), vec![1, 2, 4, 3, 6, 5]);
+    assert_eq!(nodes[3].parent().map(|p| p.value), Some(3));
+
+    // 1 can't go under 4, which is already under 1
+    assert_eq!(nodes[2].add_child(Rc::clone(&root)), Err(TreeError::WouldCreateCycle));
+    assert_eq!(root.add_child(Rc::clone(&root)), Err(TreeError::WouldCreateCycle));
+
+    assert!(root.remove_child(&nodes[0]));
+    assert!(!root.remove_child(&nodes[0]));
+    assert!(nodes[0].parent().is_none());
+    assert_eq!(values(&root.depth_first()), vec![1, 3, 6, 5]);
+    root.add_child(Rc::clone(&nodes[0])).unwrap();
+    println!("{:?}", root);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Keep weak references to every node, then drop all the strong ones we hold outside
+    // the tree. Only the root is left, so dropping it should free everything
+    #[test]
+    fn dropping_the_root_frees_every_node() {
+        let (root, nodes) = example_tree();
+        let weak: Vec<Weak<Node<i32>>> = root.depth_first().iter().map(Rc::downgrade).collect();
+        assert_eq!(weak.len(), 6);
+        drop(nodes);
+        assert!(weak.iter().all(|w| w.upgrade().is_some()));
+        drop(root);
+        assert!(weak.iter().all(|w| w.upgrade().is_none()));
+    }
+
+    // the weak parent pointers are updated when a node moves, so nothing is left holding
+    // the old shape of the tree alive
+    #[test]
+    fn re_parenting_leaves_nothing_behind() {
+        let (root, nodes) = example_tree();
+        nodes[1].add_child(Rc::clone(&nodes[3])).unwrap();
+        root.remove_child(&nodes[0]);
+        root.add_child(Rc::clone(&nodes[0])).unwrap();
+        let weak: Vec<Weak<Node<i32>>> = root.depth_first().iter().map(Rc::downgrade).collect();
+        assert_eq!(weak.len(), 6);
+        drop(nodes);
+        drop(root);
+        assert!(weak.iter().all(|w| w.upgrade().is_none()));
+    }
+}
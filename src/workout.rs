@@ -0,0 +1,421 @@
+// A workout planner built around generate_workout from the closures chapter.
+// Each user has a profile with the intensities they've trained at, and the planner turns
+// that into a schedule of several weeks, following some rules about when to rest.
+// Profiles are saved to a plain text file so progress carries over between runs.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// below this a day is pushups and situps, at or above it the day is a run
+const RUNNING_INTENSITY: u32 = 25;
+const FILE_HEADER: &str = "learn_rust workout progress v1";
+// ten years, plenty for a plan and small enough that printing it doesn't run forever
+pub const MAX_WEEKS: u32 = 520;
+
+// A small seeded random number generator (splitmix64), so the same seed always gives the
+// same plan. It stands in for the hard-coded random_number in the closures chapter.
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    // seeded from the clock, for when a repeatable plan isn't needed
+    pub fn from_time() -> Rng {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos());
+        Rng::new(nanos as u64)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // a number in 0..bound, bound must not be zero
+    pub fn below(&mut self, bound: u32) -> u32 {
+        (self.next_u64() % bound as u64) as u32
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Profile {
+    pub name: String,
+    // every intensity the user has logged, oldest first
+    pub intensity_history: Vec<u32>,
+}
+
+impl Profile {
+    pub fn new(name: &str, intensity: u32) -> Profile {
+        Profile { name: name.to_string(), intensity_history: vec![intensity] }
+    }
+
+    pub fn log(&mut self, intensity: u32) {
+        self.intensity_history.push(intensity);
+    }
+
+    // the average of the last three sessions, so one odd day doesn't swing the plan
+    pub fn current_intensity(&self) -> u32 {
+        let recent: Vec<u32> = self.intensity_history.iter().rev().take(3).copied().collect();
+        if recent.is_empty() {
+            return 0;
+        }
+        // summed as u64, three intensities near u32::MAX would overflow a u32. The average
+        // is never bigger than the largest of them, so it fits back in a u32
+        (recent.iter().map(|&i| i as u64).sum::<u64>() / recent.len() as u64) as u32
+    }
+}
+
+pub struct RestRules {
+    // never train more than this many days in a row
+    pub max_consecutive_days: u32,
+    // at least this many rest days in every week
+    pub rest_days_per_week: u32,
+    // how much the intensity goes up each week
+    pub weekly_increase: u32,
+}
+
+impl Default for RestRules {
+    fn default() -> RestRules {
+        RestRules { max_consecutive_days: 3, rest_days_per_week: 2, weekly_increase: 2 }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Day {
+    Strength { pushups: u32, situps: u32 },
+    Run { minutes: u32 },
+    Rest,
+}
+
+impl fmt::Display for Day {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Day::Strength { pushups, situps } => {
+                write!(f, "do {} pushups and {} situps", pushups, situps)
+            }
+            Day::Run { minutes } => write!(f, "run for {} minutes", minutes),
+            Day::Rest => write!(f, "take a break, remember to stay hydrated!"),
+        }
+    }
+}
+
+// the same decision generate_workout makes, for one training day
+fn training_day(intensity: u32, rng: &mut Rng) -> Day {
+    if intensity < RUNNING_INTENSITY {
+        Day::Strength { pushups: intensity, situps: intensity }
+    } else if rng.below(10) == 3 {
+        Day::Rest
+    } else {
+        Day::Run { minutes: intensity }
+    }
+}
+
+pub fn plan(profile: &Profile, weeks: u32, rules: &RestRules, rng: &mut Rng) -> Vec<Vec<Day>> {
+    let mut schedule = Vec::new();
+    let mut consecutive = 0;
+    for week in 0..weeks {
+        // saturating, an intensity near u32::MAX just stays at the top
+        let intensity = profile.current_intensity().saturating_add(week.saturating_mul(rules.weekly_increase));
+        let mut days = Vec::new();
+        let mut rests = 0;
+        for day in 0..7 {
+            let days_left = 7 - day;
+            let must_rest = consecutive >= rules.max_consecutive_days
+                || rules.rest_days_per_week.saturating_sub(rests) >= days_left;
+            let today = if must_rest { Day::Rest } else { training_day(intensity, rng) };
+            if today == Day::Rest {
+                rests += 1;
+                consecutive = 0;
+            } else {
+                consecutive += 1;
+            }
+            days.push(today);
+        }
+        schedule.push(days);
+    }
+    schedule
+}
+
+// profiles keyed by name, kept sorted so the file is stable between saves
+#[derive(Debug, Default)]
+pub struct Progress {
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+impl Progress {
+    // a missing file is just an empty set of profiles
+    pub fn load(path: &Path) -> io::Result<Progress> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Progress::default()),
+            Err(e) => return Err(e),
+        };
+        let invalid = |line: usize, why: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}:{}: {}", path.display(), line, why),
+            )
+        };
+
+        let mut lines = text.lines();
+        if lines.next() != Some(FILE_HEADER) {
+            return Err(invalid(1, "not a workout progress file"));
+        }
+        let mut progress = Progress::default();
+        // one profile per line, the name then a tab then comma separated intensities
+        for (i, line) in lines.enumerate().filter(|(_, l)| !l.is_empty()) {
+            let (name, history) = line.split_once('\t').ok_or_else(|| invalid(i + 2, "missing tab"))?;
+            let intensity_history = history
+                .split(',')
+                .filter(|s| !s.is_empty())
+                .map(|s| s.parse().map_err(|_| invalid(i + 2, "bad intensity")))
+                .collect::<io::Result<Vec<u32>>>()?;
+            let profile = Profile { name: name.to_string(), intensity_history };
+            progress.profiles.insert(profile.name.clone(), profile);
+        }
+        Ok(progress)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut text = String::from(FILE_HEADER);
+        text.push('\n');
+        for profile in self.profiles.values() {
+            let history: Vec<String> = profile.intensity_history.iter().map(|i| i.to_string()).collect();
+            text.push_str(&format!("{}\t{}\n", profile.name, history.join(",")));
+        }
+        fs::write(path, text)
+    }
+}
+
+const USAGE: &str = "usage: learn_rust workout [--file <path>] new <name> <intensity>
+       learn_rust workout [--file <path>] log <name> <intensity>
+       learn_rust workout [--file <path>] show <name>
+       learn_rust workout [--file <path>] plan <name> [--weeks <n>] [--seed <n>]";
+
+// the workout subcommand, returns an error message to print on bad input
+pub fn command(args: &[String]) -> Result<(), String> {
+    let mut path = String::from("workout_progress.txt");
+    let mut args: Vec<&str> = args.iter().map(String::as_str).collect();
+    if let ["--file", file, ..] = args.as_slice() {
+        path = file.to_string();
+        args.drain(..2);
+    }
+    let path = Path::new(&path);
+    let mut progress = Progress::load(path).map_err(|e| e.to_string())?;
+    let save = |progress: &Progress| progress.save(path).map_err(|e| e.to_string());
+
+    match args.as_slice() {
+        ["new", name, intensity] => {
+            if name.is_empty() || name.contains(['\t', '\n']) {
+                return Err(format!("'{}' can't be used as a name", name));
+            }
+            if progress.profiles.contains_key(*name) {
+                return Err(format!("{} already has a profile", name));
+            }
+            let profile = Profile::new(name, parse_number(intensity)?);
+            progress.profiles.insert(name.to_string(), profile);
+            save(&progress)
+        }
+        ["log", name, intensity] => {
+            let intensity = parse_number(intensity)?;
+            find_profile(&mut progress, name)?.log(intensity);
+            save(&progress)
+        }
+        ["show", name] => {
+            let profile = find_profile(&mut progress, name)?;
+            println!("{}: current intensity {}", profile.name, profile.current_intensity());
+            println!("history: {:?}", profile.intensity_history);
+            Ok(())
+        }
+        ["plan", name, options @ ..] => {
+            let mut weeks = 4;
+            let mut rng = Rng::from_time();
+            let mut options = options.iter();
+            while let Some(option) = options.next() {
+                let value = options.next().ok_or_else(|| format!("{} expects a value", option))?;
+                match *option {
+                    "--weeks" => {
+                        weeks = parse_number(value)?;
+                        if weeks > MAX_WEEKS {
+                            return Err(format!("--weeks can be at most {}", MAX_WEEKS));
+                        }
+                    }
+                    "--seed" => rng = Rng::new(parse_number(value)?),
+                    _ => return Err(USAGE.to_string()),
+                }
+            }
+            let profile = find_profile(&mut progress, name)?;
+            for (week, days) in plan(profile, weeks, &RestRules::default(), &mut rng).iter().enumerate() {
+                println!("week {}", week + 1);
+                for (day, today) in days.iter().enumerate() {
+                    println!("  day {}: {}", day + 1, today);
+                }
+            }
+            Ok(())
+        }
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+fn find_profile<'a>(progress: &'a mut Progress, name: &str) -> Result<&'a mut Profile, String> {
+    progress
+        .profiles
+        .get_mut(name)
+        .ok_or_else(|| format!("no profile for {}, create one with `workout new`", name))
+}
+
+fn parse_number<T: std::str::FromStr>(s: &str) -> Result<T, String> {
+    s.parse().map_err(|_| format!("'{}' is not a valid number", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn current_intensity_averages_the_last_three() {
+        let mut profile = Profile::new("sam", 10);
+        assert_eq!(profile.current_intensity(), 10);
+        for intensity in [1, 20, 30, 40] {
+            profile.log(intensity);
+        }
+        assert_eq!(profile.current_intensity(), 30);
+    }
+
+    #[test]
+    fn current_intensity_does_not_overflow() {
+        let mut profile = Profile::new("sam", u32::MAX);
+        profile.log(u32::MAX);
+        profile.log(u32::MAX - 3);
+        assert_eq!(profile.current_intensity(), u32::MAX - 1);
+    }
+
+    #[test]
+    fn plan_intensity_saturates() {
+        let profile = Profile::new("sam", u32::MAX - 5);
+        // + 2 a week, so the fourth week would be u32::MAX + 1
+        let schedule = plan(&profile, 4, &RestRules::default(), &mut Rng::new(1));
+        let runs: Vec<u32> = schedule[3]
+            .iter()
+            .filter_map(|day| match day {
+                Day::Run { minutes } => Some(*minutes),
+                _ => None,
+            })
+            .collect();
+        assert!(!runs.is_empty());
+        assert!(runs.iter().all(|&m| m == u32::MAX));
+    }
+
+    #[test]
+    fn weeks_past_the_limit_are_rejected() {
+        let args: Vec<String> = ["--file", "/nonexistent/workout.txt", "plan", "sam", "--weeks", "3000000000"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(command(&args), Err(format!("--weeks can be at most {}", MAX_WEEKS)));
+    }
+
+    fn is_rest(day: &Day) -> bool {
+        *day == Day::Rest
+    }
+
+    #[test]
+    fn plan_never_trains_too_many_days_in_a_row() {
+        let rules = RestRules { max_consecutive_days: 2, rest_days_per_week: 0, weekly_increase: 1 };
+        for seed in 0..50 {
+            let schedule = plan(&Profile::new("sam", 30), 6, &rules, &mut Rng::new(seed));
+            // the count carries across the end of a week
+            let mut in_a_row = 0;
+            for day in schedule.iter().flatten() {
+                in_a_row = if is_rest(day) { 0 } else { in_a_row + 1 };
+                assert!(in_a_row <= 2, "seed {} trained {} days in a row", seed, in_a_row);
+            }
+        }
+    }
+
+    #[test]
+    fn plan_rests_enough_every_week() {
+        let rules = RestRules { max_consecutive_days: 7, rest_days_per_week: 3, weekly_increase: 2 };
+        for seed in 0..50 {
+            let schedule = plan(&Profile::new("sam", 10), 4, &rules, &mut Rng::new(seed));
+            assert_eq!(schedule.len(), 4);
+            for week in &schedule {
+                assert_eq!(week.len(), 7);
+                assert!(week.iter().filter(|d| is_rest(d)).count() >= 3, "seed {}: {:?}", seed, week);
+            }
+        }
+    }
+
+    #[test]
+    fn plan_follows_generate_workout() {
+        let rules = RestRules { max_consecutive_days: 7, rest_days_per_week: 0, weekly_increase: 10 };
+        let schedule = plan(&Profile::new("sam", 20), 2, &rules, &mut Rng::new(7));
+        // below 25 every day is strength training, the second week is at 30 so it's runs
+        assert!(schedule[0].iter().all(|d| *d == Day::Strength { pushups: 20, situps: 20 }));
+        assert!(schedule[1].iter().all(|d| *d == Day::Run { minutes: 30 } || is_rest(d)));
+    }
+
+    #[test]
+    fn same_seed_same_plan() {
+        let profile = Profile::new("sam", 40);
+        let rules = RestRules::default();
+        let first = plan(&profile, 8, &rules, &mut Rng::new(42));
+        assert_eq!(first, plan(&profile, 8, &rules, &mut Rng::new(42)));
+        let others: Vec<_> = (0..10).map(|seed| plan(&profile, 8, &rules, &mut Rng::new(seed))).collect();
+        assert!(others.iter().any(|other| *other != first));
+    }
+
+    fn temp_file(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("learn_rust_workout_{}_{}.txt", std::process::id(), name))
+    }
+
+    #[test]
+    fn progress_round_trip() {
+        let path = temp_file("round_trip");
+        let mut progress = Progress::default();
+        let mut sam = Profile::new("sam", 10);
+        sam.log(12);
+        sam.log(15);
+        progress.profiles.insert(sam.name.clone(), sam);
+        progress.profiles.insert(String::from("alex smith"), Profile::new("alex smith", 30));
+        progress.save(&path).unwrap();
+        let loaded = Progress::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.profiles, progress.profiles);
+    }
+
+    #[test]
+    fn missing_progress_file_is_empty() {
+        let loaded = Progress::load(&temp_file("never_written")).unwrap();
+        assert!(loaded.profiles.is_empty());
+    }
+
+    #[test]
+    fn progress_errors_name_the_line() {
+        let cases = [
+            ("not a progress file\n", ":1: not a workout progress file"),
+            ("learn_rust workout progress v1\nsam\t1,2\nalex 3\n", ":3: missing tab"),
+            ("learn_rust workout progress v1\n\nsam\t1,x\n", ":3: bad intensity"),
+            ("learn_rust workout progress v1\nsam\t-1\n", ":2: bad intensity"),
+        ];
+        for (i, (text, error)) in cases.iter().enumerate() {
+            let path = temp_file(&format!("bad_{}", i));
+            fs::write(&path, text).unwrap();
+            let result = Progress::load(&path);
+            fs::remove_file(&path).unwrap();
+            let e = result.unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+            assert!(e.to_string().ends_with(error), "{} should end with {}", e, error);
+        }
+    }
+}